@@ -32,9 +32,16 @@ fn main() {
     let store_texts = env::var("CARGO_FEATURE_DIAGNOSTICS").is_ok();
 
     let mut store = Store::new();
+    // exceptions first, for the deprecated ids that stood for a license with one
+    store
+        .load_spdx_exceptions(Path::new("../datasets/spdx-exceptions"), store_texts)
+        .expect("Couldn't load SPDX exceptions. Have submodules been initialized?");
     store
         .load_spdx(Path::new("../datasets/spdx-json"), store_texts)
         .expect("Couldn't create a store from SPDX data. Have submodules been initialized?");
+    store
+        .load_variants(Path::new("../datasets/variants"), store_texts)
+        .expect("Couldn't load license variants");
     let mut cache = File::create(EMBEDDED_CACHE).unwrap();
     store.to_cache(&mut cache).unwrap();
 }
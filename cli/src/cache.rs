@@ -7,24 +7,117 @@ use failure::Error;
 use log::info;
 
 use super::commands::*;
-use askalono::Store;
+use askalono::{LshConfig, NgramSize, Store};
 
 pub fn cache(cache_filename: &Path, subcommand: CacheSubcommand) -> Result<(), Error> {
     match subcommand {
-        CacheSubcommand::LoadSpdx { dir, store_texts } => {
-            cache_load_spdx(cache_filename, &dir, store_texts)
-        }
+        CacheSubcommand::LoadSpdx {
+            dir,
+            store_texts,
+            exceptions,
+            templates,
+            variants,
+            ngram_size,
+            lsh,
+            lsh_bands,
+            lsh_rows,
+        } => cache_load_spdx(
+            cache_filename,
+            &dir,
+            store_texts,
+            SpdxExtras {
+                exceptions: exceptions.as_deref(),
+                templates: templates.as_deref(),
+                variants: variants.as_deref(),
+            },
+            ngram_size.unwrap_or_default(),
+            lsh_config(lsh, lsh_bands, lsh_rows),
+        ),
+        CacheSubcommand::LoadText {
+            dir,
+            store_texts,
+            ngram_size,
+            lsh,
+            lsh_bands,
+            lsh_rows,
+        } => cache_load_text(
+            cache_filename,
+            &dir,
+            store_texts,
+            ngram_size.unwrap_or_default(),
+            lsh_config(lsh, lsh_bands, lsh_rows),
+        ),
     }
 }
 
+fn lsh_config(lsh: bool, bands: Option<usize>, rows: Option<usize>) -> Option<LshConfig> {
+    if !lsh {
+        return None;
+    }
+    let default = LshConfig::default();
+    Some(LshConfig {
+        bands: bands.unwrap_or(default.bands),
+        rows: rows.unwrap_or(default.rows),
+        ..default
+    })
+}
+
+/// Optional directories to load alongside SPDX's license data.
+struct SpdxExtras<'a> {
+    exceptions: Option<&'a Path>,
+    templates: Option<&'a Path>,
+    variants: Option<&'a Path>,
+}
+
 fn cache_load_spdx(
     cache_filename: &Path,
     directory: &Path,
     store_texts: bool,
+    extras: SpdxExtras<'_>,
+    ngram_size: NgramSize,
+    lsh: Option<LshConfig>,
 ) -> Result<(), Error> {
+    let mut store = Store::with_ngram_size(ngram_size);
+    // exceptions first, for the deprecated ids that stood for a license with one
+    if let Some(exceptions) = extras.exceptions {
+        info!("Processing exceptions...");
+        store.load_spdx_exceptions(exceptions, store_texts)?;
+    }
     info!("Processing licenses...");
-    let mut store = Store::new();
     store.load_spdx(directory, store_texts)?;
+    if let Some(templates) = extras.templates {
+        info!("Processing templates...");
+        store.load_spdx_templates(templates)?;
+    }
+    if let Some(variants) = extras.variants {
+        info!("Processing variants...");
+        store.load_variants(variants, store_texts)?;
+    }
+    save_store(cache_filename, store, lsh)
+}
+
+fn cache_load_text(
+    cache_filename: &Path,
+    directory: &Path,
+    store_texts: bool,
+    ngram_size: NgramSize,
+    lsh: Option<LshConfig>,
+) -> Result<(), Error> {
+    info!("Processing licenses...");
+    let mut store = Store::with_ngram_size(ngram_size);
+    store.load_text_dir(directory, store_texts)?;
+    save_store(cache_filename, store, lsh)
+}
+
+fn save_store(
+    cache_filename: &Path,
+    mut store: Store,
+    lsh: Option<LshConfig>,
+) -> Result<(), Error> {
+    if let Some(config) = lsh {
+        info!("Building LSH index...");
+        store.enable_lsh(config)?;
+    }
     let cache_file = File::create(cache_filename)?;
     store.to_cache(&cache_file)?;
     Ok(())
@@ -3,6 +3,7 @@
 
 use std::path::PathBuf;
 
+use askalono::NgramSize;
 use clap::arg_enum;
 use structopt::StructOpt;
 
@@ -50,6 +51,19 @@ pub enum Subcommand {
         /// Read in filenames on stdin for batch identification
         #[structopt(long = "batch", short = "b")]
         batch: bool,
+
+        /// Also list the N best-scoring candidate licenses
+        #[structopt(long = "top")]
+        top: Option<usize>,
+
+        /// Report sentences that the identified license doesn't explain
+        #[structopt(long = "residuals")]
+        residuals: bool,
+
+        /// Check identified licenses against their SPDX templates, for an
+        /// exact match under the SPDX matching guidelines
+        #[structopt(long = "templates")]
+        templates: bool,
     },
 
     /// Crawl a directory identifying license files
@@ -66,6 +80,11 @@ pub enum Subcommand {
         /// Glob of files to check (defaults to license-like files)
         #[structopt(long = "glob")]
         glob: Option<String>,
+
+        /// Also check the leading comments of source files in languages
+        /// askalono knows the comment syntax of
+        #[structopt(long = "sources")]
+        sources: bool,
     },
 
     /// Cache management actions
@@ -88,5 +107,69 @@ pub enum CacheSubcommand {
         /// Store texts in cache along with match data
         #[structopt(long = "store")]
         store_texts: bool,
+
+        /// JSON "exceptions" directory, to load license exceptions from
+        #[structopt(long = "exceptions", parse(from_os_str))]
+        exceptions: Option<PathBuf>,
+
+        /// license-list-XML "src" directory, to read license templates from
+        /// in place of the JSON data's
+        #[structopt(long = "templates", parse(from_os_str))]
+        templates: Option<PathBuf>,
+
+        /// Directory of alternate forms, short forms, and headers to add to
+        /// the licenses, named as in load-text (e.g. "MIT.short.txt")
+        #[structopt(long = "variants", parse(from_os_str))]
+        variants: Option<PathBuf>,
+
+        /// Size of n-grams to match with: a number (default 2), or "multi"
+        /// to combine unigrams, bigrams, and trigrams
+        #[structopt(long = "ngrams")]
+        ngram_size: Option<NgramSize>,
+
+        /// Build a locality-sensitive hashing index, to speed up matching
+        /// against very large license sets
+        #[structopt(long = "lsh")]
+        lsh: bool,
+
+        /// Number of LSH bands; more find more distant matches, but slower
+        #[structopt(long = "lsh-bands", requires = "lsh")]
+        lsh_bands: Option<usize>,
+
+        /// Number of MinHash values in each LSH band
+        #[structopt(long = "lsh-rows", requires = "lsh")]
+        lsh_rows: Option<usize>,
+    },
+
+    /// Load a directory of plain text licenses: <ID>.txt files, with optional
+    /// <ID>.header.txt, <ID>.alternate.txt, <ID>.short.txt, and <ID>.aliases
+    /// files alongside
+    #[structopt(name = "load-text")]
+    LoadText {
+        /// Directory of license texts
+        #[structopt(name = "DIR", parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Store texts in cache along with match data
+        #[structopt(long = "store")]
+        store_texts: bool,
+
+        /// Size of n-grams to match with: a number (default 2), or "multi"
+        /// to combine unigrams, bigrams, and trigrams
+        #[structopt(long = "ngrams")]
+        ngram_size: Option<NgramSize>,
+
+        /// Build a locality-sensitive hashing index, to speed up matching
+        /// against very large license sets
+        #[structopt(long = "lsh")]
+        lsh: bool,
+
+        /// Number of LSH bands; more find more distant matches, but slower
+        #[structopt(long = "lsh-bands", requires = "lsh")]
+        lsh_bands: Option<usize>,
+
+        /// Number of MinHash values in each LSH band
+        #[structopt(long = "lsh-rows", requires = "lsh")]
+        lsh_rows: Option<usize>,
     },
 }
@@ -1,14 +1,23 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{fs::read_to_string, path::Path};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::read_to_string,
+    path::Path,
+};
 
 use failure::Error;
 use ignore::Error as IgnoreError;
 
-use askalono::TextData;
+use askalono::{CommentStyle, TextData};
 
-use super::{commands::*, formats::*, identify::identify_data, util::*};
+use super::{
+    commands::*,
+    formats::*,
+    identify::{identify_data, IdentifyOptions},
+    util::*,
+};
 
 pub fn crawl(
     cache_filename: &Path,
@@ -16,6 +25,7 @@ pub fn crawl(
     directory: &Path,
     follow_links: bool,
     glob: Option<&str>,
+    sources: bool,
 ) -> Result<(), Error> {
     use ignore::types::TypesBuilder;
     use ignore::WalkBuilder;
@@ -32,8 +42,13 @@ pub fn crawl(
     }
     let matcher = types_builder.build().unwrap();
 
+    // large trees tend to have many copies of the same license file; only
+    // identify each distinct text once. results carry spans and line ranges
+    // into the text, so this goes by the text as read rather than by its
+    // fingerprint, which copies reflowed or with other copyright lines share
+    let mut seen = RecentResults::default();
+
     WalkBuilder::new(directory)
-        .types(matcher)
         .follow_links(follow_links)
         .build()
         .filter_map(|entry| match entry {
@@ -49,15 +64,23 @@ pub fn crawl(
             }
         })
         .filter(|entry| !entry.metadata().unwrap().is_dir())
+        .filter(|entry| {
+            let path = entry.path();
+            matcher.matched(path, false).is_whitelist()
+                || (sources && CommentStyle::from_path(path).is_some())
+        })
         .for_each(|entry| {
             let path = entry.path();
             let path_lossy = path.to_string_lossy();
 
             match read_to_string(path) {
                 Ok(content) => {
-                    let data = TextData::new(&content);
-                    let idres = identify_data(&store, &data, false, false);
-                    let fileres = FileResult::from_identification_result(&path_lossy, &idres);
+                    let text = source_comments(path, &content).unwrap_or(content);
+                    let idres = seen.get_or_identify(text, |text| {
+                        let data = TextData::new(text);
+                        identify_data(&store, &data, &IdentifyOptions::default())
+                    });
+                    let fileres = FileResult::from_identification_result(&path_lossy, idres);
                     fileres.print_as(&output_format, true);
                 }
                 Err(err) => {
@@ -68,3 +91,66 @@ pub fn crawl(
 
     Ok(())
 }
+
+/// How many distinct texts `crawl` keeps the results of.
+const RECENT_TEXTS: usize = 64;
+
+/// The results of the texts identified most recently, so copies of a text
+/// met while crawling are only identified once, without keeping every text
+/// in a large tree around.
+#[derive(Default)]
+struct RecentResults {
+    /// Results by text, along with when each was last used.
+    results: HashMap<String, (Result<CLIIdentification, Error>, u64)>,
+    clock: u64,
+}
+
+impl RecentResults {
+    fn get_or_identify(
+        &mut self,
+        text: String,
+        identify: impl FnOnce(&str) -> Result<CLIIdentification, Error>,
+    ) -> &Result<CLIIdentification, Error> {
+        self.clock += 1;
+        if self.results.len() >= RECENT_TEXTS && !self.results.contains_key(&text) {
+            let oldest = self
+                .results
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(text, _)| text.clone());
+            if let Some(oldest) = oldest {
+                self.results.remove(&oldest);
+            }
+        }
+
+        let entry = match self.results.entry(text) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let result = identify(entry.key());
+                entry.insert((result, 0))
+            }
+        };
+        entry.1 = self.clock;
+        &entry.0
+    }
+}
+
+/// Pick the leading comments out of source files, where license headers
+/// live, leaving license files themselves alone.
+fn source_comments(path: &Path, content: &str) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    let license_file = [
+        "license",
+        "licence",
+        "copying",
+        "copyright",
+        "notice",
+        "unlicense",
+    ]
+    .iter()
+    .any(|name| stem.starts_with(name));
+    if license_file {
+        return None;
+    }
+    CommentStyle::detect(path, content).map(|style| style.extract(content))
+}
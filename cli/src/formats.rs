@@ -7,7 +7,10 @@ use failure::Error;
 use serde_derive::Serialize;
 
 use super::commands::*;
-use askalono::LicenseType;
+use askalono::{
+    Ambiguity, GnuVersion, IdentifiedLicense, LicenseMetadata, LicenseType, Residual, ResidualKind,
+    Span, Store,
+};
 
 #[derive(Serialize, Debug)]
 #[serde(untagged)]
@@ -24,9 +27,40 @@ pub enum FileResult<'a> {
 
 #[derive(Serialize, Debug)]
 pub struct CLIIdentification {
+    /// Fingerprint of the normalized text, as hex to survive JSON parsers
+    /// that read numbers as doubles
+    pub fingerprint: String,
     pub score: f32,
     pub license: Option<CLIIdentifiedLicense>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub residuals: Vec<Residual>,
     pub containing: Vec<CLIContainedResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<CLIRankedMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ambiguity: Option<CLIAmbiguity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gnu_version: Option<CLIGnuVersion>,
+    /// Whether the text exactly matches the license's SPDX template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_match: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<CLITag>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<CLIException>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CLIException {
+    pub name: String,
+    pub score: f32,
+    pub line_range: (usize, usize),
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
 }
 
 #[derive(Serialize, Debug)]
@@ -34,6 +68,11 @@ pub struct CLIIdentifiedLicense {
     pub name: String,
     pub kind: LicenseType,
     pub aliases: Vec<String>,
+    /// Aliases that are deprecated SPDX ids, kept apart from `aliases`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deprecated_aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<LicenseMetadata>,
 }
 
 #[derive(Serialize, Debug)]
@@ -41,6 +80,112 @@ pub struct CLIContainedResult {
     pub score: f32,
     pub license: CLIIdentifiedLicense,
     pub line_range: (usize, usize),
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub residuals: Vec<Residual>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gnu_version: Option<CLIGnuVersion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_match: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ambiguity: Option<CLIAmbiguity>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CLIGnuVersion {
+    pub id: String,
+    /// Whether a notice decided between `-only` and `-or-later`
+    pub from_notice: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CLIRankedMatch {
+    pub score: f32,
+    pub license: CLIIdentifiedLicense,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CLITag {
+    pub expression: String,
+    pub span: Span,
+    /// Why the expression couldn't be parsed, if it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// License ids of the tag that the store doesn't know
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unknown: Vec<String>,
+    /// License ids of the tag that are deprecated, with their replacements
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deprecated: Vec<CLIDeprecatedId>,
+    /// Licenses found in the text that the tag doesn't mention
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contradictions: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CLIDeprecatedId {
+    pub id: String,
+    pub successor: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CLIAmbiguity {
+    pub margin: f32,
+    pub candidates: Vec<CLIRankedMatch>,
+}
+
+impl CLIIdentifiedLicense {
+    pub fn new(store: &Store, name: &str, kind: LicenseType) -> CLIIdentifiedLicense {
+        let deprecated = store.deprecated_aliases(name);
+        CLIIdentifiedLicense {
+            name: name.to_owned(),
+            kind,
+            aliases: store
+                .aliases(name)
+                .unwrap()
+                .iter()
+                .filter(|alias| !deprecated.contains(&alias.as_str()))
+                .cloned()
+                .collect(),
+            deprecated_aliases: deprecated.into_iter().map(str::to_owned).collect(),
+            metadata: store.metadata(name).cloned(),
+        }
+    }
+
+    /// Describe a license identified by a scan, with the metadata the scan
+    /// settled on (that of `GPL-2.0-or-later` rather than `GPL-2.0-only`, say).
+    pub fn identified(store: &Store, license: &IdentifiedLicense) -> CLIIdentifiedLicense {
+        CLIIdentifiedLicense {
+            metadata: license.metadata.cloned(),
+            ..CLIIdentifiedLicense::new(store, license.name, license.kind)
+        }
+    }
+}
+
+impl CLIAmbiguity {
+    pub fn new(store: &Store, ambiguity: &Ambiguity) -> CLIAmbiguity {
+        CLIAmbiguity {
+            margin: ambiguity.margin,
+            candidates: ambiguity
+                .candidates
+                .iter()
+                .map(|c| CLIRankedMatch {
+                    score: c.score,
+                    license: CLIIdentifiedLicense::new(store, c.license.name, c.license.kind),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a> From<&GnuVersion<'a>> for CLIGnuVersion {
+    fn from(version: &GnuVersion<'a>) -> Self {
+        CLIGnuVersion {
+            id: version.id.to_owned(),
+            from_notice: version.from_notice,
+        }
+    }
 }
 
 impl<'a> FileResult<'a> {
@@ -114,22 +259,188 @@ impl fmt::Display for CLIIdentification {
             write!(f, "License: Unknown\nScore: {:.3}", self.score)?;
         }
 
-        if self.containing.is_empty() {
-            return Ok(());
+        if let Some(ref license) = self.license {
+            write_metadata(f, &license.metadata, "")?;
         }
-        write!(f, "\nContaining:")?;
+        write_gnu_version(f, &self.gnu_version, "")?;
+        write_template_match(f, self.template_match, "")?;
+        write_residuals(f, &self.residuals, "")?;
 
-        for res in &self.containing {
+        for tag in &self.tags {
             write!(
                 f,
-                "\n  License: {} ({})\n  Score: {:.3}\n  Lines: {} - {}",
-                res.license.name, res.license.kind, res.score, res.line_range.0, res.line_range.1
+                "\nSPDX tag: {} (line {})",
+                tag.expression,
+                tag.span.start.line + 1
             )?;
-            if !res.license.aliases.is_empty() {
-                write!(f, "\n  Aliases: {}", res.license.aliases.join(", "))?;
+            if let Some(ref error) = tag.error {
+                write!(f, "\n  Invalid expression: {}", error)?;
+            }
+            if !tag.unknown.is_empty() {
+                write!(f, "\n  Unknown ids: {}", tag.unknown.join(", "))?;
+            }
+            for deprecated in &tag.deprecated {
+                write!(
+                    f,
+                    "\n  Deprecated id: {} (now {})",
+                    deprecated.id, deprecated.successor
+                )?;
+            }
+            if !tag.contradictions.is_empty() {
+                write!(
+                    f,
+                    "\n  Contradicted by text: {}",
+                    tag.contradictions.join(", ")
+                )?;
+            }
+        }
+
+        write_ambiguity(f, &self.ambiguity, "")?;
+
+        if !self.containing.is_empty() {
+            write!(f, "\nContaining:")?;
+
+            for res in &self.containing {
+                write!(
+                    f,
+                    "\n  License: {} ({})\n  Score: {:.3}\n  Lines: {} - {}",
+                    res.license.name,
+                    res.license.kind,
+                    res.score,
+                    res.line_range.0,
+                    res.line_range.1
+                )?;
+                write_metadata(f, &res.license.metadata, "  ")?;
+                if !res.license.aliases.is_empty() {
+                    write!(f, "\n  Aliases: {}", res.license.aliases.join(", "))?;
+                }
+                if !res.license.deprecated_aliases.is_empty() {
+                    write!(
+                        f,
+                        "\n  Deprecated aliases: {}",
+                        res.license.deprecated_aliases.join(", ")
+                    )?;
+                }
+                write_gnu_version(f, &res.gnu_version, "  ")?;
+                write_template_match(f, res.template_match, "  ")?;
+                write_ambiguity(f, &res.ambiguity, "  ")?;
+                write_residuals(f, &res.residuals, "  ")?;
+            }
+        }
+
+        if !self.exceptions.is_empty() {
+            write!(f, "\nExceptions:")?;
+
+            for exception in &self.exceptions {
+                write!(
+                    f,
+                    "\n  {:.3} {} (lines {} - {})",
+                    exception.score, exception.name, exception.line_range.0, exception.line_range.1
+                )?;
+            }
+        }
+
+        // with a single license and nothing more, this would be redundant
+        if !self.containing.is_empty() || !self.exceptions.is_empty() {
+            if let Some(ref expression) = self.expression {
+                write!(f, "\nExpression: {}", expression)?;
+            }
+        }
+
+        if !self.candidates.is_empty() {
+            write!(f, "\nCandidates:")?;
+
+            for res in &self.candidates {
+                write!(
+                    f,
+                    "\n  {:.3} {} ({})",
+                    res.score, res.license.name, res.license.kind
+                )?;
             }
         }
 
         Ok(())
     }
 }
+
+fn write_metadata(
+    f: &mut fmt::Formatter<'_>,
+    metadata: &Option<LicenseMetadata>,
+    indent: &str,
+) -> fmt::Result {
+    match metadata {
+        Some(metadata) if metadata.osi_approved => {
+            write!(f, "\n{}Name: {} (OSI approved)", indent, metadata.full_name)
+        }
+        Some(metadata) => write!(f, "\n{}Name: {}", indent, metadata.full_name),
+        None => Ok(()),
+    }
+}
+
+fn write_gnu_version(
+    f: &mut fmt::Formatter<'_>,
+    version: &Option<CLIGnuVersion>,
+    indent: &str,
+) -> fmt::Result {
+    match version {
+        Some(version) if version.from_notice => write!(f, "\n{}Version: {}", indent, version.id),
+        Some(version) => write!(f, "\n{}Version: {} (no notice found)", indent, version.id),
+        None => Ok(()),
+    }
+}
+
+fn write_template_match(
+    f: &mut fmt::Formatter<'_>,
+    template_match: Option<bool>,
+    indent: &str,
+) -> fmt::Result {
+    match template_match {
+        Some(true) => write!(f, "\n{}Exact match: yes (SPDX template)", indent),
+        Some(false) => write!(f, "\n{}Exact match: no (SPDX template)", indent),
+        None => Ok(()),
+    }
+}
+
+fn write_ambiguity(
+    f: &mut fmt::Formatter<'_>,
+    ambiguity: &Option<CLIAmbiguity>,
+    indent: &str,
+) -> fmt::Result {
+    let ambiguity = match ambiguity {
+        Some(ambiguity) => ambiguity,
+        None => return Ok(()),
+    };
+
+    write!(f, "\n{}Ambiguous (margin {:.3}):", indent, ambiguity.margin)?;
+    for res in &ambiguity.candidates {
+        write!(
+            f,
+            "\n{}  {:.3} {} ({})",
+            indent, res.score, res.license.name, res.license.kind
+        )?;
+    }
+    Ok(())
+}
+
+fn write_residuals(
+    f: &mut fmt::Formatter<'_>,
+    residuals: &[Residual],
+    indent: &str,
+) -> fmt::Result {
+    if residuals.is_empty() {
+        return Ok(());
+    }
+
+    write!(f, "\n{}Residuals:", indent)?;
+    for residual in residuals {
+        let marker = match residual.kind {
+            ResidualKind::Added => "+",
+            ResidualKind::Removed => "-",
+            ResidualKind::Altered => "~",
+        };
+        // keep multi-line sentences on one line of output
+        let text: Vec<&str> = residual.text.split_whitespace().collect();
+        write!(f, "\n{}  {} {}", indent, marker, text.join(" "))?;
+    }
+    Ok(())
+}
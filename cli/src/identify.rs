@@ -16,13 +16,22 @@ use askalono::{ScanMode, ScanStrategy, Store, TextData};
 
 const MIN_SCORE: f32 = 0.8;
 
+/// What to look for when identifying a text, beyond its license.
+#[derive(Default)]
+pub struct IdentifyOptions {
+    pub optimize: bool,
+    pub want_diff: bool,
+    pub top: Option<usize>,
+    pub residuals: bool,
+    pub templates: bool,
+}
+
 pub fn identify(
     cache_filename: &Path,
     output_format: &OutputFormat,
     filename: Option<PathBuf>,
-    optimize: bool,
-    want_diff: bool,
     batch: bool,
+    options: &IdentifyOptions,
 ) -> Result<(), Error> {
     // load the cache from disk or embedded data
     let cache_inst = Instant::now();
@@ -44,7 +53,7 @@ pub fn identify(
             read_to_string(&filename)?
         };
 
-        let idres = identify_data(&store, &content.into(), optimize, want_diff);
+        let idres = identify_data(&store, &content.into(), options);
         let file_lossy = filename.to_string_lossy();
         let fileres = FileResult::from_identification_result(&file_lossy, &idres);
         fileres.print_as(&output_format, false);
@@ -74,7 +83,7 @@ pub fn identify(
             }
         };
 
-        let idres = identify_data(&store, &content.into(), optimize, want_diff);
+        let idres = identify_data(&store, &content.into(), options);
         let fileres = FileResult::from_identification_result(&buf, &idres);
         fileres.print_as(&output_format, false);
     }
@@ -85,15 +94,16 @@ pub fn identify(
 pub fn identify_data(
     store: &Store,
     text_data: &TextData,
-    optimize: bool,
-    want_diff: bool,
+    options: &IdentifyOptions,
 ) -> Result<CLIIdentification, Error> {
     let inst = Instant::now();
 
     let strategy = ScanStrategy::new(store)
         .mode(ScanMode::Elimination)
         .confidence_threshold(MIN_SCORE)
-        .optimize(optimize)
+        .optimize(options.optimize)
+        .residuals(options.residuals)
+        .match_templates(options.templates)
         .max_passes(1);
     let result = strategy.scan(text_data)?;
 
@@ -105,32 +115,92 @@ pub fn identify_data(
 
     // start building an output structure to print
     let mut output = CLIIdentification {
+        fingerprint: format!("{:016x}", text_data.fingerprint()),
         score: result.score,
         license: None,
+        span: result.span,
+        residuals: result.residuals.clone(),
         containing: result
             .containing
             .iter()
             .map(|cr| CLIContainedResult {
                 score: cr.score,
-                license: CLIIdentifiedLicense {
-                    aliases: store.aliases(&cr.license.name).unwrap().clone(),
-                    name: cr.license.name.to_owned(),
-                    kind: cr.license.kind,
-                },
+                license: CLIIdentifiedLicense::identified(store, &cr.license),
                 line_range: cr.line_range,
+                span: cr.span,
+                residuals: cr.residuals.clone(),
+                gnu_version: cr.gnu_version.as_ref().map(CLIGnuVersion::from),
+                template_match: cr.template_match,
+                ambiguity: cr.ambiguity.as_ref().map(|a| CLIAmbiguity::new(store, a)),
+            })
+            .collect(),
+        candidates: Vec::new(),
+        ambiguity: result
+            .ambiguity
+            .as_ref()
+            .map(|a| CLIAmbiguity::new(store, a)),
+        tags: result
+            .tags
+            .iter()
+            .map(|t| CLITag {
+                expression: t.tag.expression.clone(),
+                span: t.tag.span,
+                error: t.tag.parsed.as_ref().err().cloned(),
+                unknown: t
+                    .tag
+                    .licenses
+                    .iter()
+                    .zip(&t.resolved)
+                    .filter(|(_, resolved)| resolved.is_none())
+                    .map(|(id, _)| id.clone())
+                    .collect(),
+                deprecated: t
+                    .tag
+                    .licenses
+                    .iter()
+                    .zip(&t.successors)
+                    .filter_map(|(id, successor)| {
+                        successor.map(|successor| CLIDeprecatedId {
+                            id: id.clone(),
+                            successor: successor.to_owned(),
+                        })
+                    })
+                    .collect(),
+                contradictions: t.contradictions.iter().map(|&c| c.to_owned()).collect(),
+            })
+            .collect(),
+        exceptions: result
+            .exceptions
+            .iter()
+            .map(|e| CLIException {
+                name: e.name.to_owned(),
+                score: e.score,
+                line_range: e.line_range,
+                span: e.span,
             })
             .collect(),
+        gnu_version: result.gnu_version.as_ref().map(CLIGnuVersion::from),
+        template_match: result.template_match,
+        expression: result.expression.as_ref().map(|e| e.to_string()),
     };
 
+    // list runners-up if asked; these are shown regardless of the threshold
+    if let Some(n) = options.top {
+        output.candidates = store
+            .analyze_top(text_data, n)
+            .iter()
+            .map(|m| CLIRankedMatch {
+                score: m.score,
+                license: CLIIdentifiedLicense::new(store, m.name, m.license_type),
+            })
+            .collect();
+    }
+
     // include the overall license if present
     if let Some(license) = result.license {
-        output.license = Some(CLIIdentifiedLicense {
-            aliases: store.aliases(&license.name).unwrap().clone(),
-            name: license.name.to_owned(),
-            kind: license.kind,
-        });
+        output.license = Some(CLIIdentifiedLicense::identified(store, &license));
 
-        if want_diff {
+        if options.want_diff {
             diff_result(&text_data, &license.data);
         }
 
@@ -139,12 +209,17 @@ pub fn identify_data(
 
     // not a good enough match overall, but maybe inside
     if !output.containing.is_empty() {
-        if want_diff {
+        if options.want_diff {
             diff_result(&text_data, &result.containing[0].license.data);
         }
         return Ok(output);
     }
 
+    // nothing confident, but the candidates were explicitly requested
+    if !output.candidates.is_empty() {
+        return Ok(output);
+    }
+
     Err(err_msg(
         "Confidence threshold not high enough for any known license",
     ))
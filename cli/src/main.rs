@@ -34,17 +34,34 @@ fn main() {
             optimize,
             diff,
             batch,
-        } => identify::identify(&cache_file, &output_format, filename, optimize, diff, batch),
+            top,
+            residuals,
+            templates,
+        } => identify::identify(
+            &cache_file,
+            &output_format,
+            filename,
+            batch,
+            &identify::IdentifyOptions {
+                optimize,
+                want_diff: diff,
+                top,
+                residuals,
+                templates,
+            },
+        ),
         Subcommand::Crawl {
             directory,
             follow_links,
             glob,
+            sources,
         } => crawl::crawl(
             &cache_file,
             &output_format,
             &directory,
             follow_links,
             glob.as_deref(),
+            sources,
         ),
         Subcommand::Cache { subcommand } => cache::cache(&cache_file, subcommand),
     };
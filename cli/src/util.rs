@@ -41,5 +41,21 @@ pub fn diff_result(license: &TextData, other: &TextData) {
     }
 
     #[cfg(not(feature = "diagnostics"))]
-    println!("askalono wasn't compiled with diagnostics enabled. diff not available.");
+    {
+        use askalono::RegionKind;
+
+        if license.text_processed().is_none() {
+            println!("license text isn't in the cache (see `cache load-spdx --store`); diff not available.");
+            return;
+        }
+
+        for region in other.explain(license).regions {
+            let marker = match region.kind {
+                RegionKind::Matched => " ",
+                RegionKind::Missing => "-",
+                RegionKind::Extra => "+",
+            };
+            println!("{} {}", marker, region.text);
+        }
+    }
 }
@@ -80,6 +80,21 @@ impl AskalonoStore {
         }
     }
 
+    #[wasm_bindgen(js_name = identifyTop)]
+    pub fn identify_top(&self, text: &str, n: usize) -> Array {
+        self.store
+            .analyze_top(&text.into(), n)
+            .into_iter()
+            .map(|matched| {
+                JsValue::from(MatchResult {
+                    name: matched.name.to_owned(),
+                    score: matched.score,
+                    license_text: matched.data.lines().join("\n"),
+                })
+            })
+            .collect()
+    }
+
     pub fn licenses(&self) -> Array {
         self.store.licenses().map(JsValue::from).collect()
     }
@@ -0,0 +1,363 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::Path;
+
+/// The comment syntax of a family of programming languages, used to pick the
+/// leading comments (where license headers live) out of source files.
+///
+/// ```
+/// use askalono::{CommentStyle, TextData};
+///
+/// let source = "// Copyright 2020 Someone\n//\n// My First License\n\nfn main() {}\n";
+/// let style = CommentStyle::from_path("main.rs".as_ref()).unwrap();
+/// let comments = style.extract(source);
+///
+/// // the code and comment markers are blanked out...
+/// assert_eq!("My First License", comments[32..48].trim());
+/// assert_eq!("", comments[48..].trim());
+///
+/// // ...so spans of a TextData built from the comments point into the source
+/// let data = TextData::from(comments.as_str());
+/// let span = data.span((2, 3)).unwrap();
+/// assert_eq!("My First License", &source[span.bytes()]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommentStyle {
+    /// `//` and `/* */`, as in C, C++, Java, JavaScript, Go, Rust, and many
+    /// others.
+    CLike,
+    /// `#`, as in shell scripts, Ruby, Perl, R, YAML, and TOML.
+    Hash,
+    /// `#`, and a module docstring in triple quotes.
+    Python,
+    /// `--` and `/* */`.
+    Sql,
+    /// `--` and `--[[ ]]`.
+    Lua,
+    /// `--` and `{- -}`.
+    Haskell,
+    /// `;`, as in Lisps.
+    Lisp,
+    /// `%`, as in Erlang and TeX.
+    Percent,
+    /// `<!-- -->`, as in HTML and XML.
+    Markup,
+}
+
+struct Syntax {
+    line: &'static [&'static str],
+    block: &'static [(&'static str, &'static str)],
+    /// Characters that decorate comments, and are blanked out along with
+    /// the markers when they follow them or start a line of a block.
+    decoration: &'static str,
+}
+
+impl CommentStyle {
+    /// Guess the comment style of a file from its name or extension.
+    pub fn from_path(path: &Path) -> Option<CommentStyle> {
+        use self::CommentStyle::*;
+
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        let extension = match name.rfind('.') {
+            Some(dot) => &name[dot + 1..],
+            None => name.as_str(),
+        };
+        Some(match extension {
+            "c" | "h" | "cc" | "cpp" | "cxx" | "c++" | "hh" | "hpp" | "hxx" | "ino" | "m"
+            | "mm" | "java" | "js" | "mjs" | "cjs" | "jsx" | "ts" | "tsx" | "go" | "rs" | "cs"
+            | "swift" | "kt" | "kts" | "scala" | "sc" | "dart" | "groovy" | "gradle" | "php"
+            | "css" | "scss" | "less" | "proto" | "zig" | "d" | "v" | "sv" => CLike,
+            "sh" | "bash" | "zsh" | "ksh" | "fish" | "rb" | "pl" | "pm" | "r" | "yaml" | "yml"
+            | "toml" | "cmake" | "mk" | "tf" | "bzl" | "bazel" | "makefile" | "dockerfile"
+            | "gemfile" | "rakefile" => Hash,
+            "py" | "pyw" | "pyi" | "pyx" => Python,
+            "sql" => Sql,
+            "lua" => Lua,
+            "hs" | "lhs" | "elm" => Haskell,
+            "el" | "lisp" | "lsp" | "cl" | "clj" | "cljs" | "cljc" | "edn" | "scm" | "ss"
+            | "rkt" => Lisp,
+            "erl" | "hrl" | "tex" | "sty" | "cls" => Percent,
+            "html" | "htm" | "xhtml" | "xml" | "svg" | "xsl" | "xslt" | "vue" => Markup,
+            _ => return None,
+        })
+    }
+
+    /// Guess the comment style of a script from its `#!` line.
+    pub fn from_shebang(text: &str) -> Option<CommentStyle> {
+        use self::CommentStyle::*;
+
+        let line = text.lines().next()?.strip_prefix("#!")?;
+        let mut words = line.split_whitespace();
+        let mut interpreter = words.next()?.rsplit('/').next()?;
+        if interpreter == "env" {
+            interpreter = words.find(|word| !word.starts_with('-'))?;
+        }
+        let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        Some(match interpreter {
+            "python" | "pypy" => Python,
+            "node" | "nodejs" | "deno" | "bun" => CLike,
+            "sh" | "bash" | "zsh" | "ksh" | "dash" | "fish" | "ruby" | "perl" | "Rscript" => Hash,
+            "lua" | "luajit" => Lua,
+            "runhaskell" | "runghc" => Haskell,
+            _ => return None,
+        })
+    }
+
+    /// Guess the comment style of a file from its name, or failing that, its
+    /// `#!` line.
+    pub fn detect(path: &Path, text: &str) -> Option<CommentStyle> {
+        CommentStyle::from_path(path).or_else(|| CommentStyle::from_shebang(text))
+    }
+
+    fn syntax(self) -> Syntax {
+        use self::CommentStyle::*;
+
+        match self {
+            CLike => Syntax {
+                line: &["//"],
+                block: &[("/*", "*/")],
+                decoration: "/*!",
+            },
+            Hash => Syntax {
+                line: &["#"],
+                block: &[],
+                decoration: "#",
+            },
+            Python => Syntax {
+                line: &["#"],
+                block: &[("\"\"\"", "\"\"\""), ("'''", "'''")],
+                decoration: "#",
+            },
+            Sql => Syntax {
+                line: &["--"],
+                block: &[("/*", "*/")],
+                decoration: "-*",
+            },
+            Lua => Syntax {
+                line: &["--"],
+                block: &[("--[[", "]]")],
+                decoration: "-",
+            },
+            Haskell => Syntax {
+                line: &["--"],
+                block: &[("{-", "-}")],
+                decoration: "-|",
+            },
+            Lisp => Syntax {
+                line: &[";"],
+                block: &[],
+                decoration: ";",
+            },
+            Percent => Syntax {
+                line: &["%"],
+                block: &[],
+                decoration: "%",
+            },
+            Markup => Syntax {
+                line: &[],
+                block: &[("<!--", "-->")],
+                decoration: "",
+            },
+        }
+    }
+
+    /// Pick out the leading comments of a source file.
+    ///
+    /// These are the comments before the first line of code, skipping over
+    /// any `#!` or `<?...` line at the very start. Rust's `#![...]`
+    /// attributes are code, not a `#!` line. Everything else -- code,
+    /// comment markers, and decoration such as the `*` starting each line of
+    /// a block comment -- is replaced with whitespace. The result has the
+    /// same lines, byte offsets, and columns as the original, so spans of a
+    /// `TextData` built from it can be used on the original directly.
+    pub fn extract(self, text: &str) -> String {
+        let syntax = self.syntax();
+        let mut out = String::with_capacity(text.len());
+
+        let mut lines = text.split('\n').enumerate().peekable();
+        // the closing marker of the block comment we're in, if any
+        let mut block: Option<&str> = None;
+        while let Some((number, line)) = lines.next() {
+            let mut rest = line;
+            let shebang = line.starts_with("#!") && !line.starts_with("#![");
+            if block.is_none() && number == 0 && (shebang || line.starts_with("<?")) {
+                blank(&mut out, line);
+                rest = "";
+            }
+
+            while !rest.is_empty() {
+                if let Some(close) = block {
+                    // inside a block comment: blank decoration at the start
+                    // of the line, then keep everything up to the end of it
+                    if rest.len() == line.len() {
+                        // ...but leave the closing marker for below
+                        let limit = rest.find(close).unwrap_or(rest.len());
+                        let decorated = rest[..limit].trim_start_matches(|c: char| {
+                            c.is_whitespace() || (syntax.decoration.contains(c) && c != '/')
+                        });
+                        let cut = limit - decorated.len();
+                        blank(&mut out, &rest[..cut]);
+                        rest = &rest[cut..];
+                    }
+                    match rest.find(close) {
+                        Some(end) => {
+                            let (content, after) = rest.split_at(end);
+                            // decoration leading up to the closer, as in `***/`
+                            let trimmed = content
+                                .trim_end_matches(|c| syntax.decoration.contains(c) && c != '/');
+                            out.push_str(trimmed);
+                            blank(&mut out, &content[trimmed.len()..]);
+                            blank(&mut out, close);
+                            rest = &after[close.len()..];
+                            block = None;
+                        }
+                        None => {
+                            out.push_str(rest);
+                            rest = "";
+                        }
+                    }
+                    continue;
+                }
+
+                let content = rest.trim_start();
+                blank(&mut out, &rest[..rest.len() - content.len()]);
+                rest = content;
+                if rest.is_empty() {
+                    break;
+                }
+
+                if let Some(&(open, close)) =
+                    syntax.block.iter().find(|(open, _)| rest.starts_with(open))
+                {
+                    blank(&mut out, open);
+                    rest = &rest[open.len()..];
+                    let content = rest.trim_start_matches(|c| syntax.decoration.contains(c));
+                    blank(&mut out, &rest[..rest.len() - content.len()]);
+                    rest = content;
+                    block = Some(close);
+                } else if let Some(marker) =
+                    syntax.line.iter().find(|marker| rest.starts_with(*marker))
+                {
+                    let content =
+                        rest[marker.len()..].trim_start_matches(|c| syntax.decoration.contains(c));
+                    blank(&mut out, &rest[..rest.len() - content.len()]);
+                    out.push_str(content);
+                    rest = "";
+                } else {
+                    // the first line of code: blank it and everything after
+                    blank(&mut out, rest);
+                    for (_, line) in lines.by_ref() {
+                        out.push('\n');
+                        blank(&mut out, line);
+                    }
+                    return out;
+                }
+            }
+
+            if lines.peek().is_some() {
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// Add whitespace to take the place of some text, keeping its byte offsets
+/// and character count intact where possible.
+fn blank(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c.len_utf8() {
+            _ if c == '\r' => out.push('\r'),
+            1 => out.push(' '),
+            2 => out.push('\u{a0}'),
+            3 => out.push('\u{2002}'),
+            // there's no four-byte whitespace; symbols like emoji are dropped
+            // by preprocessing anyway, so leave those be
+            _ if !c.is_alphanumeric() => out.push(c),
+            _ => out.push_str("\u{a0}\u{a0}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kept(style: CommentStyle, text: &str) -> Vec<String> {
+        let extracted = style.extract(text);
+        assert_eq!(text.len(), extracted.len());
+        assert_eq!(text.lines().count(), extracted.lines().count());
+        extracted
+            .split('\n')
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn block_comments_lose_decoration() {
+        let text = "/*\n * Copyright 2020 Someone\n *\n * Licensed under the Example License,\n * Version 2.0 (the \"License\").\n */\npackage example;\n/* not leading */\n";
+        assert_eq!(
+            vec![
+                "Copyright 2020 Someone",
+                "Licensed under the Example License,",
+                "Version 2.0 (the \"License\")."
+            ],
+            kept(CommentStyle::CLike, text)
+        );
+
+        let text = "/*********\n** Banner\n*********/ int x;\n// after\n";
+        assert_eq!(vec!["Banner"], kept(CommentStyle::CLike, text));
+    }
+
+    #[test]
+    fn line_comments_and_preambles() {
+        let text = "#!/usr/bin/env python3\n# -*- coding: utf-8 -*-\n## Licensed under\n##   the Example License\n\n\"\"\"Docstring, too.\"\"\"\nimport os  # not this\n";
+        let style = CommentStyle::detect("script".as_ref(), text).unwrap();
+        assert_eq!(CommentStyle::Python, style);
+        assert_eq!(
+            vec![
+                "-*- coding: utf-8 -*-",
+                "Licensed under",
+                "the Example License",
+                "Docstring, too."
+            ],
+            kept(style, text)
+        );
+
+        let text = "//! Crate docs\n/// más «docs» ✓\nfn main() {}\n";
+        assert_eq!(
+            vec!["Crate docs", "más «docs» ✓"],
+            kept(CommentStyle::CLike, text)
+        );
+        let text = "#![deny(warnings)]\n// not leading\nfn main() {}\n";
+        assert!(kept(CommentStyle::CLike, text).is_empty());
+        assert_eq!(
+            vec!["license"],
+            kept(
+                CommentStyle::Markup,
+                "<?xml version=\"1.0\"?>\n<!-- license -->\n<a/>"
+            )
+        );
+    }
+
+    #[test]
+    fn styles_are_detected() {
+        let style = |name: &str| CommentStyle::from_path(name.as_ref());
+        assert_eq!(Some(CommentStyle::CLike), style("src/lib.rs"));
+        assert_eq!(Some(CommentStyle::CLike), style("Main.JAVA"));
+        assert_eq!(Some(CommentStyle::Hash), style("Makefile"));
+        assert_eq!(None, style("LICENSE"));
+
+        assert_eq!(
+            Some(CommentStyle::CLike),
+            CommentStyle::from_shebang("#!/usr/bin/env -S node --harmony\n")
+        );
+        assert_eq!(
+            Some(CommentStyle::Hash),
+            CommentStyle::from_shebang("#!/bin/bash\n")
+        );
+        assert_eq!(None, CommentStyle::from_shebang("no shebang\n"));
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    license::TextData,
+    ngram::{hash_str, NgramSize},
+    span::{Position, Span},
+};
+
+/// Diffs larger than this many word pairs aren't aligned word-by-word; the
+/// differing middle of the texts is reported as one missing and one extra
+/// region instead. This caps memory use at a few megabytes.
+const MAX_DIFF_CELLS: usize = 32 * 1024 * 1024;
+
+/// How a region of text figures into a match.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionKind {
+    /// The text appears in both the sample and the license.
+    Matched,
+    /// The text is in the license, but not in the sample.
+    Missing,
+    /// The text is in the sample, but not in the license.
+    Extra,
+}
+
+/// A run of words that a sample and a license either agree or disagree on.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Region {
+    /// Whether the words were matched, missing, or extra.
+    pub kind: RegionKind,
+    /// The words of the region, as they look after preprocessing.
+    pub text: String,
+    /// Where the region is in the sample's original text. For missing
+    /// regions, this is an empty span where the text would have been.
+    pub sample_span: Option<Span>,
+    /// Where the region is in the license's original text. For extra
+    /// regions, this is an empty span where the text would have been.
+    pub license_span: Option<Span>,
+    /// The n-grams that the region contributed to the score, or took away
+    /// from it: those entirely inside a matched region, or those overlapping
+    /// a missing or extra one.
+    pub ngrams: Vec<String>,
+}
+
+/// A word-level breakdown of how a sample matched a license.
+///
+/// Produced by `TextData::explain`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Explanation {
+    /// The score of the match, as given by `TextData::match_score`.
+    pub score: f32,
+    /// The regions of the texts, in order. Where the texts differ, missing
+    /// text comes before extra text.
+    pub regions: Vec<Region>,
+}
+
+impl Explanation {
+    /// Iterate over the regions of one kind.
+    pub fn regions_of(&self, kind: RegionKind) -> impl Iterator<Item = &Region> {
+        self.regions
+            .iter()
+            .filter(move |region| region.kind == kind)
+    }
+}
+
+/// The words of a text, and where each came from in the original.
+pub(crate) struct Words<'a> {
+    pub words: Vec<&'a str>,
+    pub spans: Vec<Option<Span>>,
+    pub original: Option<&'a str>,
+}
+
+impl<'a> Words<'a> {
+    /// Find a span covering some of the words, or an empty span where they
+    /// would be if the range is empty.
+    pub fn span(&self, range: Range<usize>) -> Option<Span> {
+        if range.start == range.end {
+            return self
+                .point(range.start)
+                .map(|start| Span { start, end: start });
+        }
+
+        let mut located = self.spans[range].iter().flatten();
+        let first = located.next()?;
+        let last = located.next_back().unwrap_or(first);
+        Some(Span {
+            start: first.start,
+            end: last.end,
+        })
+    }
+
+    /// Find the position just before a word: after the nearest word before it
+    /// that could be located, or failing that, at the nearest one after it.
+    fn point(&self, index: usize) -> Option<Position> {
+        self.spans[..index]
+            .iter()
+            .rev()
+            .flatten()
+            .map(|span| span.end)
+            .next()
+            .or_else(|| {
+                self.spans[index..]
+                    .iter()
+                    .flatten()
+                    .map(|span| span.start)
+                    .next()
+            })
+    }
+
+    /// List the n-grams of the given sizes around a range of words. If
+    /// `contained` is set, only those entirely inside the range are listed;
+    /// otherwise, any that overlap it.
+    fn ngrams(&self, range: Range<usize>, ngram_size: NgramSize, contained: bool) -> Vec<String> {
+        let mut ngrams = Vec::new();
+        for n in ngram_size.sizes().filter(|&n| n > 0) {
+            let (from, to) = match contained {
+                true => (range.start, range.end),
+                false if range.start == range.end => continue,
+                false => (
+                    range.start.saturating_sub(n - 1),
+                    (range.end + n - 1).min(self.words.len()),
+                ),
+            };
+            if to - from >= n {
+                ngrams.extend(self.words[from..to].windows(n).map(|w| w.join(" ")));
+            }
+        }
+        ngrams
+    }
+}
+
+pub(crate) fn explain(sample: &TextData, license: &TextData) -> Explanation {
+    let ngram_size = license.ngram_size();
+    let sample_words = sample.words();
+    let license_words = license.words();
+
+    let hash =
+        |words: &Words<'_>| -> Vec<u64> { words.words.iter().map(|w| hash_str(w)).collect() };
+    let ops = diff(&hash(&sample_words), &hash(&license_words));
+
+    let mut regions = Vec::new();
+    let (mut s, mut l) = (0, 0);
+    let mut ops = ops.into_iter().peekable();
+    while let Some(op) = ops.next() {
+        if op == Op::Equal {
+            let mut len = 1;
+            while ops.peek() == Some(&Op::Equal) {
+                ops.next();
+                len += 1;
+            }
+            regions.push(Region {
+                kind: RegionKind::Matched,
+                text: license_words.words[l..l + len].join(" "),
+                sample_span: sample_words.span(s..s + len),
+                license_span: license_words.span(l..l + len),
+                ngrams: license_words.ngrams(l..l + len, ngram_size, true),
+            });
+            s += len;
+            l += len;
+            continue;
+        }
+
+        // gather up the whole hunk of differences, in whatever order the
+        // diff interleaved them
+        let (mut extra, mut missing) = (0, 0);
+        let mut next = Some(op);
+        while let Some(op) = next {
+            match op {
+                Op::Extra => extra += 1,
+                Op::Missing => missing += 1,
+                Op::Equal => unreachable!(),
+            }
+            next = ops.next_if(|&op| op != Op::Equal);
+        }
+
+        if missing > 0 {
+            regions.push(Region {
+                kind: RegionKind::Missing,
+                text: license_words.words[l..l + missing].join(" "),
+                sample_span: sample_words.span(s..s),
+                license_span: license_words.span(l..l + missing),
+                ngrams: license_words.ngrams(l..l + missing, ngram_size, false),
+            });
+            l += missing;
+        }
+        if extra > 0 {
+            regions.push(Region {
+                kind: RegionKind::Extra,
+                text: sample_words.words[s..s + extra].join(" "),
+                sample_span: sample_words.span(s..s + extra),
+                license_span: license_words.span(l..l),
+                ngrams: sample_words.ngrams(s..s + extra, ngram_size, false),
+            });
+            s += extra;
+        }
+    }
+
+    Explanation {
+        score: sample.match_score(license),
+        regions,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    /// The next word of both texts is the same.
+    Equal,
+    /// The next word of the sample isn't in the license.
+    Extra,
+    /// The next word of the license isn't in the sample.
+    Missing,
+}
+
+/// Find a longest common subsequence of two lists of words, returning the
+/// steps to walk both of them in order.
+fn diff(sample: &[u64], license: &[u64]) -> Vec<Op> {
+    let prefix = sample
+        .iter()
+        .zip(license)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = sample[prefix..]
+        .iter()
+        .rev()
+        .zip(license[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &sample[prefix..sample.len() - suffix];
+    let b = &license[prefix..license.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        ops.extend(std::iter::repeat_n(Op::Missing, b.len()));
+        ops.extend(std::iter::repeat_n(Op::Extra, a.len()));
+    } else {
+        ops.append(&mut lcs(a, b));
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+    ops
+}
+
+/// The textbook dynamic programming LCS, keeping two rows of lengths and a
+/// table of 2-bit steps to trace the result back with.
+fn lcs(a: &[u64], b: &[u64]) -> Vec<Op> {
+    let (n, m) = (a.len(), b.len());
+    let mut steps = vec![0u8; (n * m).div_ceil(4)];
+    let step_at = |steps: &[u8], i: usize, j: usize| {
+        let cell = i * m + j;
+        match (steps[cell / 4] >> (cell % 4 * 2)) & 0b11 {
+            0 => Op::Equal,
+            1 => Op::Extra,
+            _ => Op::Missing,
+        }
+    };
+
+    // lengths[j] is the LCS of a[i..] and b[j..], filled from the end
+    let mut below = vec![0u32; m + 1];
+    let mut lengths = vec![0u32; m + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            let (length, step) = if a[i] == b[j] {
+                (below[j + 1] + 1, 0)
+            } else if below[j] >= lengths[j + 1] {
+                (below[j], 1)
+            } else {
+                (lengths[j + 1], 2)
+            };
+            lengths[j] = length;
+            let cell = i * m + j;
+            steps[cell / 4] |= step << (cell % 4 * 2);
+        }
+        std::mem::swap(&mut below, &mut lengths);
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        let op = step_at(&steps, i, j);
+        match op {
+            Op::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Op::Extra => i += 1,
+            Op::Missing => j += 1,
+        }
+        ops.push(op);
+    }
+    ops.extend(std::iter::repeat_n(Op::Extra, n - i));
+    ops.extend(std::iter::repeat_n(Op::Missing, m - j));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_finds_common_words() {
+        let words = |s: &str| -> Vec<u64> { s.split(' ').map(hash_str).collect() };
+        let ops = diff(&words("a b x c d"), &words("a b c y d"));
+        assert_eq!(
+            vec![
+                Op::Equal,
+                Op::Equal,
+                Op::Extra,
+                Op::Equal,
+                Op::Missing,
+                Op::Equal
+            ],
+            ops
+        );
+        assert_eq!(vec![Op::Missing], diff(&[], &words("a")));
+    }
+
+    #[test]
+    fn regions_point_into_both_texts() {
+        let license_text = "Permission is hereby granted, free of charge,\nto any person.";
+        let sample_text = "// Permission is granted, free of\n// charge, to every person.";
+        let license = TextData::from(license_text);
+        let sample = TextData::from(sample_text);
+
+        let explanation = sample.explain(&license);
+        let kinds: Vec<RegionKind> = explanation.regions.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            vec![
+                RegionKind::Matched,
+                RegionKind::Missing,
+                RegionKind::Matched,
+                RegionKind::Missing,
+                RegionKind::Extra,
+                RegionKind::Matched,
+            ],
+            kinds
+        );
+
+        let hereby = &explanation.regions[1];
+        assert_eq!("hereby", hereby.text);
+        assert_eq!(
+            "hereby",
+            &license_text[hereby.license_span.unwrap().bytes()]
+        );
+        assert!(hereby.sample_span.unwrap().is_empty());
+        assert_eq!(vec!["is hereby", "hereby granted"], hereby.ngrams);
+
+        let matched = &explanation.regions[2];
+        assert_eq!(
+            "granted, free of\n// charge, to",
+            &sample_text[matched.sample_span.unwrap().bytes()]
+        );
+        assert_eq!(
+            "granted, free of charge,\nto",
+            &license_text[matched.license_span.unwrap().bytes()]
+        );
+
+        let every = &explanation.regions[4];
+        assert_eq!("every", &sample_text[every.sample_span.unwrap().bytes()]);
+        assert_eq!(2, explanation.regions_of(RegionKind::Missing).count());
+    }
+}
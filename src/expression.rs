@@ -0,0 +1,566 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fmt, str::FromStr};
+
+use failure::{bail, format_err, Error};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::store::{split_with, Store};
+
+lazy_static! {
+    /// Wording that offers a choice between licenses, rather than requiring
+    /// all of them.
+    static ref RX_CHOICE: Regex = Regex::new(
+        r"(?ix)
+        at\s+(your|the\s+licensee'?s|his|her|their)\s+(option|choice)
+        | dual[\s-]+licen[sc]ed
+        | (under|of)\s+either
+        | choice\s+of\s+(either\s+)?(the\s+)?licen[sc]es?
+        | (choose|select)\s+(either|one|between)"
+    )
+    .unwrap();
+}
+
+/// An SPDX license expression, such as `MIT OR Apache-2.0`.
+///
+/// Expressions are parsed with `str::parse`, and displayed in their usual
+/// form. Operators are case-insensitive when parsing, `WITH` binds tighter
+/// than `AND`, and `AND` tighter than `OR`. Parentheses may nest at most 64
+/// deep.
+///
+/// ```
+/// use askalono::Expression;
+///
+/// let expression: Expression = "(mit or Apache-2.0) and GPL-2.0+ with Classpath-exception-2.0"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(
+///     "(mit OR Apache-2.0) AND GPL-2.0+ WITH Classpath-exception-2.0",
+///     expression.to_string()
+/// );
+/// assert_eq!(vec!["mit", "Apache-2.0", "GPL-2.0"], expression.licenses());
+/// assert!("MIT OR".parse::<Expression>().is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Expression {
+    /// A single license.
+    License {
+        /// The license's SPDX id, or a `LicenseRef-`.
+        id: String,
+        /// Whether the license was given with `+`, for "or any later version".
+        or_later: bool,
+        /// The id of an exception given with `WITH`, if any.
+        exception: Option<String>,
+    },
+    /// All of the expressions apply.
+    And(Vec<Expression>),
+    /// Any one of the expressions may be chosen.
+    Or(Vec<Expression>),
+}
+
+impl Expression {
+    /// Create an expression for a single license, without `+` or exceptions.
+    pub fn license(id: &str) -> Expression {
+        Expression::License {
+            id: id.to_owned(),
+            or_later: false,
+            exception: None,
+        }
+    }
+
+    /// List the license ids in the expression, in order, without any `+`.
+    pub fn licenses(&self) -> Vec<&str> {
+        let mut ids = Vec::new();
+        self.visit(&mut |id, _| ids.push(id));
+        ids
+    }
+
+    /// List the exception ids in the expression, in order.
+    pub fn exceptions(&self) -> Vec<&str> {
+        let mut ids = Vec::new();
+        self.visit(&mut |_, exception| ids.extend(exception));
+        ids
+    }
+
+    fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a str, Option<&'a str>)) {
+        match self {
+            Expression::License { id, exception, .. } => f(id, exception.as_deref()),
+            Expression::And(terms) | Expression::Or(terms) => {
+                terms.iter().for_each(|term| term.visit(f))
+            }
+        }
+    }
+
+    /// Put the expression in a canonical form: nested operators of the same
+    /// kind are flattened, duplicate terms are dropped, terms are sorted, and
+    /// operators with a single term are replaced by it.
+    ///
+    /// Two expressions that normalize the same mean the same thing.
+    ///
+    /// ```
+    /// use askalono::Expression;
+    ///
+    /// let a: Expression = "MIT OR (Apache-2.0 OR MIT)".parse().unwrap();
+    /// let b: Expression = "Apache-2.0 OR MIT".parse().unwrap();
+    /// assert_eq!(a.normalize(), b.normalize());
+    /// ```
+    pub fn normalize(&self) -> Expression {
+        let (terms, is_and) = match self {
+            Expression::License { .. } => return self.clone(),
+            Expression::And(terms) => (terms, true),
+            Expression::Or(terms) => (terms, false),
+        };
+
+        let mut flat = Vec::new();
+        for term in terms.iter().map(Expression::normalize) {
+            match term {
+                Expression::And(inner) if is_and => flat.extend(inner),
+                Expression::Or(inner) if !is_and => flat.extend(inner),
+                other => flat.push(other),
+            }
+        }
+        flat.sort();
+        flat.dedup();
+
+        match (flat.len(), is_and) {
+            (1, _) => flat.remove(0),
+            (_, true) => Expression::And(flat),
+            (_, false) => Expression::Or(flat),
+        }
+    }
+
+    /// Check the expression's license and exception ids against a store,
+    /// replacing them with the names the store uses (matching
+    /// case-insensitively and by alias, as with `Store::resolve_id`).
+    ///
+    /// Deprecated ids are replaced with the ids that succeed them, as given
+    /// by `Store::successor` (with an exception, for ids such as
+    /// `GPL-2.0-with-classpath-exception`), and `+` on an `-only` id by the
+    /// `-or-later` id, as SPDX doesn't allow the two together. `LicenseRef-` and
+    /// `DocumentRef-` ids are left as they are. Any other id the store doesn't
+    /// know is an error.
+    pub fn resolve(&self, store: &Store) -> Result<Expression, Error> {
+        let mut unknown = Vec::new();
+        let resolved = self.resolve_with(store, &mut unknown);
+        if !unknown.is_empty() {
+            bail!("unknown ids: {}", unknown.join(", "));
+        }
+        Ok(resolved)
+    }
+
+    fn resolve_with(&self, store: &Store, unknown: &mut Vec<String>) -> Expression {
+        let resolve_all = |terms: &[Expression], unknown: &mut Vec<String>| {
+            terms
+                .iter()
+                .map(|term| term.resolve_with(store, unknown))
+                .collect()
+        };
+        match self {
+            Expression::License {
+                id,
+                or_later,
+                exception,
+            } => {
+                // a deprecated `GPL-2.0+` is replaced as a whole, `+` and all
+                let later = match or_later {
+                    true => store.successor(&format!("{}+", id)),
+                    false => None,
+                };
+                // and a deprecated id may stand for a license with an exception
+                let (id, or_later, implied) =
+                    match (later, store.successor(id), store.resolve_id(id)) {
+                        _ if is_reference(id) => (id.as_str(), *or_later, None),
+                        (Some(successor), _, _) => {
+                            let (id, exception) = split_with(successor);
+                            (id, false, exception)
+                        }
+                        (None, Some(successor), _) => {
+                            let (id, exception) = split_with(successor);
+                            (id, *or_later, exception)
+                        }
+                        (None, None, Some(name)) => (name, *or_later, None),
+                        (None, None, None) => {
+                            unknown.push(id.clone());
+                            (id.as_str(), *or_later, None)
+                        }
+                    };
+                let (id, or_later) = fold_or_later(store, id.to_owned(), or_later, unknown);
+                let exception = exception.as_deref().or(implied).map(|exception| {
+                    match store.resolve_exception_id(exception) {
+                        Some(name) => name.to_owned(),
+                        None => {
+                            unknown.push(exception.to_owned());
+                            exception.to_owned()
+                        }
+                    }
+                });
+                Expression::License {
+                    id,
+                    or_later,
+                    exception,
+                }
+            }
+            Expression::And(terms) => Expression::And(resolve_all(terms, unknown)),
+            Expression::Or(terms) => Expression::Or(resolve_all(terms, unknown)),
+        }
+    }
+
+    fn fmt_term(&self, f: &mut fmt::Formatter<'_>, parent_is_and: bool) -> fmt::Result {
+        match self {
+            Expression::Or(_) if parent_is_and => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+/// `+` can't follow an `-only` or `-or-later` id, so fold the two into the
+/// `-or-later` form, if the store has one. Otherwise, the id is unknown.
+fn fold_or_later(
+    store: &Store,
+    id: String,
+    or_later: bool,
+    unknown: &mut Vec<String>,
+) -> (String, bool) {
+    if !or_later || id.ends_with("-or-later") {
+        return (id, false);
+    }
+    let base = match id.strip_suffix("-only") {
+        Some(base) => base,
+        None => return (id, true),
+    };
+    match store.resolve_id(&format!("{}-or-later", base)) {
+        Some(name) => (name.to_owned(), false),
+        None => {
+            unknown.push(format!("{}+", id));
+            (id, true)
+        }
+    }
+}
+
+/// Check whether an id refers to a license defined outside the SPDX list.
+fn is_reference(id: &str) -> bool {
+    id.starts_with("LicenseRef-") || id.starts_with("DocumentRef-")
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (terms, operator, is_and) = match self {
+            Expression::License {
+                id,
+                or_later,
+                exception,
+            } => {
+                write!(f, "{}", id)?;
+                if *or_later {
+                    write!(f, "+")?;
+                }
+                if let Some(exception) = exception {
+                    write!(f, " WITH {}", exception)?;
+                }
+                return Ok(());
+            }
+            Expression::And(terms) => (terms, " AND ", true),
+            Expression::Or(terms) => (terms, " OR ", false),
+        };
+
+        for (i, term) in terms.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", operator)?;
+            }
+            term.fmt_term(f, is_and)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Expression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Expression, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            next: 0,
+            depth: 0,
+        };
+        let expression = parser.or()?;
+        match parser.tokens.get(parser.next) {
+            None => Ok(expression),
+            Some(token) => Err(format_err!("unexpected {:?} in expression", token)),
+        }
+    }
+}
+
+impl Serialize for Expression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Expression, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(de::Error::custom)
+    }
+}
+
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    for word in text.split_whitespace() {
+        let mut rest = word;
+        while !rest.is_empty() {
+            let end = match rest.find(['(', ')']) {
+                Some(0) => 1,
+                Some(paren) => paren,
+                None => rest.len(),
+            };
+            tokens.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+/// How deeply parentheses may nest in an expression. The parser recurses
+/// once per level, and tags come from whatever text is scanned.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    next: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_operator(&self, operator: &str) -> bool {
+        self.tokens
+            .get(self.next)
+            .is_some_and(|token| token.eq_ignore_ascii_case(operator))
+    }
+
+    fn or(&mut self) -> Result<Expression, Error> {
+        let mut terms = vec![self.and()?];
+        while self.peek_operator("OR") {
+            self.next += 1;
+            terms.push(self.and()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.remove(0),
+            _ => Expression::Or(terms),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expression, Error> {
+        let mut terms = vec![self.with()?];
+        while self.peek_operator("AND") {
+            self.next += 1;
+            terms.push(self.with()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.remove(0),
+            _ => Expression::And(terms),
+        })
+    }
+
+    fn with(&mut self) -> Result<Expression, Error> {
+        let parenthesized = self.tokens.get(self.next) == Some(&"(");
+        let mut term = self.atom()?;
+        if self.peek_operator("WITH") {
+            self.next += 1;
+            let exception = self.id()?;
+            match term {
+                Expression::License {
+                    exception: ref mut slot @ None,
+                    ..
+                } if !parenthesized => *slot = Some(exception.to_owned()),
+                _ => bail!("WITH must follow a single license"),
+            }
+        }
+        Ok(term)
+    }
+
+    fn atom(&mut self) -> Result<Expression, Error> {
+        if self.tokens.get(self.next) == Some(&"(") {
+            if self.depth == MAX_DEPTH {
+                bail!("parentheses nested more than {} deep", MAX_DEPTH);
+            }
+            self.next += 1;
+            self.depth += 1;
+            let inner = self.or()?;
+            if self.tokens.get(self.next) != Some(&")") {
+                bail!("unclosed parenthesis in expression");
+            }
+            self.next += 1;
+            self.depth -= 1;
+            return Ok(inner);
+        }
+
+        let id = self.id()?;
+        let (id, or_later) = match id.strip_suffix('+') {
+            Some(id) => (id, true),
+            None => (id, false),
+        };
+        Ok(Expression::License {
+            id: id.to_owned(),
+            or_later,
+            exception: None,
+        })
+    }
+
+    fn id(&mut self) -> Result<&'a str, Error> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .ok_or_else(|| format_err!("expression ended early"))?;
+        let is_operator = ["AND", "OR", "WITH"]
+            .iter()
+            .any(|op| token.eq_ignore_ascii_case(op));
+        let body = token.strip_suffix('+').unwrap_or(token);
+        let valid = !body.is_empty()
+            && body
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ':');
+        if is_operator || !valid {
+            bail!("expected a license id, found {:?}", token);
+        }
+        self.next += 1;
+        Ok(token)
+    }
+}
+
+/// Check whether a text offers a choice between licenses, with wording like
+/// "at your option" or "dual licensed".
+pub(crate) fn offers_choice(text: &str) -> bool {
+    RX_CHOICE.is_match(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let parse = |s: &str| s.parse::<Expression>().unwrap();
+
+        let expression = parse("(MIT AND Zlib) OR BSD-3-Clause AND (ISC OR LicenseRef-mine)");
+        assert_eq!(
+            "MIT AND Zlib OR BSD-3-Clause AND (ISC OR LicenseRef-mine)",
+            expression.to_string()
+        );
+        assert_eq!(
+            Expression::Or(vec![
+                Expression::And(vec![
+                    Expression::license("MIT"),
+                    Expression::license("Zlib")
+                ]),
+                Expression::And(vec![
+                    Expression::license("BSD-3-Clause"),
+                    Expression::Or(vec![
+                        Expression::license("ISC"),
+                        Expression::license("LicenseRef-mine")
+                    ])
+                ]),
+            ]),
+            expression
+        );
+        assert_eq!(
+            "BSD-3-Clause AND (ISC OR LicenseRef-mine) OR MIT AND Zlib",
+            expression.normalize().to_string()
+        );
+        assert_eq!(Expression::license("MIT"), parse("((MIT))"));
+
+        for bad in &[
+            "",
+            "MIT AND",
+            "(MIT",
+            "MIT)",
+            "(MIT) WITH x",
+            "MIT WITH",
+            "M!T",
+        ] {
+            assert!(bad.parse::<Expression>().is_err(), "{:?} parsed", bad);
+        }
+
+        let nested = |depth| format!("{}MIT{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(nested(MAX_DEPTH).parse::<Expression>().is_ok());
+        assert!(nested(MAX_DEPTH + 1).parse::<Expression>().is_err());
+        assert!(format!("{}MIT", "(".repeat(100_000))
+            .parse::<Expression>()
+            .is_err());
+    }
+
+    #[test]
+    fn resolve_against_store() {
+        let mut store = Store::new();
+        store.add_license("MIT".into(), "mit text".into());
+        store.add_license("GPL-2.0-only".into(), "gpl text".into());
+        store
+            .set_aliases("GPL-2.0-only", vec!["GPL-2.0".into()])
+            .unwrap();
+        store.add_exception("Classpath-exception-2.0".into(), "exception text".into());
+
+        // without an `-or-later` form, `+` on an `-only` license is an error
+        let expression: Expression = "gpl-2.0+".parse().unwrap();
+        let error = expression.resolve(&store).unwrap_err();
+        assert!(error.to_string().contains("GPL-2.0-only+"));
+
+        store.add_license("GPL-2.0-or-later".into(), "gpl text, or later".into());
+
+        let expression: Expression = "mit OR gpl-2.0+ WITH classpath-exception-2.0 OR LicenseRef-x"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            "MIT OR GPL-2.0-or-later WITH Classpath-exception-2.0 OR LicenseRef-x",
+            expression.resolve(&store).unwrap().to_string()
+        );
+        let expression: Expression = "GPL-2.0-or-later+".parse().unwrap();
+        assert_eq!(
+            "GPL-2.0-or-later",
+            expression.resolve(&store).unwrap().to_string()
+        );
+        let error = "MIT AND Nope"
+            .parse::<Expression>()
+            .unwrap()
+            .resolve(&store);
+        assert!(error.unwrap_err().to_string().contains("Nope"));
+    }
+
+    #[test]
+    fn deprecated_ids_resolve_to_successors() {
+        let mut store = Store::new();
+        store.add_license("GPL-2.0-only".into(), "gpl text".into());
+        store
+            .set_aliases("GPL-2.0-only", vec!["GPL-2.0-or-later".into()])
+            .unwrap();
+        store
+            .add_deprecated("GPL-2.0".into(), "GPL-2.0-only")
+            .unwrap();
+        store
+            .add_deprecated("GPL-2.0+".into(), "GPL-2.0-or-later")
+            .unwrap();
+        assert!(store.add_deprecated("Old".into(), "New").is_err());
+
+        assert_eq!(Some("GPL-2.0-or-later"), store.successor("gpl-2.0+"));
+        assert_eq!(None, store.successor("GPL-2.0-only"));
+        assert_eq!(
+            vec!["GPL-2.0", "GPL-2.0+"],
+            store.deprecated_aliases("GPL-2.0-only")
+        );
+
+        let expression: Expression = "GPL-2.0+ AND gpl-2.0".parse().unwrap();
+        assert_eq!(
+            "GPL-2.0-only AND GPL-2.0-or-later",
+            expression.resolve(&store).unwrap().normalize().to_string()
+        );
+    }
+
+    #[test]
+    fn choice_wording() {
+        assert!(offers_choice("Licensed under either of\n * Apache License"));
+        assert!(offers_choice("at your\noption"));
+        assert!(offers_choice("This project is dual-licensed."));
+        assert!(!offers_choice("Licensed under the Apache License"));
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Serialize;
+
+use crate::{
+    license::{LicenseType, TextData},
+    ngram::gram_id,
+    store::Store,
+};
+
+/// How much to scale the score of a GNU license match that has no notice to
+/// tell its version terms from.
+pub(crate) const NO_NOTICE_PENALTY: f32 = 0.95;
+
+/// Wording found in GNU license notices, but only in the "how to apply"
+/// appendix of the license texts themselves.
+const NOTICE: &[&str] = &["software", "foundation"];
+/// Wording found in notices that allow later versions of a license.
+const LATER: &[&str] = &["later", "version"];
+
+/// The version terms of a GNU license (GPL, LGPL, AGPL, GFDL), decided from
+/// its notice.
+///
+/// The texts of the `-only` and `-or-later` forms of these licenses are the
+/// same, so a store has them as one license with the other as an alias. The
+/// difference lies in the notice a project puts on its files: "either
+/// version 2 of the License, or (at your option) any later version".
+#[derive(Serialize, Debug, Clone)]
+pub struct GnuVersion<'a> {
+    /// The identifier of the license with its version terms, such as
+    /// `GPL-2.0-or-later`. This is the license's name or one of its aliases.
+    pub id: &'a str,
+    /// Whether a notice was found to decide the version terms from. Without
+    /// one, `id` is the `-only` form -- the most the license text alone can
+    /// be relied on for -- and the match's score is reduced.
+    pub from_notice: bool,
+}
+
+/// Decide the version terms of a GNU license matched against a text, if the
+/// store has both forms of it.
+///
+/// This is `None` (undecided) if the wording can't be counted in the text or
+/// the license, as when the store kept no texts and its n-grams aren't the
+/// size of the phrases looked for.
+pub(crate) fn gnu_version<'a>(
+    store: &'a Store,
+    text: &TextData,
+    name: &'a str,
+    kind: LicenseType,
+    license: &TextData,
+) -> Option<GnuVersion<'a>> {
+    let entry = store.licenses.get(name)?;
+    let ids: Vec<&'a str> = std::iter::once(name)
+        .chain(entry.aliases.iter().map(String::as_str))
+        .collect();
+    let (only, later) = ids.iter().find_map(|id| {
+        let base = id.strip_suffix("-only")?;
+        let later = ids
+            .iter()
+            .find(|other| other.strip_suffix("-or-later") == Some(base))?;
+        Some((*id, *later))
+    })?;
+
+    // a full license text carries a sample notice of its own; only wording
+    // beyond what the license has counts
+    let extra = |phrase: &[&str]| {
+        let found = phrase_count(text, phrase)?;
+        let expected = match kind {
+            LicenseType::Header | LicenseType::Short => 0,
+            _ => phrase_count(license, phrase)?,
+        };
+        Some(found > expected)
+    };
+
+    let (notice, allows_later) = (extra(NOTICE)?, extra(LATER)?);
+    Some(GnuVersion {
+        id: match allows_later {
+            true => later,
+            false => only,
+        },
+        from_notice: notice || allows_later,
+    })
+}
+
+/// Count the occurrences of a phrase in a text's processed words, or failing
+/// that, its n-grams.
+fn phrase_count(data: &TextData, phrase: &[&str]) -> Option<usize> {
+    if let Some(processed) = data.text_processed() {
+        let words: Vec<&str> = processed.split(' ').filter(|w| !w.is_empty()).collect();
+        return Some(words.windows(phrase.len()).filter(|w| w == &phrase).count());
+    }
+    match data.ngram_size().sizes().any(|n| n == phrase.len()) {
+        true => Some(data.ngrams().get(gram_id(phrase)) as usize),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngram::NgramSize;
+
+    const GPL: &str = "you can redistribute it under the terms of this license. \
+                       to apply it, attach a notice: this program is free software; \
+                       published by the free software foundation; either version 2 \
+                       of the license, or (at your option) any later version.";
+
+    #[test]
+    fn notices_decide_the_version() {
+        let mut store = Store::new();
+        store.add_license("GPL-2.0-only".into(), GPL.into());
+        store
+            .set_aliases("GPL-2.0-only", vec!["GPL-2.0-or-later".into()])
+            .unwrap();
+        store.add_license("MIT".into(), "permission is granted".into());
+        let license = store.get_original("GPL-2.0-only").unwrap();
+
+        let version = |text: &str, kind| {
+            gnu_version(&store, &text.into(), "GPL-2.0-only", kind, license)
+                .map(|v| (v.id, v.from_notice))
+        };
+
+        // the license text alone doesn't say
+        assert_eq!(
+            Some(("GPL-2.0-only", false)),
+            version(GPL, LicenseType::Original)
+        );
+        let later = format!(
+            "licensed under the gnu gpl as published by the free software \
+             foundation, version 2 or any later version.\n\n{}",
+            GPL
+        );
+        assert_eq!(
+            Some(("GPL-2.0-or-later", true)),
+            version(&later, LicenseType::Original)
+        );
+        let only = format!(
+            "licensed under version 2 of the gnu gpl as published by the free \
+             software foundation.\n\n{}",
+            GPL
+        );
+        assert_eq!(
+            Some(("GPL-2.0-only", true)),
+            version(&only, LicenseType::Original)
+        );
+        assert_eq!(
+            Some(("GPL-2.0-or-later", true)),
+            version(
+                "published by the free software foundation; any later version",
+                LicenseType::Header
+            )
+        );
+
+        let mit = store.get_original("MIT").unwrap();
+        assert!(gnu_version(&store, &GPL.into(), "MIT", LicenseType::Original, mit).is_none());
+    }
+
+    #[test]
+    fn undecided_without_license_counts() {
+        // trigrams, and no text to count the two-word phrases in
+        let mut store = Store::with_ngram_size(NgramSize::Fixed(3));
+        let gpl = TextData::with_ngram_size(GPL, NgramSize::Fixed(3)).without_text();
+        store.add_license("GPL-2.0-only".into(), gpl);
+        store
+            .set_aliases("GPL-2.0-only", vec!["GPL-2.0-or-later".into()])
+            .unwrap();
+        let license = store.get_original("GPL-2.0-only").unwrap();
+
+        let text = TextData::with_ngram_size(GPL, NgramSize::Fixed(3));
+        let version = gnu_version(
+            &store,
+            &text,
+            "GPL-2.0-only",
+            LicenseType::Original,
+            license,
+        );
+        assert!(version.is_none());
+
+        // a header is expected to have no notice wording, so it can be told
+        let header = TextData::with_ngram_size(
+            "published by the free software foundation; any later version",
+            NgramSize::Fixed(3),
+        );
+        let version = gnu_version(
+            &store,
+            &header,
+            "GPL-2.0-only",
+            LicenseType::Header,
+            license,
+        );
+        assert_eq!("GPL-2.0-or-later", version.unwrap().id);
+    }
+}
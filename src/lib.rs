@@ -9,14 +9,35 @@
 #![warn(missing_docs)]
 #![allow(clippy::match_bool, clippy::useless_format)]
 
+mod comments;
+mod explain;
+mod expression;
+mod gnu;
 mod license;
 mod ngram;
 mod preproc;
+mod residual;
+mod span;
 mod store;
 mod strategy;
+mod tag;
+mod template;
 
 pub use crate::{
+    comments::CommentStyle,
+    explain::{Explanation, Region, RegionKind},
+    expression::Expression,
+    gnu::GnuVersion,
     license::{LicenseType, TextData},
-    store::{Match, Store},
-    strategy::{ContainedResult, IdentifiedLicense, ScanMode, ScanResult, ScanStrategy},
+    ngram::{NgramSize, SimilarityMetric},
+    preproc::{Pipeline, PipelineBuilder, PipelineStage, Preprocessor},
+    residual::{Residual, ResidualKind},
+    span::{Position, Span},
+    store::{Disambiguation, LicenseMetadata, LshConfig, Match, Store},
+    strategy::{
+        Ambiguity, ContainedResult, IdentifiedException, IdentifiedLicense, ScanMode, ScanResult,
+        ScanStrategy, ScoredLicense, TagResult,
+    },
+    tag::SpdxTag,
+    template::Template,
 };
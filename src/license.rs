@@ -6,8 +6,12 @@ use std::{collections::HashMap, fmt};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    ngram::NgramSet,
-    preproc::{apply_aggressive, apply_normalizers},
+    explain::{explain, Explanation, Words},
+    ngram::{hash_str, NgramSet, NgramSize, SimilarityMetric, Vocabulary},
+    preproc::Pipeline,
+    residual::{residuals, Residual},
+    span::{join_lines, locate_words, word_spans, Original, Span},
+    tag::{find_tags, SpdxTag},
 };
 
 /// The type of a license entry (typically in a `Store`).
@@ -20,8 +24,14 @@ pub enum LicenseType {
     Header,
     /// An alternate form of a license. This is intended to be used for
     /// alternate _formats_ of a license, not for variants where the text has
-    /// different meaning. Not currently used in askalono's SPDX dataset.
+    /// different meaning. SPDX's data has none; they're loaded with
+    /// `Store::load_variants`, among others.
     Alternate,
+    /// A short form of a license, such as the sentence projects paste into
+    /// their READMEs in place of the full text. Short forms of different
+    /// licenses differ by little more than the name, so one only matches a
+    /// text that names its license; see `Store::load_variants`.
+    Short,
 }
 
 impl fmt::Display for LicenseType {
@@ -33,6 +43,7 @@ impl fmt::Display for LicenseType {
                 LicenseType::Original => "original text",
                 LicenseType::Header => "license header",
                 LicenseType::Alternate => "alternate text",
+                LicenseType::Short => "short form",
             }
         )
     }
@@ -80,6 +91,10 @@ pub struct TextData {
     lines_view: (usize, usize),
     lines_normalized: Option<Vec<String>>,
     text_processed: Option<String>,
+    text_original: Option<Original>,
+    fingerprint: u64,
+    #[serde(skip)]
+    pipeline: Pipeline,
 }
 
 const TEXTDATA_TEXT_ERROR: &str = "TextData does not have original text";
@@ -95,16 +110,37 @@ impl TextData {
     /// this library you want to keep the text data, but askalono will throw it
     /// away in its own `Store` as it's not needed.
     pub fn new(text: &str) -> TextData {
-        let normalized = apply_normalizers(text);
+        TextData::with_ngram_size(text, NgramSize::default())
+    }
+
+    /// Create a new TextData structure from a string, using n-grams of the
+    /// given size for matching.
+    ///
+    /// There's usually no need to call this directly: a `Store` will convert
+    /// samples to the size its licenses were built with when analyzing them.
+    pub fn with_ngram_size(text: &str, ngram_size: NgramSize) -> TextData {
+        TextData::with_pipeline(text, &Pipeline::standard(), ngram_size)
+    }
+
+    /// Create a new TextData structure from a string, preprocessing it with
+    /// a custom pipeline and using n-grams of the given size for matching.
+    ///
+    /// Like the n-gram size, a `Store` will re-process samples with its own
+    /// pipeline if they were built with a different one.
+    pub fn with_pipeline(text: &str, pipeline: &Pipeline, ngram_size: NgramSize) -> TextData {
+        let normalized = pipeline.normalize(text);
         let normalized_joined = normalized.join("\n");
-        let processed = apply_aggressive(&normalized_joined);
-        let match_data = NgramSet::from_str(&processed, 2);
+        let processed = pipeline.aggressive(&normalized_joined);
+        let match_data = NgramSet::from_str(&processed, ngram_size);
 
         TextData {
             match_data,
             lines_view: (0, normalized.len()),
             lines_normalized: Some(normalized),
+            fingerprint: hash_str(&processed),
             text_processed: Some(processed),
+            text_original: Some(Original::new(text)),
+            pipeline: pipeline.clone(),
         }
     }
 
@@ -119,6 +155,67 @@ impl TextData {
             lines_view: (0, 0),
             lines_normalized: None,
             text_processed: None,
+            text_original: None,
+            fingerprint: self.fingerprint,
+            pipeline: self.pipeline,
+        }
+    }
+
+    /// Get a fingerprint of the text in view.
+    ///
+    /// This is a stable hash of the text after normalization, so texts that
+    /// only differ in things like whitespace, punctuation, or copyright lines
+    /// share a fingerprint. It's kept by `without_text`, and can be stored
+    /// to recognize texts seen before.
+    ///
+    /// ```
+    /// use askalono::TextData;
+    ///
+    /// let a = TextData::from("Permission is granted,\nfree of charge.");
+    /// let b = TextData::from("permission is   granted - free of charge");
+    /// assert_eq!(a.fingerprint(), b.fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Get the preprocessing pipeline this text was built with.
+    ///
+    /// This isn't kept when a `TextData` is serialized on its own; a
+    /// deserialized one reports the standard pipeline. Those loaded as part of
+    /// a `Store` have the store's.
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    pub(crate) fn set_pipeline(&mut self, pipeline: &Pipeline) {
+        self.pipeline = pipeline.clone();
+    }
+
+    /// Get the original text this was built from, if it's still around.
+    pub(crate) fn original(&self) -> Option<&str> {
+        self.text_original.as_ref().map(Original::text)
+    }
+
+    /// Get the size of the n-grams this text was broken into.
+    pub fn ngram_size(&self) -> NgramSize {
+        self.match_data.ngram_size()
+    }
+
+    /// Clone this `TextData`, re-generating match data with n-grams of the
+    /// given size.
+    ///
+    /// Like `with_view`, this requires the text to be present.
+    pub fn to_ngram_size(&self, ngram_size: NgramSize) -> Self {
+        let processed = self.text_processed.as_ref().expect(TEXTDATA_TEXT_ERROR);
+        TextData {
+            match_data: NgramSet::from_str(processed, ngram_size),
+            lines_view: self.lines_view,
+            lines_normalized: self.lines_normalized.clone(),
+            text_processed: self.text_processed.clone(),
+            text_original: self.text_original.clone(),
+            fingerprint: self.fingerprint,
+            pipeline: self.pipeline.clone(),
         }
     }
 
@@ -147,12 +244,15 @@ impl TextData {
     pub fn with_view(&self, start: usize, end: usize) -> Self {
         let view = &self.lines_normalized.as_ref().expect(TEXTDATA_TEXT_ERROR)[start..end];
         let view_joined = view.join("\n");
-        let processed = apply_aggressive(&view_joined);
+        let processed = self.pipeline.aggressive(&view_joined);
         TextData {
-            match_data: NgramSet::from_str(&processed, 2),
+            match_data: NgramSet::from_str(&processed, self.ngram_size()),
             lines_view: (start, end),
             lines_normalized: self.lines_normalized.clone(),
+            fingerprint: hash_str(&processed),
             text_processed: Some(processed),
+            text_original: self.text_original.clone(),
+            pipeline: self.pipeline.clone(),
         }
     }
 
@@ -180,15 +280,28 @@ impl TextData {
             })
             .collect();
 
-        let processed = apply_aggressive(&new_normalized.join("\n"));
+        let processed = self.pipeline.aggressive(&new_normalized.join("\n"));
         TextData {
-            match_data: NgramSet::from_str(&processed, 2),
+            match_data: NgramSet::from_str(&processed, self.ngram_size()),
             lines_view: (0, new_normalized.len()),
             lines_normalized: Some(new_normalized),
+            fingerprint: hash_str(&processed),
             text_processed: Some(processed),
+            text_original: self.text_original.clone(),
+            pipeline: self.pipeline.clone(),
         }
     }
 
+    /// Find where a range of normalized lines came from in the original text.
+    ///
+    /// The range is in the same terms as `lines_view`, and blank lines at
+    /// either end of it are left out of the span. Returns `None` if the range
+    /// is empty or out of bounds, or if this `TextData` was stripped of its
+    /// text.
+    pub fn span(&self, lines: (usize, usize)) -> Option<Span> {
+        join_lines(self.text_original.as_ref()?.lines(), lines)
+    }
+
     /// Get a slice of the normalized lines in this `TextData`.
     pub fn lines(&self) -> &[String] {
         &self.lines_normalized.as_ref().expect(TEXTDATA_TEXT_ERROR)
@@ -207,7 +320,120 @@ impl TextData {
         self.match_data.dice(&other.match_data)
     }
 
-    #[cfg(feature = "spdx")]
+    /// Compare this `TextData` with another using a specific similarity
+    /// metric.
+    ///
+    /// `match_score` is equivalent to using `SimilarityMetric::Dice`. As there
+    /// is no `Store` to draw n-gram frequencies from here,
+    /// `SimilarityMetric::TfIdfCosine` behaves like plain `Cosine`.
+    ///
+    /// ```
+    /// use askalono::{SimilarityMetric, TextData};
+    ///
+    /// let license = TextData::from("My First License");
+    /// let sample = TextData::from("fn main() {}\n// my first license\nfn other() {}");
+    /// assert!(sample.match_score(&license) < 0.7);
+    /// assert_eq!(1.0, sample.match_score_with(&license, SimilarityMetric::Containment));
+    /// ```
+    pub fn match_score_with(&self, other: &TextData, metric: SimilarityMetric) -> f32 {
+        self.match_data
+            .similarity(&other.match_data, metric, &|_| 1.0)
+    }
+
+    /// Explain how this text matches a license, word by word.
+    ///
+    /// The processed words of both texts are aligned, and split up into
+    /// regions that both share, that are missing from this text, and that
+    /// this text adds. Each region records where it came from in the original
+    /// text of both sides, and the n-grams it added to or took away from the
+    /// score.
+    ///
+    /// Like `optimize_bounds`, this requires both `TextData`s to have their
+    /// text. Licenses in a `Store` don't, so to explain a match from one,
+    /// create a `TextData` from the license's text separately.
+    ///
+    /// ```
+    /// use askalono::{RegionKind, TextData};
+    ///
+    /// let license = TextData::from("My First License, version 2");
+    /// let sample = TextData::from("// my first license, version 3");
+    /// let explanation = sample.explain(&license);
+    ///
+    /// let extra: Vec<&str> = explanation
+    ///     .regions_of(RegionKind::Extra)
+    ///     .map(|region| region.text.as_str())
+    ///     .collect();
+    /// assert_eq!(vec!["3"], extra);
+    /// ```
+    pub fn explain(&self, license: &TextData) -> Explanation {
+        explain(self, license)
+    }
+
+    /// Find the sentences of this text that aren't explained by a license,
+    /// such as extra clauses added to an otherwise standard license.
+    ///
+    /// Sentences with n-grams missing from the license are reported as added
+    /// if most of them are missing, and altered otherwise. If the license's
+    /// text is available, its sentences that are mostly missing from this
+    /// text are reported as removed. Licenses in a `Store` only have their
+    /// text if it was built to keep it.
+    ///
+    /// This text must have its text, like with `optimize_bounds`.
+    pub fn residuals(&self, license: &TextData) -> Vec<Residual> {
+        residuals(self, license)
+    }
+
+    /// Find the `SPDX-License-Identifier` tags in the original text. The
+    /// whole text is searched, regardless of the view.
+    ///
+    /// Returns nothing if the text was stripped of its original.
+    pub fn spdx_tags(&self) -> Vec<SpdxTag> {
+        self.original().map(find_tags).unwrap_or_default()
+    }
+
+    /// Split the processed text in view into words, finding where each came
+    /// from in the original text.
+    pub(crate) fn words(&self) -> Words<'_> {
+        let processed = self.text_processed.as_ref().expect(TEXTDATA_TEXT_ERROR);
+        let words: Vec<&str> = processed.split(' ').filter(|w| !w.is_empty()).collect();
+
+        // only look for words on lines that could have produced any, so that
+        // whited-out or out-of-view lines don't throw off the search
+        let lines = self.lines_normalized.as_ref().expect(TEXTDATA_TEXT_ERROR);
+        let (start, end) = self.lines_view;
+        let original: Vec<_> = match &self.text_original {
+            Some(original) => word_spans(original.text())
+                .into_iter()
+                .filter(|(_, span)| {
+                    let line = span.start.line;
+                    line >= start && line < end && lines.get(line).is_some_and(|l| !l.is_empty())
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Words {
+            spans: locate_words(&words, &original),
+            words,
+            original: self.original(),
+        }
+    }
+
+    /// Record the text of this data's n-grams, if the text is still around.
+    pub(crate) fn add_to_vocabulary(&self, vocabulary: &mut Vocabulary) {
+        if let Some(text) = &self.text_processed {
+            vocabulary.register(text, self.match_data.ngram_size());
+        }
+    }
+
+    pub(crate) fn ngrams(&self) -> &NgramSet {
+        &self.match_data
+    }
+
+    pub(crate) fn ngrams_mut(&mut self) -> &mut NgramSet {
+        &mut self.match_data
+    }
+
     pub(crate) fn eq_data(&self, other: &Self) -> bool {
         self.match_data.eq(&other.match_data)
     }
@@ -402,4 +628,19 @@ mod tests {
         let c = b.white_out();
         assert_eq!(Some("aaa ddd"), c.text_processed());
     }
+
+    #[test]
+    fn views_share_the_original() {
+        let a = TextData::from("aaa\n  bbb\nccc");
+        let b = a.with_view(1, 2).white_out();
+        let (a_text, b_text) = (a.original().unwrap(), b.original().unwrap());
+        assert!(std::ptr::eq(a_text, b_text));
+        assert_eq!("bbb", &a_text[a.span((1, 2)).unwrap().bytes()]);
+
+        // line spans are found again after a round trip
+        let mut buf = Vec::new();
+        rmp_serde::encode::write(&mut buf, &a).unwrap();
+        let c: TextData = rmp_serde::decode::from_slice(&buf).unwrap();
+        assert_eq!(a.span((1, 2)), c.span((1, 2)));
+    }
 }
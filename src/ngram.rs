@@ -2,64 +2,242 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::{
-    cmp::min,
-    collections::{hash_map::Iter, HashMap, VecDeque},
+    cmp::{min, Ordering},
+    collections::HashMap,
+    fmt,
+    ops::RangeInclusive,
+    slice::Iter,
+    str::FromStr,
 };
 
+use failure::{format_err, Error};
 use serde::{Deserialize, Serialize};
 
+/// The size of the n-grams that texts are broken into for matching.
+///
+/// Larger n-grams capture more of the word order, which helps tell apart
+/// licenses that differ in a few phrases; smaller ones are more forgiving of
+/// reworded or reflowed text. A `Store` records the size its licenses were
+/// built with, and samples are converted to match it during analysis.
+///
+/// This can be parsed from a string: a number for `Fixed`, or `multi`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NgramSize {
+    /// N-grams of a single size. This is `Fixed(2)` (bigrams) by default.
+    Fixed(u8),
+    /// Unigrams, bigrams, and trigrams together. Scores are then a blend of
+    /// the similarity at each size, weighted by the number of n-grams of
+    /// each.
+    MultiResolution,
+}
+
+impl NgramSize {
+    /// The sizes of n-gram to extract.
+    pub fn sizes(self) -> RangeInclusive<usize> {
+        match self {
+            NgramSize::Fixed(n) => n as usize..=n as usize,
+            NgramSize::MultiResolution => 1..=3,
+        }
+    }
+}
+
+impl Default for NgramSize {
+    fn default() -> NgramSize {
+        NgramSize::Fixed(2)
+    }
+}
+
+impl fmt::Display for NgramSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NgramSize::Fixed(n) => write!(f, "{}", n),
+            NgramSize::MultiResolution => write!(f, "multi"),
+        }
+    }
+}
+
+impl FromStr for NgramSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<NgramSize, Error> {
+        if s == "multi" {
+            return Ok(NgramSize::MultiResolution);
+        }
+        match s.parse() {
+            Ok(n) if n > 0 => Ok(NgramSize::Fixed(n)),
+            _ => Err(format_err!(
+                "n-gram size must be a positive number or \"multi\", not {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// A method of comparing the n-grams of two texts.
+///
+/// All metrics produce a score from 0.0 (nothing in common) to 1.0 (identical,
+/// or in the case of `Containment`, one text wholly inside the other).
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimilarityMetric {
+    /// The Sørensen–Dice coefficient. This is askalono's default, and what
+    /// scores and thresholds elsewhere are tuned for.
+    Dice,
+    /// The Jaccard index (intersection over union). Ranks the same as Dice,
+    /// but penalizes differences more heavily.
+    Jaccard,
+    /// The overlap coefficient: the intersection over the size of the smaller
+    /// text. Useful to detect a license embedded in a much larger file, as
+    /// the surrounding content doesn't drag the score down.
+    Containment,
+    /// Cosine similarity of n-gram counts.
+    Cosine,
+    /// Cosine similarity with each n-gram weighted by how rare it is across
+    /// a `Store`. Without a store to draw frequencies from, this is the same
+    /// as `Cosine`.
+    TfIdfCosine,
+    /// The Sørensen–Dice coefficient with each n-gram weighted by how rare it
+    /// is across a `Store`, so that boilerplate like "of the" counts for less
+    /// than distinctive phrases like "advertising materials". Without a store
+    /// to draw frequencies from, this is the same as `Dice`.
+    WeightedDice,
+}
+
+/// Identifier of an n-gram: a stable hash of its words.
+///
+/// Hashing (rather than numbering n-grams as they're seen) means that texts
+/// built without access to a `Store` still agree with it on the ID of every
+/// n-gram, including ones the store has never seen.
+pub type GramId = u64;
+
+/// Compute the ID of an n-gram from its words.
+///
+/// This is the 64-bit FNV-1a hash of the words joined by single spaces, so
+/// `gram_id(&["a", "b"]) == gram_id_str("a b")`.
+pub fn gram_id(words: &[&str]) -> GramId {
+    let mut hash = FNV_OFFSET;
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            hash = fnv_step(hash, b' ');
+        }
+        hash = word.bytes().fold(hash, fnv_step);
+    }
+    hash
+}
+
+/// Compute the ID of an n-gram from its space-separated text.
+pub fn gram_id_str(gram: &str) -> GramId {
+    hash_str(gram)
+}
+
+/// Hash a string with 64-bit FNV-1a. This is stable across platforms and
+/// releases, so the result can be stored.
+pub fn hash_str(s: &str) -> u64 {
+    s.bytes().fold(FNV_OFFSET, fnv_step)
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+fn fnv_step(hash: u64, byte: u8) -> u64 {
+    (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+}
+
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct NgramSet {
-    map: HashMap<String, u32>,
+    /// N-gram IDs paired with their counts, sorted by ID.
+    #[serde(with = "delta")]
+    grams: Vec<(GramId, u32)>,
     // once Rust supports it, it'd be nice to make this
     // a type parameter & specialize
-    n: u8,
+    ngram_size: NgramSize,
     size: usize,
 }
 
-impl<'a> NgramSet {
-    pub fn new(n: u8) -> NgramSet {
+impl NgramSet {
+    pub fn new(ngram_size: NgramSize) -> NgramSet {
         NgramSet {
-            map: HashMap::new(),
-            n,
+            grams: Vec::new(),
+            ngram_size,
             size: 0,
         }
     }
 
-    pub fn from_str(s: &str, n: u8) -> NgramSet {
-        let mut set = NgramSet::new(n);
+    pub fn from_str(s: &str, ngram_size: NgramSize) -> NgramSet {
+        let mut set = NgramSet::new(ngram_size);
         set.analyze(s);
         set
     }
 
     pub fn analyze(&mut self, s: &str) {
-        let words = s.split(' ');
-
-        let mut deque: VecDeque<&str> = VecDeque::with_capacity(self.n as usize);
-        for w in words {
-            deque.push_back(w);
-            if deque.len() == self.n as usize {
-                let parts = deque.iter().cloned().collect::<Vec<&str>>();
-                self.add_gram(parts.join(" "));
-                deque.pop_front();
+        let words: Vec<&str> = s.split(' ').collect();
+        let mut ids: Vec<GramId> = self
+            .ngram_size
+            .sizes()
+            .filter(|&n| n > 0)
+            .flat_map(|n| words.windows(n).map(gram_id))
+            .collect();
+        if ids.is_empty() {
+            return;
+        }
+        self.size += ids.len();
+        ids.sort_unstable();
+
+        let mut counted: Vec<(GramId, u32)> = Vec::with_capacity(ids.len());
+        for id in ids {
+            match counted.last_mut() {
+                Some((last, count)) if *last == id => *count += 1,
+                _ => counted.push((id, 1)),
             }
         }
+
+        if self.grams.is_empty() {
+            self.grams = counted;
+        } else {
+            let mut merged = Vec::with_capacity(self.grams.len() + counted.len());
+            merged.append(&mut self.grams);
+            merged.extend(counted);
+            self.grams = NgramSet::collapse(merged);
+        }
     }
 
-    fn add_gram(&mut self, gram: String) {
-        let n = self.map.entry(gram).or_insert(0);
-        *n += 1;
-        self.size += 1;
+    /// Sort and sum up counts of a list of n-grams that may contain
+    /// duplicates.
+    fn collapse(mut grams: Vec<(GramId, u32)>) -> Vec<(GramId, u32)> {
+        grams.sort_unstable_by_key(|&(id, _)| id);
+        let mut out: Vec<(GramId, u32)> = Vec::with_capacity(grams.len());
+        for (id, count) in grams {
+            match out.last_mut() {
+                Some((last, total)) if *last == id => *total += count,
+                _ => out.push((id, count)),
+            }
+        }
+        out
     }
 
-    pub fn get(&self, gram: &str) -> u32 {
-        if let Some(count) = self.map.get(gram) {
-            *count
-        } else {
-            0
+    /// Replace every n-gram ID with another, as when moving between the
+    /// in-memory IDs and the compact IDs used in a cache.
+    pub fn remap(&mut self, f: &dyn Fn(GramId) -> GramId) {
+        let grams = self
+            .grams
+            .drain(..)
+            .map(|(id, count)| (f(id), count))
+            .collect();
+        self.grams = NgramSet::collapse(grams);
+    }
+
+    pub fn get(&self, id: GramId) -> u32 {
+        match self.grams.binary_search_by_key(&id, |&(id, _)| id) {
+            Ok(i) => self.grams[i].1,
+            Err(_) => 0,
         }
     }
 
+    pub fn ngram_size(&self) -> NgramSize {
+        self.ngram_size
+    }
+
     pub fn len(&self) -> usize {
         self.size
     }
@@ -68,41 +246,250 @@ impl<'a> NgramSet {
         self.size == 0
     }
 
-    pub fn dice(&self, other: &NgramSet) -> f32 {
-        // no sense comparing sets of different sizes
-        if other.n != self.n {
-            return 0f32;
+    /// Compare against another set with the given metric.
+    ///
+    /// `weight` is consulted for the weight of each n-gram when using
+    /// `TfIdfCosine` or `WeightedDice`, and ignored otherwise.
+    pub fn similarity(
+        &self,
+        other: &NgramSet,
+        metric: SimilarityMetric,
+        weight: &dyn Fn(GramId) -> f32,
+    ) -> f32 {
+        match metric {
+            SimilarityMetric::Dice => self.dice(other),
+            SimilarityMetric::Jaccard => self.jaccard(other),
+            SimilarityMetric::Containment => self.containment(other),
+            SimilarityMetric::Cosine => self.weighted_cosine(other, &|_| 1.0),
+            SimilarityMetric::TfIdfCosine => self.weighted_cosine(other, weight),
+            SimilarityMetric::WeightedDice => self.weighted_dice(other, weight),
         }
+    }
 
+    /// Compute a score for metrics that only depend on the number of n-grams
+    /// two sets have in common, and the sizes of the sets.
+    ///
+    /// Returns `None` for metrics that need to look at the n-grams
+    /// themselves.
+    pub fn overlap_score(
+        metric: SimilarityMetric,
+        matches: u32,
+        a_len: usize,
+        b_len: usize,
+    ) -> Option<f32> {
         // there's obviously no match if either are empty strings;
         // if we don't check here we could end up with NaN below
         // when both are empty
-        if self.is_empty() || other.is_empty() {
+        if a_len == 0 || b_len == 0 {
+            return Some(0f32);
+        }
+
+        let matches = matches as f32;
+        match metric {
+            SimilarityMetric::Dice => Some((2.0 * matches) / (a_len + b_len) as f32),
+            SimilarityMetric::Jaccard => Some(matches / ((a_len + b_len) as f32 - matches)),
+            SimilarityMetric::Containment => Some(matches / min(a_len, b_len) as f32),
+            _ => None,
+        }
+    }
+
+    pub fn dice(&self, other: &NgramSet) -> f32 {
+        self.overlap_similarity(other, SimilarityMetric::Dice)
+    }
+
+    pub fn weighted_dice(&self, other: &NgramSet, weight: &dyn Fn(GramId) -> f32) -> f32 {
+        if !self.comparable(other) {
             return 0f32;
         }
 
-        // choose the smaller map to iterate
-        let (x, y) = if self.len() < other.len() {
-            (self, other)
-        } else {
-            (other, self)
+        let mut matches = 0f32;
+        self.for_each_common(other, |id, a, b| matches += min(a, b) as f32 * weight(id));
+
+        let size = |set: &NgramSet| {
+            set.grams
+                .iter()
+                .map(|&(id, count)| count as f32 * weight(id))
+                .sum::<f32>()
         };
 
-        let mut matches = 0;
-        for (gram, count) in x {
-            matches += min(*count, y.get(gram));
+        (2.0 * matches) / (size(self) + size(other))
+    }
+
+    pub fn jaccard(&self, other: &NgramSet) -> f32 {
+        self.overlap_similarity(other, SimilarityMetric::Jaccard)
+    }
+
+    pub fn containment(&self, other: &NgramSet) -> f32 {
+        self.overlap_similarity(other, SimilarityMetric::Containment)
+    }
+
+    /// The fraction of another set's n-grams that are also in this one, as
+    /// when looking for a short text inside a longer one.
+    pub fn coverage_of(&self, other: &NgramSet) -> f32 {
+        if !self.comparable(other) {
+            return 0f32;
+        }
+        self.matches(other) as f32 / other.len() as f32
+    }
+
+    fn overlap_similarity(&self, other: &NgramSet, metric: SimilarityMetric) -> f32 {
+        if !self.comparable(other) {
+            return 0f32;
         }
 
-        (2.0 * matches as f32) / ((self.len() + other.len()) as f32)
+        NgramSet::overlap_score(metric, self.matches(other), self.len(), other.len())
+            .expect("metric is overlap-based")
+    }
+
+    pub fn weighted_cosine(&self, other: &NgramSet, weight: &dyn Fn(GramId) -> f32) -> f32 {
+        if !self.comparable(other) {
+            return 0f32;
+        }
+
+        let mut dot = 0f32;
+        self.for_each_common(other, |id, a, b| {
+            let w = weight(id);
+            dot += (a as f32 * w) * (b as f32 * w);
+        });
+
+        let norm = |set: &NgramSet| {
+            set.grams
+                .iter()
+                .map(|&(id, count)| (count as f32 * weight(id)).powi(2))
+                .sum::<f32>()
+                .sqrt()
+        };
+        let norms = norm(self) * norm(other);
+        if norms == 0f32 {
+            return 0f32;
+        }
+
+        dot / norms
+    }
+
+    fn comparable(&self, other: &NgramSet) -> bool {
+        // no sense comparing sets of different sizes, and there's obviously
+        // no match if either are empty strings; if we don't check here we
+        // could end up with NaN when both are empty
+        other.ngram_size == self.ngram_size && !self.is_empty() && !other.is_empty()
+    }
+
+    /// Walk both sorted sets in step, calling `f` with the ID and both counts
+    /// of each n-gram they have in common.
+    fn for_each_common(&self, other: &NgramSet, mut f: impl FnMut(GramId, u32, u32)) {
+        let (mut x, mut y) = (self.grams.iter().peekable(), other.grams.iter().peekable());
+        while let (Some(&&(a, a_count)), Some(&&(b, b_count))) = (x.peek(), y.peek()) {
+            match a.cmp(&b) {
+                Ordering::Less => {
+                    x.next();
+                }
+                Ordering::Greater => {
+                    y.next();
+                }
+                Ordering::Equal => {
+                    f(a, a_count, b_count);
+                    x.next();
+                    y.next();
+                }
+            }
+        }
+    }
+
+    /// Count the n-grams the two sets have in common, including duplicates.
+    fn matches(&self, other: &NgramSet) -> u32 {
+        let mut matches = 0;
+        self.for_each_common(other, |_, a, b| matches += min(a, b));
+        matches
     }
 }
 
 impl<'a> IntoIterator for &'a NgramSet {
-    type Item = (&'a String, &'a u32);
-    type IntoIter = Iter<'a, String, u32>;
+    type Item = &'a (GramId, u32);
+    type IntoIter = Iter<'a, (GramId, u32)>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.map.iter()
+        self.grams.iter()
+    }
+}
+
+/// Serialization of sorted n-gram lists as a flat list alternating between
+/// the difference from the previous ID and the count. When IDs are small and
+/// dense, as in a cache, this takes a byte or two per n-gram rather than a
+/// nested array.
+mod delta {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::GramId;
+
+    pub fn serialize<S: Serializer>(grams: &[(GramId, u32)], s: S) -> Result<S::Ok, S::Error> {
+        let mut last = 0;
+        let mut flat = Vec::with_capacity(grams.len() * 2);
+        for &(id, count) in grams {
+            flat.push(id - last);
+            flat.push(u64::from(count));
+            last = id;
+        }
+        flat.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<(GramId, u32)>, D::Error> {
+        let flat: Vec<u64> = Vec::deserialize(d)?;
+        if flat.len() % 2 == 1 {
+            return Err(D::Error::custom("odd number of values in n-gram list"));
+        }
+
+        let mut id: GramId = 0;
+        flat.chunks(2)
+            .map(|pair| {
+                id = id
+                    .checked_add(pair[0])
+                    .ok_or_else(|| D::Error::custom("n-gram IDs out of order"))?;
+                Ok((id, pair[1] as u32))
+            })
+            .collect()
+    }
+}
+
+/// The text of each n-gram in a `Store`, so that IDs can be turned back into
+/// words when explaining a match.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Vocabulary {
+    grams: HashMap<GramId, String>,
+}
+
+impl Vocabulary {
+    /// Record the text of every n-gram in a string of space-separated words.
+    pub fn register(&mut self, s: &str, ngram_size: NgramSize) {
+        let words: Vec<&str> = s.split(' ').collect();
+        for n in ngram_size.sizes().filter(|&n| n > 0) {
+            for window in words.windows(n) {
+                self.grams
+                    .entry(gram_id(window))
+                    .or_insert_with(|| window.join(" "));
+            }
+        }
+    }
+
+    /// Add an n-gram whose ID is already known.
+    pub fn insert(&mut self, id: GramId, gram: String) {
+        self.grams.insert(id, gram);
+    }
+
+    pub fn get(&self, id: GramId) -> Option<&str> {
+        self.grams.get(&id).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GramId, &str)> {
+        self.grams.iter().map(|(&id, gram)| (id, gram.as_str()))
+    }
+
+    /// Get the text of an n-gram, or a placeholder showing its ID if the text
+    /// isn't known.
+    pub fn describe(&self, id: GramId) -> String {
+        match self.get(id) {
+            Some(gram) => gram.to_owned(),
+            None => format!("#{:016x}", id),
+        }
     }
 }
 
@@ -113,15 +500,15 @@ mod tests {
     // this is a pretty banal test, but it's a starting point :P
     #[test]
     fn can_construct() {
-        let set = NgramSet::new(2);
+        let set = NgramSet::new(NgramSize::Fixed(2));
         assert_eq!(set.size, 0);
-        assert_eq!(set.n, 2);
+        assert_eq!(set.ngram_size, NgramSize::Fixed(2));
     }
 
     #[test]
     fn no_nan() {
-        let a = NgramSet::from_str("", 2);
-        let b = NgramSet::from_str("", 2);
+        let a = NgramSet::from_str("", NgramSize::Fixed(2));
+        let b = NgramSet::from_str("", NgramSize::Fixed(2));
 
         let score = a.dice(&b);
 
@@ -130,18 +517,103 @@ mod tests {
 
     #[test]
     fn same_size() {
-        let a = NgramSet::from_str("", 2);
-        let b = NgramSet::from_str("", 3);
+        let a = NgramSet::from_str("", NgramSize::Fixed(2));
+        let b = NgramSet::from_str("", NgramSize::Fixed(3));
 
         let score = a.dice(&b);
 
         assert_eq!(0f32, score);
     }
 
+    #[test]
+    fn metrics_agree_on_extremes() {
+        let a = NgramSet::from_str("one two three apple banana", NgramSize::Fixed(2));
+        let b = NgramSet::from_str("one two three apple banana", NgramSize::Fixed(2));
+        let c = NgramSet::from_str("four five six", NgramSize::Fixed(2));
+        let uniform = |_: GramId| 1.0;
+
+        for metric in &[
+            SimilarityMetric::Dice,
+            SimilarityMetric::Jaccard,
+            SimilarityMetric::Containment,
+            SimilarityMetric::Cosine,
+            SimilarityMetric::TfIdfCosine,
+            SimilarityMetric::WeightedDice,
+        ] {
+            let same = a.similarity(&b, *metric, &uniform);
+            assert!((same - 1.0).abs() < 1e-6, "{:?} of identical sets", metric);
+            assert_eq!(0f32, a.similarity(&c, *metric, &uniform), "{:?}", metric);
+        }
+    }
+
+    #[test]
+    fn containment_ignores_surroundings() {
+        let license = NgramSet::from_str("one two three apple banana", NgramSize::Fixed(2));
+        let sample = NgramSet::from_str(
+            "lots of code here one two three apple banana and more code after",
+            NgramSize::Fixed(2),
+        );
+
+        assert_eq!(1f32, license.containment(&sample));
+        assert!(license.dice(&sample) < 0.6);
+        assert!(license.jaccard(&sample) < license.dice(&sample));
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        assert_eq!(gram_id(&["one", "two"]), gram_id_str("one two"));
+        assert_ne!(gram_id(&["one", "two"]), gram_id(&["onetwo"]));
+        assert_ne!(gram_id(&["one", "two"]), gram_id(&["two", "one"]));
+
+        let set = NgramSet::from_str("one two one two", NgramSize::Fixed(2));
+        assert_eq!(3, set.len());
+        assert_eq!(2, set.get(gram_id_str("one two")));
+        assert_eq!(1, set.get(gram_id_str("two one")));
+        assert_eq!(0, set.get(gram_id_str("two three")));
+    }
+
+    #[test]
+    fn remap_round_trips() {
+        let original = NgramSet::from_str("one two three four five", NgramSize::Fixed(2));
+        let mut set = original.clone();
+        set.remap(&|id| id.rotate_left(7));
+        assert_ne!(original, set);
+        set.remap(&|id| id.rotate_right(7));
+        assert_eq!(original, set);
+    }
+
+    #[test]
+    fn multi_resolution_blends_sizes() {
+        let a = "one two three four five six";
+        let b = "one two three six five four";
+        let score = |size| NgramSet::from_str(a, size).dice(&NgramSet::from_str(b, size));
+
+        let (uni, tri) = (score(NgramSize::Fixed(1)), score(NgramSize::Fixed(3)));
+        let multi = score(NgramSize::MultiResolution);
+        assert_eq!(1f32, uni);
+        assert!(tri < multi && multi < uni, "{} < {} < {}", tri, multi, uni);
+
+        let set = NgramSet::from_str(a, NgramSize::MultiResolution);
+        assert_eq!(6 + 5 + 4, set.len());
+    }
+
+    #[test]
+    fn parse_ngram_size() {
+        for size in &[
+            NgramSize::Fixed(1),
+            NgramSize::Fixed(3),
+            NgramSize::MultiResolution,
+        ] {
+            assert_eq!(*size, size.to_string().parse().unwrap());
+        }
+        assert!("0".parse::<NgramSize>().is_err());
+        assert!("bigrams".parse::<NgramSize>().is_err());
+    }
+
     #[test]
     fn identical() {
-        let a = NgramSet::from_str("one two three apple banana", 2);
-        let b = NgramSet::from_str("one two three apple banana", 2);
+        let a = NgramSet::from_str("one two three apple banana", NgramSize::Fixed(2));
+        let b = NgramSet::from_str("one two three apple banana", NgramSize::Fixed(2));
 
         let score = a.dice(&b);
 
@@ -2,14 +2,20 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
 
+use failure::{bail, format_err, Error};
 use lazy_static::lazy_static;
 use log::debug;
 use regex::{Regex, Replacer};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use unicode_normalization::UnicodeNormalization;
 
-type PreprocFn = dyn Fn(Cow<str>) -> Cow<str>;
+use crate::ngram::hash_str;
+
+type PreprocFn = for<'a> fn(Cow<'a, str>) -> Cow<'a, str>;
 
 trait CowRegex {
     fn replace_all_cow<'a, R: Replacer>(&self, text: Cow<'a, str>, replace: R) -> Cow<'a, str>;
@@ -24,51 +30,491 @@ impl CowRegex for Regex {
     }
 }
 
-/// A list of preprocessors that normalize text without removing anything
-/// substantial. These operate on one line at a time.
-pub const PREPROC_NORMALIZE: [&PreprocFn; 6] = [
-    &normalize_unicode,
-    &remove_junk,
-    &blackbox_urls,
-    &normalize_horizontal_whitespace,
-    &normalize_punctuation,
-    &trim,
+/// Preprocessors that normalize text without removing anything substantial.
+/// These operate on one line at a time.
+const STANDARD_NORMALIZE: [(&str, PreprocFn); 6] = [
+    ("normalize_unicode", normalize_unicode),
+    ("remove_junk", remove_junk),
+    ("blackbox_urls", blackbox_urls),
+    (
+        "normalize_horizontal_whitespace",
+        normalize_horizontal_whitespace,
+    ),
+    ("normalize_punctuation", normalize_punctuation),
+    ("trim", trim),
 ];
 
-/// A list of preprocessors that more aggressively normalize/mangle text
-/// to make for friendlier matching. May remove statements and lines, and
-/// more heavily normalize punctuation.
-pub const PREPROC_AGGRESSIVE: [&PreprocFn; 8] = [
-    &remove_common_tokens,
-    &normalize_vertical_whitespace,
-    &remove_punctuation,
-    &lowercaseify,
-    &remove_title_line,
-    &remove_copyright_statements,
-    &collapse_whitespace,
-    &trim,
+/// Preprocessors that more aggressively normalize/mangle text to make for
+/// friendlier matching. May remove statements and lines, and more heavily
+/// normalize punctuation.
+const STANDARD_AGGRESSIVE: [(&str, PreprocFn); 8] = [
+    ("remove_common_tokens", remove_common_tokens),
+    (
+        "normalize_vertical_whitespace",
+        normalize_vertical_whitespace,
+    ),
+    ("remove_punctuation", remove_punctuation),
+    ("lowercaseify", lowercaseify),
+    ("remove_title_line", remove_title_line),
+    ("remove_copyright_statements", remove_copyright_statements),
+    ("collapse_whitespace", collapse_whitespace),
+    ("trim", trim),
 ];
 
-pub fn apply_normalizers(text: &str) -> Vec<String> {
-    let mut lines = Vec::new();
-    for line in text.split('\n') {
-        let mut out: Cow<str> = line.into();
-        for preproc in &PREPROC_NORMALIZE {
-            out = preproc(out);
+lazy_static! {
+    static ref STANDARD: Pipeline = Pipeline::new(
+        STANDARD_NORMALIZE.iter().map(Builtin::step).collect(),
+        STANDARD_AGGRESSIVE.iter().map(Builtin::step).collect(),
+    );
+}
+
+/// A single step of text preprocessing.
+///
+/// Preprocessors run in order as part of a `Pipeline`, each taking the output
+/// of the one before. Implement this to add your own steps:
+///
+/// ```
+/// use std::borrow::Cow;
+/// use askalono::{Pipeline, PipelineStage, Preprocessor, TextData};
+///
+/// /// Blank out the lines of our file banner.
+/// struct StripBanner;
+///
+/// impl Preprocessor for StripBanner {
+///     fn name(&self) -> &str {
+///         "strip_banner"
+///     }
+///
+///     fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+///         match text.starts_with("## ACME") {
+///             true => "".into(),
+///             false => text,
+///         }
+///     }
+/// }
+///
+/// let pipeline = Pipeline::builder()
+///     .add(PipelineStage::Normalize, StripBanner)
+///     .build()
+///     .unwrap();
+/// let data = TextData::with_pipeline("## ACME internal\nMy License", &pipeline, Default::default());
+/// assert_eq!(Some("my license"), data.text_processed());
+/// ```
+pub trait Preprocessor: Send + Sync {
+    /// The name of this step.
+    ///
+    /// Names must be unique within a stage of a pipeline, and shouldn't
+    /// change: they're recorded in caches to check that texts are processed
+    /// the same way at scan time as when the cache was built. For the same
+    /// reason, they can't be the names of askalono's standard steps.
+    fn name(&self) -> &str;
+
+    /// Process some text.
+    ///
+    /// In the `Normalize` stage, this is given a single line, and must not
+    /// add or remove line breaks.
+    fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str>;
+}
+
+/// One of askalono's own preprocessing steps.
+struct Builtin {
+    name: &'static str,
+    func: PreprocFn,
+}
+
+impl Builtin {
+    fn step(&(name, func): &(&'static str, PreprocFn)) -> Arc<dyn Preprocessor> {
+        Arc::new(Builtin { name, func })
+    }
+
+    fn find(stage: PipelineStage, name: &str) -> Option<Arc<dyn Preprocessor>> {
+        let steps: &[(&'static str, PreprocFn)] = match stage {
+            PipelineStage::Normalize => &STANDARD_NORMALIZE,
+            PipelineStage::Aggressive => &STANDARD_AGGRESSIVE,
+        };
+        steps
+            .iter()
+            .find(|&&(builtin, _)| builtin == name)
+            .map(Builtin::step)
+    }
+
+    /// Check whether a name is taken by a standard step of either stage.
+    fn is_reserved(name: &str) -> bool {
+        STANDARD_NORMALIZE
+            .iter()
+            .chain(STANDARD_AGGRESSIVE.iter())
+            .any(|&(builtin, _)| builtin == name)
+    }
+}
+
+impl Preprocessor for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+        (self.func)(text)
+    }
+}
+
+/// The stages of a preprocessing `Pipeline`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PipelineStage {
+    /// Steps run on each line of a text separately, keeping the lines
+    /// intact. Texts are split into lines for matching after this stage.
+    Normalize,
+    /// Steps run on the normalized text as a whole (or the lines in view),
+    /// which may remove lines and mangle the text freely.
+    Aggressive,
+}
+
+/// An ordered set of preprocessing steps that texts go through before
+/// they're broken into n-grams.
+///
+/// The standard pipeline is what askalono uses by default. Build a custom
+/// one with `Pipeline::builder`, then use it with `Store::with_pipeline` and
+/// `TextData::with_pipeline`. A store records the names of its pipeline's
+/// steps in its cache.
+///
+/// ```
+/// use askalono::{Pipeline, PipelineStage};
+///
+/// let pipeline = Pipeline::builder()
+///     .remove(PipelineStage::Aggressive, "remove_title_line")
+///     .build()
+///     .unwrap();
+/// assert!(!pipeline
+///     .steps(PipelineStage::Aggressive)
+///     .any(|name| name == "remove_title_line"));
+/// ```
+#[derive(Clone)]
+pub struct Pipeline {
+    normalize: Vec<Arc<dyn Preprocessor>>,
+    aggressive: Vec<Arc<dyn Preprocessor>>,
+    id: u64,
+}
+
+/// The names of the steps in a pipeline, which is what gets stored in caches.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct PipelineSpec {
+    normalize: Vec<String>,
+    aggressive: Vec<String>,
+}
+
+impl Pipeline {
+    fn new(normalize: Vec<Arc<dyn Preprocessor>>, aggressive: Vec<Arc<dyn Preprocessor>>) -> Self {
+        let mut pipeline = Pipeline {
+            normalize,
+            aggressive,
+            id: 0,
+        };
+        let spec = pipeline.spec();
+        pipeline.id = hash_str(&format!(
+            "{}\n{}",
+            spec.normalize.join(" "),
+            spec.aggressive.join(" ")
+        ));
+        pipeline
+    }
+
+    /// Get the standard pipeline that askalono uses by default.
+    pub fn standard() -> Pipeline {
+        STANDARD.clone()
+    }
+
+    /// Start building a pipeline, beginning with the standard steps.
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::new()
+    }
+
+    /// Iterate over the names of the steps in a stage, in order.
+    pub fn steps(&self, stage: PipelineStage) -> impl Iterator<Item = &str> {
+        self.stage(stage).iter().map(|step| step.name())
+    }
+
+    /// Get an identifier for this pipeline's configuration. Pipelines with
+    /// the same steps in the same order share an ID.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn stage(&self, stage: PipelineStage) -> &[Arc<dyn Preprocessor>] {
+        match stage {
+            PipelineStage::Normalize => &self.normalize,
+            PipelineStage::Aggressive => &self.aggressive,
         }
-        lines.push(out.into());
     }
-    debug!("Normalized to:\n{:?}\n---", lines);
-    lines
+
+    /// Run the `Normalize` stage over each line of a text.
+    pub(crate) fn normalize(&self, text: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        for line in text.split('\n') {
+            let mut out: Cow<'_, str> = line.into();
+            for step in &self.normalize {
+                out = step.apply(out);
+            }
+            lines.push(out.into());
+        }
+        debug!("Normalized to:\n{:?}\n---", lines);
+        lines
+    }
+
+    /// Run the `Aggressive` stage over normalized text.
+    pub(crate) fn aggressive(&self, text: &str) -> String {
+        let mut out: Cow<'_, str> = text.into();
+        for step in &self.aggressive {
+            out = step.apply(out);
+        }
+        debug!("Aggressively normalized to:\n{}\n---", &out);
+        out.into()
+    }
+
+    pub(crate) fn spec(&self) -> PipelineSpec {
+        let names = |stage| self.steps(stage).map(str::to_owned).collect();
+        PipelineSpec {
+            normalize: names(PipelineStage::Normalize),
+            aggressive: names(PipelineStage::Aggressive),
+        }
+    }
+
+    /// Get the pipeline described by a spec: the given pipeline if it
+    /// matches, or else the standard steps it names.
+    pub(crate) fn resolve(
+        spec: &PipelineSpec,
+        given: Option<&Pipeline>,
+    ) -> Result<Pipeline, Error> {
+        if let Some(pipeline) = given {
+            if pipeline.spec() != *spec {
+                bail!(
+                    "preprocessing pipeline doesn't match the one recorded: expected {:?}",
+                    spec
+                );
+            }
+            return Ok(pipeline.clone());
+        }
+
+        let find = |stage, names: &[String]| {
+            names
+                .iter()
+                .map(|name| {
+                    Builtin::find(stage, name).ok_or_else(|| {
+                        format_err!(
+                            "preprocessing step {:?} isn't built in; supply the pipeline it came from",
+                            name
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        };
+        Ok(Pipeline::new(
+            find(PipelineStage::Normalize, &spec.normalize)?,
+            find(PipelineStage::Aggressive, &spec.aggressive)?,
+        ))
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::standard()
+    }
+}
+
+impl PartialEq for Pipeline {
+    fn eq(&self, other: &Pipeline) -> bool {
+        self.spec() == other.spec()
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("normalize", &self.spec().normalize)
+            .field("aggressive", &self.spec().aggressive)
+            .finish()
+    }
 }
 
-pub fn apply_aggressive(text: &str) -> String {
-    let mut out = text.into();
-    for preproc in &PREPROC_AGGRESSIVE {
-        out = preproc(out);
+/// Pipelines serialize as the names of their steps, so only those made of
+/// standard steps can be deserialized.
+impl Serialize for Pipeline {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.spec().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pipeline {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let spec = PipelineSpec::deserialize(deserializer)?;
+        Pipeline::resolve(&spec, None).map_err(D::Error::custom)
+    }
+}
+
+/// Builds a custom `Pipeline`. Start one with `Pipeline::builder`.
+///
+/// Steps are referred to by name. Mistakes, such as naming a step that isn't
+/// there, are reported by `build`.
+pub struct PipelineBuilder {
+    normalize: Vec<Arc<dyn Preprocessor>>,
+    aggressive: Vec<Arc<dyn Preprocessor>>,
+    errors: Vec<String>,
+}
+
+impl PipelineBuilder {
+    /// Start with the standard steps.
+    pub fn new() -> PipelineBuilder {
+        PipelineBuilder {
+            normalize: STANDARD.normalize.clone(),
+            aggressive: STANDARD.aggressive.clone(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Start with no steps at all.
+    pub fn empty() -> PipelineBuilder {
+        PipelineBuilder {
+            normalize: Vec::new(),
+            aggressive: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Add a step to the end of a stage.
+    pub fn add<P: Preprocessor + 'static>(mut self, stage: PipelineStage, step: P) -> Self {
+        let step = self.custom(step);
+        self.stage(stage).push(step);
+        self
+    }
+
+    /// Add one of the standard steps to the end of a stage, such as after
+    /// starting from `empty`.
+    pub fn add_standard(mut self, stage: PipelineStage, name: &str) -> Self {
+        match Builtin::find(stage, name) {
+            Some(step) => self.stage(stage).push(step),
+            None => self
+                .errors
+                .push(format!("no standard {:?} step named {:?}", stage, name)),
+        }
+        self
+    }
+
+    /// Add a step just before another one.
+    pub fn insert_before<P: Preprocessor + 'static>(
+        mut self,
+        stage: PipelineStage,
+        before: &str,
+        step: P,
+    ) -> Self {
+        let step = self.custom(step);
+        if let Some(index) = self.position(stage, before) {
+            self.stage(stage).insert(index, step);
+        }
+        self
+    }
+
+    /// Add a step just after another one.
+    pub fn insert_after<P: Preprocessor + 'static>(
+        mut self,
+        stage: PipelineStage,
+        after: &str,
+        step: P,
+    ) -> Self {
+        let step = self.custom(step);
+        if let Some(index) = self.position(stage, after) {
+            self.stage(stage).insert(index + 1, step);
+        }
+        self
+    }
+
+    /// Remove a step.
+    pub fn remove(mut self, stage: PipelineStage, name: &str) -> Self {
+        if let Some(index) = self.position(stage, name) {
+            self.stage(stage).remove(index);
+        }
+        self
+    }
+
+    /// Put the steps of a stage in the given order. Every step in the stage
+    /// must be named exactly once.
+    pub fn reorder(mut self, stage: PipelineStage, names: &[&str]) -> Self {
+        let mut steps = std::mem::take(self.stage(stage));
+        let mut ordered = Vec::with_capacity(steps.len());
+        for name in names {
+            match steps.iter().position(|step| step.name() == *name) {
+                Some(index) => ordered.push(steps.remove(index)),
+                None => self.errors.push(format!(
+                    "can't reorder {:?} step {:?}: not found",
+                    stage, name
+                )),
+            }
+        }
+        for step in &steps {
+            self.errors.push(format!(
+                "{:?} step {:?} was left out of the new order",
+                stage,
+                step.name()
+            ));
+        }
+        *self.stage(stage) = ordered;
+        self
+    }
+
+    /// Finish building the pipeline.
+    pub fn build(mut self) -> Result<Pipeline, Error> {
+        let stages = [
+            (PipelineStage::Normalize, &self.normalize),
+            (PipelineStage::Aggressive, &self.aggressive),
+        ];
+        for (stage, steps) in &stages {
+            let mut seen = HashSet::new();
+            for step in steps.iter() {
+                if !seen.insert(step.name()) {
+                    self.errors
+                        .push(format!("{:?} step {:?} appears twice", stage, step.name()));
+                }
+            }
+        }
+        if !self.errors.is_empty() {
+            bail!("invalid pipeline: {}", self.errors.join("; "));
+        }
+        Ok(Pipeline::new(self.normalize, self.aggressive))
+    }
+
+    fn stage(&mut self, stage: PipelineStage) -> &mut Vec<Arc<dyn Preprocessor>> {
+        match stage {
+            PipelineStage::Normalize => &mut self.normalize,
+            PipelineStage::Aggressive => &mut self.aggressive,
+        }
+    }
+
+    /// Wrap up a custom step, which can't pass for a standard one: caches
+    /// would load the standard step in its place.
+    fn custom<P: Preprocessor + 'static>(&mut self, step: P) -> Arc<dyn Preprocessor> {
+        if Builtin::is_reserved(step.name()) {
+            self.errors.push(format!(
+                "custom step {:?} has the name of a standard step",
+                step.name()
+            ));
+        }
+        Arc::new(step)
+    }
+
+    fn position(&mut self, stage: PipelineStage, name: &str) -> Option<usize> {
+        let found = self
+            .stage(stage)
+            .iter()
+            .position(|step| step.name() == name);
+        if found.is_none() {
+            self.errors
+                .push(format!("no {:?} step named {:?}", stage, name));
+        }
+        found
+    }
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> PipelineBuilder {
+        PipelineBuilder::new()
     }
-    debug!("Aggressively normalized to:\n{}\n---", &out);
-    out.into()
 }
 
 // Line-by-line normalizers
@@ -399,6 +845,79 @@ mod tests {
         assert!(!new_text.contains("AAAAAA"));
     }
 
+    #[test]
+    fn builder_edits_steps() {
+        struct Shout;
+        impl Preprocessor for Shout {
+            fn name(&self) -> &str {
+                "shout"
+            }
+            fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+                text.to_uppercase().into()
+            }
+        }
+
+        let pipeline = Pipeline::builder()
+            .remove(PipelineStage::Aggressive, "remove_title_line")
+            .add(PipelineStage::Aggressive, Shout)
+            .build()
+            .unwrap();
+        assert_ne!(Pipeline::standard().id(), pipeline.id());
+        assert_eq!(
+            Some("shout"),
+            pipeline.steps(PipelineStage::Aggressive).last()
+        );
+        assert_eq!("MY LICENSE", pipeline.aggressive("My License!"));
+
+        // putting the steps back in the standard order gets the standard
+        // pipeline again
+        let standard = Pipeline::standard();
+        let names: Vec<&str> = standard.steps(PipelineStage::Normalize).collect();
+        let mut reversed = names.clone();
+        reversed.reverse();
+        let pipeline = PipelineBuilder::new()
+            .reorder(PipelineStage::Normalize, &reversed)
+            .reorder(PipelineStage::Normalize, &names)
+            .build()
+            .unwrap();
+        assert_eq!(Pipeline::standard().id(), pipeline.id());
+
+        // mistakes are reported when building
+        assert!(PipelineBuilder::new()
+            .remove(PipelineStage::Normalize, "remove_title_line")
+            .build()
+            .is_err());
+        assert!(PipelineBuilder::new()
+            .reorder(PipelineStage::Normalize, &["trim"])
+            .build()
+            .is_err());
+        assert!(PipelineBuilder::empty()
+            .add_standard(PipelineStage::Aggressive, "trim")
+            .add_standard(PipelineStage::Aggressive, "trim")
+            .build()
+            .is_err());
+
+        // a custom step can't stand in for a standard one
+        struct Lowercase;
+        impl Preprocessor for Lowercase {
+            fn name(&self) -> &str {
+                "lowercaseify"
+            }
+            fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+                text.to_lowercase().into()
+            }
+        }
+        assert!(PipelineBuilder::new()
+            .remove(PipelineStage::Aggressive, "lowercaseify")
+            .add(PipelineStage::Aggressive, Lowercase)
+            .build()
+            .is_err());
+        assert!(PipelineBuilder::empty()
+            .add(PipelineStage::Normalize, Lowercase)
+            .build()
+            .is_err());
+    }
+
     #[test]
     fn normalize_no_line_mangle() {
         let text = "some license
@@ -417,7 +936,7 @@ mod tests {
 
         let text_lines = text.lines().count();
 
-        let normalized = apply_normalizers(text);
+        let normalized = Pipeline::standard().normalize(text);
         let normalized_lines = normalized.len();
 
         assert_eq!(
@@ -0,0 +1,203 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    explain::Words,
+    license::TextData,
+    ngram::{gram_id, NgramSet},
+    span::Span,
+};
+
+/// Sentences with less than this fraction of their n-grams found in the other
+/// text are considered added (or removed) outright, rather than altered.
+const ADDED_BELOW: f32 = 0.5;
+
+/// How a sentence differs from the license it was matched against.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResidualKind {
+    /// The sentence is in the sample, but has little to do with the license.
+    Added,
+    /// The sentence is in the license, but has little to do with the sample.
+    Removed,
+    /// The sentence is in the sample and resembles the license, but some of
+    /// its wording doesn't appear there.
+    Altered,
+}
+
+/// A sentence that isn't explained by the license a text matched.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Residual {
+    /// Whether the sentence was added, removed, or altered.
+    pub kind: ResidualKind,
+    /// The sentence as it appears in its original text.
+    pub text: String,
+    /// Where the sentence is in the sample, for added and altered sentences.
+    pub sample_span: Option<Span>,
+    /// Where the sentence is in the license, for removed sentences.
+    pub license_span: Option<Span>,
+    /// The fraction of the sentence's n-grams found in the other text.
+    pub coverage: f32,
+}
+
+pub(crate) fn residuals(sample: &TextData, license: &TextData) -> Vec<Residual> {
+    let mut found = Vec::new();
+
+    let sample_words = sample.words();
+    for (range, coverage) in uncovered(&sample_words, license.ngrams()) {
+        found.push(Residual {
+            kind: match coverage < ADDED_BELOW {
+                true => ResidualKind::Added,
+                false => ResidualKind::Altered,
+            },
+            text: sentence_text(&sample_words, range.clone()),
+            sample_span: sample_words.span(range),
+            license_span: None,
+            coverage,
+        });
+    }
+
+    // sentences of the license that are only partially there correspond to
+    // altered ones in the sample, so only report those missing outright
+    if license.text_processed().is_some() {
+        let license_words = license.words();
+        for (range, coverage) in uncovered(&license_words, sample.ngrams()) {
+            if coverage < ADDED_BELOW {
+                found.push(Residual {
+                    kind: ResidualKind::Removed,
+                    text: sentence_text(&license_words, range.clone()),
+                    sample_span: None,
+                    license_span: license_words.span(range),
+                    coverage,
+                });
+            }
+        }
+    }
+
+    found
+}
+
+/// Find the sentences of a text that have n-grams missing from another set,
+/// with the fraction of their n-grams that were found.
+fn uncovered(words: &Words<'_>, other: &NgramSet) -> Vec<(Range<usize>, f32)> {
+    sentences(words)
+        .into_iter()
+        .filter_map(|range| {
+            let (mut total, mut hits) = (0, 0);
+            for n in other.ngram_size().sizes().filter(|&n| n > 0) {
+                if range.len() < n {
+                    continue;
+                }
+                for window in words.words[range.clone()].windows(n) {
+                    total += 1;
+                    if other.get(gram_id(window)) > 0 {
+                        hits += 1;
+                    }
+                }
+            }
+            match total > hits {
+                true => Some((range, hits as f32 / total as f32)),
+                false => None,
+            }
+        })
+        .collect()
+}
+
+/// Split a text's words into sentences, using the punctuation and paragraph
+/// breaks of the original text between them.
+///
+/// Words that couldn't be located in the original stay with the sentence
+/// before them.
+fn sentences(words: &Words<'_>) -> Vec<Range<usize>> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<Span> = None;
+    for (i, span) in words.spans.iter().enumerate() {
+        let span = match span {
+            Some(span) => span,
+            None => continue,
+        };
+        if let (Some(previous), Some(original)) = (previous, words.original) {
+            let gap = &original[previous.end.byte..span.start.byte];
+            let ends_sentence = gap.contains(|c| ".!?;:".contains(c));
+            let ends_paragraph = gap.matches('\n').count() >= 2;
+            if (ends_sentence || ends_paragraph) && i > start {
+                sentences.push(start..i);
+                start = i;
+            }
+        }
+        previous = Some(*span);
+    }
+    if start < words.words.len() {
+        sentences.push(start..words.words.len());
+    }
+    sentences
+}
+
+/// Get the original text of a sentence, falling back to its processed words.
+fn sentence_text(words: &Words<'_>, range: Range<usize>) -> String {
+    match (words.original, words.span(range.clone())) {
+        (Some(original), Some(span)) => original[span.bytes()].to_owned(),
+        _ => words.words[range].join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIT: &str = "Permission is hereby granted, free of charge, to any person obtaining \
+                       a copy of this software, to deal in the Software without restriction.\n\n\
+                       The above copyright notice and this permission notice shall be included \
+                       in all copies or substantial portions of the Software.\n\n\
+                       THE SOFTWARE IS PROVIDED \"AS IS\", WITHOUT WARRANTY OF ANY KIND.";
+
+    #[test]
+    fn riders_are_reported() {
+        let license = TextData::from(MIT);
+        let sample = format!(
+            "{}\n\nThe Software shall not be used for military purposes.",
+            MIT.replace("to any person", "to any natural person")
+                .replace(
+                    "THE SOFTWARE IS PROVIDED \"AS IS\", WITHOUT WARRANTY OF ANY KIND.",
+                    ""
+                )
+        );
+        let residuals = TextData::from(sample.as_str()).residuals(&license);
+
+        let kinds: Vec<(ResidualKind, &str)> = residuals
+            .iter()
+            .map(|r| (r.kind, r.text.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    ResidualKind::Altered,
+                    "Permission is hereby granted, free of charge, to any natural person \
+                     obtaining a copy of this software, to deal in the Software without \
+                     restriction"
+                ),
+                (
+                    ResidualKind::Added,
+                    "The Software shall not be used for military purposes"
+                ),
+                (
+                    ResidualKind::Removed,
+                    "THE SOFTWARE IS PROVIDED \"AS IS\", WITHOUT WARRANTY OF ANY KIND"
+                ),
+            ],
+            kinds
+        );
+
+        let added = &residuals[1];
+        assert_eq!(added.text, &sample[added.sample_span.unwrap().bytes()]);
+        assert!(added.coverage < ADDED_BELOW);
+
+        // the license alone has nothing left over
+        assert!(TextData::from(MIT).residuals(&license).is_empty());
+    }
+}
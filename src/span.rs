@@ -0,0 +1,260 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fmt, sync::Arc};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::ngram::hash_str;
+
+/// A location in the original text a `TextData` was created from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    /// 0-indexed line number.
+    pub line: usize,
+    /// 0-indexed column within the line, in chars.
+    pub column: usize,
+    /// Offset from the start of the text, in bytes.
+    pub byte: usize,
+    /// Offset from the start of the text, in chars.
+    pub char: usize,
+}
+
+/// A region of the original text a `TextData` was created from.
+///
+/// Spans start at the first non-whitespace character of their first line,
+/// and end just after the last non-whitespace character of their last line.
+///
+/// ```
+/// use askalono::TextData;
+///
+/// let text = "fn main() {}\n\n  // My First License\n  // is short\n";
+/// let span = TextData::from(text).span((1, 4)).unwrap();
+/// assert_eq!((2, 2), (span.start.line, span.start.column));
+/// assert_eq!((3, 13), (span.end.line, span.end.column));
+/// assert_eq!("// My First License\n  // is short", &text[span.bytes()]);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// Where the span starts (inclusive).
+    pub start: Position,
+    /// Where the span ends (exclusive).
+    pub end: Position,
+}
+
+impl Span {
+    /// Get the range of bytes covered by the span, to slice the original
+    /// text with.
+    pub fn bytes(&self) -> std::ops::Range<usize> {
+        self.start.byte..self.end.byte
+    }
+
+    /// Get the range of chars covered by the span.
+    pub fn chars(&self) -> std::ops::Range<usize> {
+        self.start.char..self.end.char
+    }
+
+    /// Check if the span covers nothing at all.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// The original text a `TextData` was built from, along with the span of
+/// each of its lines.
+///
+/// Both are shared between a `TextData` and all of its views, so cloning is
+/// cheap. Only the text is serialized; the line spans are found again when
+/// it's read back.
+#[derive(Clone)]
+pub(crate) struct Original {
+    text: Arc<str>,
+    lines: Arc<[Span]>,
+}
+
+impl Original {
+    pub fn new(text: &str) -> Original {
+        Original {
+            lines: line_spans(text).into(),
+            text: text.into(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn lines(&self) -> &[Span] {
+        &self.lines
+    }
+}
+
+impl fmt::Debug for Original {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.text, f)
+    }
+}
+
+impl Serialize for Original {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Original {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Original, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Ok(Original::new(&text))
+    }
+}
+
+/// Find the span of the content of each line of a text, split the same way
+/// that normalization splits it.
+pub(crate) fn line_spans(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let (mut byte, mut char) = (0, 0);
+    for (line, content) in text.split('\n').enumerate() {
+        let trimmed = content.trim_start();
+        let leading = &content[..content.len() - trimmed.len()];
+        let trimmed = trimmed.trim_end();
+
+        let column = leading.chars().count();
+        let start = Position {
+            line,
+            column,
+            byte: byte + leading.len(),
+            char: char + column,
+        };
+        let width = trimmed.chars().count();
+        let end = Position {
+            line,
+            column: column + width,
+            byte: start.byte + trimmed.len(),
+            char: start.char + width,
+        };
+        spans.push(Span { start, end });
+
+        byte += content.len() + 1;
+        char += content.chars().count() + 1;
+    }
+    spans
+}
+
+/// Find the span of each word in a text, along with a hash of the word as it
+/// would appear after preprocessing (lowercased, without punctuation).
+///
+/// This is an approximation: words that normalization rewrites, such as
+/// URLs, won't hash the same as their processed form. `locate_words` copes
+/// with that by skipping over them.
+pub(crate) fn word_spans(text: &str) -> Vec<(u64, Span)> {
+    let is_break = |c: char| c.is_whitespace() || "/\\|\u{2044}".contains(c);
+
+    let mut words = Vec::new();
+    let (mut byte, mut char) = (0, 0);
+    for (line, content) in text.split('\n').enumerate() {
+        let position = |column, offset| Position {
+            line,
+            column,
+            byte: byte + offset,
+            char: char + column,
+        };
+
+        // spans cover the first through last word character of each
+        // whitespace-separated piece, leaving out surrounding punctuation
+        let mut key = String::new();
+        let mut start = None;
+        let mut end = (0, 0);
+        let mut offset = 0;
+        for (column, c) in content.chars().chain(std::iter::once(' ')).enumerate() {
+            if is_break(c) {
+                if let Some((column, offset)) = start.take() {
+                    let span = Span {
+                        start: position(column, offset),
+                        end: position(end.0, end.1),
+                    };
+                    words.push((hash_str(&key), span));
+                    key.clear();
+                }
+            } else if c.is_alphanumeric() || c == '_' {
+                start.get_or_insert((column, offset));
+                end = (column + 1, offset + c.len_utf8());
+                key.extend(c.to_lowercase());
+            }
+            offset += c.len_utf8();
+        }
+
+        byte += content.len() + 1;
+        char += content.chars().count() + 1;
+    }
+    words
+}
+
+/// Find where each word of processed text came from, given the words of the
+/// original text from `word_spans`.
+///
+/// Preprocessing only ever drops or rewrites words, so this walks both lists
+/// in order, looking a short way ahead in the original for each processed
+/// word. Words that can't be found get `None`.
+pub(crate) fn locate_words(processed: &[&str], original: &[(u64, Span)]) -> Vec<Option<Span>> {
+    const LOOKAHEAD: usize = 64;
+
+    let mut next = 0;
+    processed
+        .iter()
+        .map(|word| {
+            let key = hash_str(word);
+            let window = &original[next.min(original.len())..];
+            let found = window
+                .iter()
+                .take(LOOKAHEAD)
+                .position(|&(candidate, _)| candidate == key)?;
+            next += found + 1;
+            Some(window[found].1)
+        })
+        .collect()
+}
+
+/// Join the spans of a range of lines, skipping blank lines at either end.
+///
+/// Returns an empty span at the start of the range if every line in it is
+/// blank, or `None` if the range is empty or out of bounds.
+pub(crate) fn join_lines(lines: &[Span], range: (usize, usize)) -> Option<Span> {
+    let lines = lines.get(range.0..range.1)?;
+    let first = lines.first()?;
+    let mut content = lines.iter().filter(|span| !span.is_empty());
+    match (content.next(), content.next_back()) {
+        (Some(start), Some(end)) => Some(Span {
+            start: start.start,
+            end: end.end,
+        }),
+        (Some(only), None) => Some(*only),
+        _ => Some(Span {
+            start: first.start,
+            end: first.start,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_count_multibyte_chars() {
+        let text = "  Straße\r\n\n\t«Crab» 🦀 ";
+        let lines = line_spans(text);
+        assert_eq!(3, lines.len());
+
+        assert_eq!("Straße", &text[lines[0].bytes()]);
+        assert_eq!((2, 8), (lines[0].start.char, lines[0].end.char));
+        assert_eq!("«Crab» 🦀", &text[lines[2].bytes()]);
+        assert_eq!((1, 9), (lines[2].start.column, lines[2].end.column));
+        assert_eq!(12, lines[2].start.char);
+
+        let joined = join_lines(&lines, (0, 3)).unwrap();
+        assert_eq!("Straße\r\n\n\t«Crab» 🦀", &text[joined.bytes()]);
+        assert!(join_lines(&lines, (1, 2)).unwrap().is_empty());
+        assert!(join_lines(&lines, (2, 2)).is_none());
+        assert!(join_lines(&lines, (2, 4)).is_none());
+    }
+}
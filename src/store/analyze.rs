@@ -1,12 +1,15 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{cmp::Ordering, fmt};
+use std::{cmp::Ordering, collections::HashSet, fmt};
+
+use log::debug;
 
 use crate::{
     license::LicenseType,
     license::TextData,
-    store::base::{LicenseEntry, Store},
+    ngram::{GramId, NgramSet, SimilarityMetric},
+    store::{base::Store, index::IndexedText},
 };
 
 /// Information about text that was compared against licenses in the store.
@@ -63,6 +66,17 @@ impl<'a> fmt::Debug for Match<'a> {
     }
 }
 
+impl<'a> From<PartialMatch<'a>> for Match<'a> {
+    fn from(m: PartialMatch<'a>) -> Match<'a> {
+        Match {
+            score: m.score,
+            name: m.name,
+            license_type: m.license_type,
+            data: m.data,
+        }
+    }
+}
+
 impl Store {
     /// Compare the given `TextData` against all licenses in the `Store`.
     ///
@@ -70,50 +84,205 @@ impl Store {
     /// Once a match is obtained, it can be optimized further; see methods on
     /// `TextData` for more information.
     pub fn analyze<'a>(&'a self, text: &TextData) -> Match<'a> {
+        self.analyze_with(text, SimilarityMetric::Dice)
+    }
+
+    /// Like `analyze`, but scoring with the given similarity metric.
+    pub(crate) fn analyze_with<'a>(
+        &'a self,
+        text: &TextData,
+        metric: SimilarityMetric,
+    ) -> Match<'a> {
+        if let Some(exact) = self.find_exact(text) {
+            return exact;
+        }
+        self.analyze_ranked(text, metric).swap_remove(0).into()
+    }
+
+    /// Look for a license, header, or alternate whose text is identical to
+    /// the given text after normalization.
+    ///
+    /// This only needs a lookup of the text's fingerprint, so it's far
+    /// quicker than a full analysis; `analyze` and `ScanStrategy` try it
+    /// first. An exact match always has a score of 1.0.
+    ///
+    /// ```
+    /// use askalono::{Store, TextData};
+    ///
+    /// let mut store = Store::new();
+    /// store.add_license("one".into(), "aaa bbb ccc ddd".into());
+    ///
+    /// let exact = store.find_exact(&TextData::from("AAA bbb\nccc ddd"));
+    /// assert_eq!("one", exact.unwrap().name);
+    /// assert!(store.find_exact(&TextData::from("aaa bbb ccc")).is_none());
+    /// ```
+    pub fn find_exact<'a>(&'a self, text: &TextData) -> Option<Match<'a>> {
+        self.find_exact_all(text).into_iter().next()
+    }
+
+    /// Like `find_exact`, but finding every license with a text identical to
+    /// the given text, each represented once as with `analyze_candidates`.
+    pub(crate) fn find_exact_all<'a>(&'a self, text: &TextData) -> Vec<Match<'a>> {
+        if text.ngrams().is_empty() {
+            return Vec::new();
+        }
+        let mut seen = HashSet::new();
+        self.index
+            .exact(text.fingerprint())
+            .into_iter()
+            .filter(|doc| seen.insert(&doc.name))
+            .map(|doc| Match {
+                score: 1.0,
+                name: &doc.name,
+                license_type: doc.kind,
+                data: self.indexed_text(doc),
+            })
+            .collect()
+    }
+
+    /// Compare the given `TextData` against all licenses in the `Store`,
+    /// returning up to `n` of the best matches in descending order of score.
+    ///
+    /// Each license appears at most once in the result, represented by
+    /// whichever of its forms (original text, header, or alternate) scored
+    /// highest. This is useful to see what else came close to the top match:
+    ///
+    /// ```
+    /// use askalono::{Store, TextData};
+    ///
+    /// let mut store = Store::new();
+    /// store.add_license("one".into(), "aaa bbb ccc ddd".into());
+    /// store.add_license("two".into(), "aaa bbb ccc eee".into());
+    /// store.add_license("three".into(), "xxx yyy zzz".into());
+    ///
+    /// let top = store.analyze_top(&TextData::from("aaa bbb ccc ddd"), 2);
+    /// assert_eq!(2, top.len());
+    /// assert_eq!("one", top[0].name);
+    /// assert_eq!("two", top[1].name);
+    /// ```
+    pub fn analyze_top<'a>(&'a self, text: &TextData, n: usize) -> Vec<Match<'a>> {
+        let mut candidates = self.analyze_candidates(text, SimilarityMetric::Dice);
+        candidates.truncate(n);
+        candidates
+    }
+
+    /// Score every license exception in the store by how much of its text
+    /// appears in the given text, sorted from best to worst.
+    pub(crate) fn analyze_exceptions<'a>(&'a self, text: &TextData) -> Vec<Match<'a>> {
+        let text = self.conform(text);
+        let mut res: Vec<Match<'a>> = self
+            .exceptions
+            .iter()
+            .map(|(name, entry)| Match {
+                score: text.ngrams().coverage_of(entry.original.ngrams()),
+                name,
+                license_type: LicenseType::Original,
+                data: &entry.original,
+            })
+            .collect();
+        res.sort_unstable_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        res
+    }
+
+    /// Rank every license in the store against the given text, keeping only
+    /// the best-scoring form of each license.
+    pub(crate) fn analyze_candidates<'a>(
+        &'a self,
+        text: &TextData,
+        metric: SimilarityMetric,
+    ) -> Vec<Match<'a>> {
+        let mut seen = HashSet::new();
+        self.analyze_ranked(text, metric)
+            .into_iter()
+            .filter(|m| seen.insert(m.name))
+            .map(Match::from)
+            .collect()
+    }
+
+    /// Score every license, header, and alternate in the store against the
+    /// given text, sorted from best to worst. If the store has an LSH index,
+    /// this may only include the texts it found to be similar. Short forms
+    /// of licenses the text doesn't name score 0.
+    ///
+    /// The store's index is used to count the n-grams each text shares with
+    /// the sample up front. That's all that's needed to score most metrics;
+    /// the rest only need a full comparison against texts with something in
+    /// common with the sample.
+    fn analyze_ranked<'a>(
+        &'a self,
+        text: &TextData,
+        metric: SimilarityMetric,
+    ) -> Vec<PartialMatch<'a>> {
         let mut res: Vec<PartialMatch<'a>>;
 
-        let analyze_fold =
-            |mut acc: Vec<PartialMatch<'a>>, (name, data): (&'a String, &'a LicenseEntry)| {
-                acc.push(PartialMatch {
-                    score: data.original.match_score(text),
-                    name,
-                    license_type: LicenseType::Original,
-                    data: &data.original,
-                });
-                data.alternates.iter().for_each(|alt| {
-                    acc.push(PartialMatch {
-                        score: alt.match_score(text),
-                        name,
-                        license_type: LicenseType::Alternate,
-                        data: alt,
-                    })
-                });
-                data.headers.iter().for_each(|head| {
-                    acc.push(PartialMatch {
-                        score: head.match_score(text),
-                        name,
-                        license_type: LicenseType::Header,
-                        data: head,
-                    })
-                });
-                acc
+        let text = self.conform(text);
+        let sample = text.ngrams();
+        let idf = |gram: GramId| self.index.idf(gram);
+        let sample_words = words(&text);
+        let unnamed = |doc: &IndexedText| {
+            doc.kind == LicenseType::Short && !self.is_named(&doc.name, &sample_words)
+        };
+
+        // with an LSH index, only score the texts it suggests, provided one
+        // of them turns out to be a good enough match
+        if let Some(lsh) = self.index.lsh() {
+            let mut shortlist: Vec<PartialMatch<'a>> = lsh
+                .query(sample)
+                .into_iter()
+                .map(|doc| {
+                    let doc = &self.index.docs()[doc as usize];
+                    let data = self.indexed_text(doc);
+                    PartialMatch {
+                        score: match unnamed(doc) {
+                            true => 0f32,
+                            false => data.ngrams().similarity(sample, metric, &idf),
+                        },
+                        name: &doc.name,
+                        license_type: doc.kind,
+                        data,
+                    }
+                })
+                .collect();
+            shortlist.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
+
+            match shortlist.first() {
+                Some(best) if best.score >= lsh.config().fallback_below => return shortlist,
+                _ => debug!(
+                    "No good match among {} LSH candidates; scoring everything",
+                    shortlist.len()
+                ),
+            }
+        }
+
+        let overlaps = self.index.overlaps(sample);
+
+        let analyze_doc = |(doc, overlap): (&'a IndexedText, u32)| {
+            let data = self.indexed_text(doc);
+            let score = if overlap == 0 || doc.ngram_size != sample.ngram_size() || unnamed(doc) {
+                0f32
+            } else {
+                NgramSet::overlap_score(metric, overlap, doc.size, sample.len())
+                    .unwrap_or_else(|| data.ngrams().similarity(sample, metric, &idf))
             };
+            PartialMatch {
+                score,
+                name: &doc.name,
+                license_type: doc.kind,
+                data,
+            }
+        };
 
         // parallel analysis
         #[cfg(not(target_arch = "wasm32"))]
         {
             use rayon::prelude::*;
             res = self
-                .licenses
+                .index
+                .docs()
                 .par_iter()
-                .fold(Vec::new, analyze_fold)
-                .reduce(
-                    Vec::new,
-                    |mut a: Vec<PartialMatch<'a>>, b: Vec<PartialMatch<'a>>| {
-                        a.extend(b);
-                        a
-                    },
-                );
+                .zip(overlaps)
+                .map(analyze_doc)
+                .collect();
             res.par_sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
         }
 
@@ -121,20 +290,181 @@ impl Store {
         #[cfg(target_arch = "wasm32")]
         {
             res = self
-                .licenses
+                .index
+                .docs()
                 .iter()
-                // len of licenses isn't strictly correct, but it'll do
-                .fold(Vec::with_capacity(self.licenses.len()), analyze_fold);
+                .zip(overlaps)
+                .map(analyze_doc)
+                .collect();
             res.sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
         }
 
-        let m = &res[0];
+        res
+    }
 
-        Match {
-            score: m.score,
-            name: m.name,
-            license_type: m.license_type,
-            data: m.data,
+    /// Check whether a text, given as its set of words, names a license: all
+    /// the words of its id, an alias, or its full name appear in it.
+    fn is_named(&self, name: &str, sample: &HashSet<String>) -> bool {
+        let entry = &self.licenses[name];
+        let full_name = entry.metadata.as_ref().map(|m| m.full_name.as_str());
+        std::iter::once(name)
+            .chain(entry.aliases.iter().map(String::as_str))
+            .chain(full_name)
+            .any(|id| words_of(id).all(|word| sample.contains(&word)))
+    }
+}
+
+/// The lowercased words of a text's lines, split at anything but letters and
+/// digits, so that "BSD-2-Clause" and "2-clause BSD" have the same words.
+fn words(text: &TextData) -> HashSet<String> {
+    match text.text_processed() {
+        Some(_) => text
+            .lines()
+            .iter()
+            .flat_map(|line| words_of(line))
+            .collect(),
+        None => HashSet::new(),
+    }
+}
+
+fn words_of(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_matches_are_unique_by_name() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa\nbbbbb\nccccc".into());
+        store
+            .add_variant("license-1", LicenseType::Header, "aaaaa bbbbb".into())
+            .unwrap();
+        store.add_license("license-2".into(), "aaaaa bbbbb ddddd".into());
+
+        let top = store.analyze_top(&"aaaaa bbbbb ccccc".into(), 5);
+        assert_eq!(2, top.len());
+        assert_eq!("license-1", top[0].name);
+        assert_eq!(LicenseType::Original, top[0].license_type);
+        assert_eq!("license-2", top[1].name);
+        assert!(top[0].score >= top[1].score);
+    }
+
+    #[test]
+    fn exact_matches_by_fingerprint() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc ddddd".into());
+        let header = TextData::from("aaaaa bbbbb").without_text();
+        store
+            .add_variant("license-1", LicenseType::Header, header)
+            .unwrap();
+
+        let exact = store.find_exact(&"Aaaaa  bbbbb".into()).unwrap();
+        assert_eq!(LicenseType::Header, exact.license_type);
+        assert_eq!(1f32, exact.score);
+
+        // a view of a larger text has its own fingerprint
+        let text = TextData::from("zzzzz\naaaaa bbbbb\nccccc ddddd");
+        assert!(store.find_exact(&text).is_none());
+        let view = text.with_view(1, 3);
+        assert_eq!("license-1", store.find_exact(&view).unwrap().name);
+        assert_eq!(LicenseType::Original, store.analyze(&view).license_type);
+
+        assert!(store.find_exact(&"".into()).is_none());
+
+        // every license with the same text is found, each once
+        store.add_license("license-0".into(), "aaaaa bbbbb".into());
+        let exact = store.find_exact_all(&"aaaaa bbbbb".into());
+        let found: Vec<(&str, LicenseType)> =
+            exact.iter().map(|m| (m.name, m.license_type)).collect();
+        assert_eq!(
+            vec![
+                ("license-0", LicenseType::Original),
+                ("license-1", LicenseType::Header)
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn lsh_shortlists_or_falls_back() {
+        let text = |range: std::ops::Range<usize>| {
+            let words: Vec<String> = range.map(|i| format!("w{}", i)).collect();
+            words.join(" ")
+        };
+        let mut store = Store::new();
+        for i in 0..20 {
+            store.add_license(format!("license-{}", i), text(i * 100..i * 100 + 80).into());
         }
+        store.enable_lsh(crate::LshConfig::default()).unwrap();
+        assert!(store.lsh_config().is_some());
+
+        // a near copy is found through the index, skipping the rest
+        let sample = TextData::from(text(505..580).as_str());
+        let ranked = store.analyze_ranked(&sample, SimilarityMetric::Dice);
+        assert_eq!("license-5", ranked[0].name);
+        assert!(ranked.len() < 20);
+
+        // licenses added later are indexed too
+        store.add_license("late".into(), text(5000..5080).into());
+        assert_eq!("late", store.analyze(&text(5001..5080).into()).name);
+
+        // half of a license isn't similar enough to be found, so everything
+        // is scored to find it
+        let sample = TextData::from(text(700..740).as_str());
+        let ranked = store.analyze_ranked(&sample, SimilarityMetric::Dice);
+        assert_eq!("license-7", ranked[0].name);
+        assert_eq!(21, ranked.len());
+
+        store.disable_lsh();
+        assert!(store.lsh_config().is_none());
+    }
+
+    #[test]
+    fn samples_use_store_pipeline() {
+        use crate::{
+            ngram::NgramSize,
+            preproc::{Pipeline, PipelineStage},
+        };
+
+        // without the title line removed, the first line counts
+        let pipeline = Pipeline::builder()
+            .remove(PipelineStage::Aggressive, "remove_title_line")
+            .build()
+            .unwrap();
+        let mut store = Store::with_pipeline(pipeline, NgramSize::default());
+        let license = "The Example License\n\naaaaa bbbbb ccccc ddddd";
+        store.add_license("license-1".into(), license.into());
+
+        let sample = TextData::from(license);
+        assert_eq!(Some("aaaaa bbbbb ccccc ddddd"), sample.text_processed());
+        let matched = store.analyze(&sample);
+        assert_eq!(1f32, matched.score);
+        assert_eq!(
+            Some("the example license aaaaa bbbbb ccccc ddddd"),
+            matched.data.text_processed()
+        );
+    }
+
+    #[test]
+    fn weighting_prefers_rare_grams() {
+        let mut store = Store::new();
+        store.add_license("common".into(), "of the software of the work".into());
+        store.add_license("rare".into(), "copyleft clause applies here".into());
+        store.add_license("other-1".into(), "terms of the license".into());
+        store.add_license("other-2".into(), "use of the program".into());
+        store.add_license("other-3".into(), "copies of the code".into());
+
+        let text = TextData::from("of the copyleft clause");
+        let cosine = store.analyze_with(&text, SimilarityMetric::Cosine);
+        let tfidf = store.analyze_with(&text, SimilarityMetric::TfIdfCosine);
+        let weighted = store.analyze_with(&text, SimilarityMetric::WeightedDice);
+        assert_eq!("common", cosine.name);
+        assert_eq!("rare", tfidf.name);
+        assert_eq!("rare", weighted.name);
     }
 }
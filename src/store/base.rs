@@ -1,19 +1,50 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 use failure::{format_err, Error};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
-use crate::{license::LicenseType, license::TextData};
+use crate::{
+    license::LicenseType,
+    license::TextData,
+    ngram::{NgramSize, Vocabulary},
+    preproc::Pipeline,
+    store::index::Index,
+    template::Template,
+};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct LicenseEntry {
     pub original: TextData,
     pub aliases: Vec<String>,
     pub headers: Vec<TextData>,
     pub alternates: Vec<TextData>,
+    pub short_forms: Vec<TextData>,
+    pub metadata: Option<LicenseMetadata>,
+    /// Metadata of aliases that are licenses in their own right, such as
+    /// `GPL-2.0-or-later` folded into `GPL-2.0-only` for having the same text.
+    pub alias_metadata: HashMap<String, LicenseMetadata>,
+    pub template: Option<Template>,
+}
+
+/// Descriptive information about a license, as published in the SPDX license
+/// list. None of it is used for matching.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LicenseMetadata {
+    /// The human-readable name of the license, such as "MIT License".
+    pub full_name: String,
+    /// Whether the Open Source Initiative has approved the license.
+    pub osi_approved: bool,
+    /// Whether the Free Software Foundation considers the license free
+    /// ("libre"). SPDX only lists this for some licenses.
+    pub fsf_libre: Option<bool>,
+    /// URLs where the license can be found elsewhere.
+    pub see_also: Vec<String>,
+    /// The license's reference number in the SPDX license list.
+    pub reference_number: Option<u32>,
 }
 
 /// A representation of a collection of known licenses.
@@ -39,6 +70,16 @@ pub(crate) struct LicenseEntry {
 #[derive(Default, Serialize, Deserialize)]
 pub struct Store {
     pub(crate) licenses: HashMap<String, LicenseEntry>,
+    pub(crate) index: Index,
+    pub(crate) vocabulary: Vocabulary,
+    pub(crate) ngram_size: NgramSize,
+    pub(crate) pipeline: Pipeline,
+    /// License exceptions, such as `Classpath-exception-2.0`. These are kept
+    /// apart from licenses, as they're only ever found alongside one.
+    pub(crate) exceptions: HashMap<String, LicenseEntry>,
+    /// Deprecated SPDX ids, such as `GPL-2.0+`, and the ids that replace
+    /// them. Each is also an alias of the license its successor refers to.
+    pub(crate) deprecated: HashMap<String, String>,
 }
 
 impl LicenseEntry {
@@ -47,9 +88,21 @@ impl LicenseEntry {
             original,
             aliases: Vec::new(),
             alternates: Vec::new(),
+            short_forms: Vec::new(),
             headers: Vec::new(),
+            metadata: None,
+            alias_metadata: HashMap::new(),
+            template: None,
         }
     }
+
+    /// Iterate over the original text and all variants of the license.
+    pub fn texts_mut(&mut self) -> impl Iterator<Item = &mut TextData> {
+        std::iter::once(&mut self.original)
+            .chain(self.alternates.iter_mut())
+            .chain(self.short_forms.iter_mut())
+            .chain(self.headers.iter_mut())
+    }
 }
 
 impl Store {
@@ -58,8 +111,84 @@ impl Store {
     /// More often, you probably want to use `from_cache` instead of creating
     /// an empty store.
     pub fn new() -> Store {
+        Store::with_ngram_size(NgramSize::default())
+    }
+
+    /// Create a new `Store` that matches using n-grams of the given size.
+    ///
+    /// Licenses added to the store are converted to this size if they were
+    /// built differently, as are texts given to `analyze`. The size is saved
+    /// along with the rest of the store in a cache.
+    pub fn with_ngram_size(ngram_size: NgramSize) -> Store {
+        Store::with_pipeline(Pipeline::standard(), ngram_size)
+    }
+
+    /// Create a new `Store` that preprocesses texts with a custom pipeline,
+    /// and matches using n-grams of the given size.
+    ///
+    /// Like the n-gram size, licenses and samples are re-processed with this
+    /// pipeline if they were built with another. The names of the pipeline's
+    /// steps are saved in a cache; if any of them are custom, load the cache
+    /// with `from_cache_with_pipeline`.
+    pub fn with_pipeline(pipeline: Pipeline, ngram_size: NgramSize) -> Store {
         Store {
             licenses: HashMap::new(),
+            index: Index::default(),
+            vocabulary: Vocabulary::default(),
+            ngram_size,
+            pipeline,
+            exceptions: HashMap::new(),
+            deprecated: HashMap::new(),
+        }
+    }
+
+    /// Get the size of n-grams this store matches with.
+    pub fn ngram_size(&self) -> NgramSize {
+        self.ngram_size
+    }
+
+    /// Get the preprocessing pipeline this store was built with.
+    pub fn pipeline(&self) -> &Pipeline {
+        &self.pipeline
+    }
+
+    /// Convert a text to this store's preprocessing and n-gram size, if it
+    /// doesn't already use them.
+    ///
+    /// Texts without their text can't be converted, and are returned as-is;
+    /// they'll never match anything in the store.
+    pub(crate) fn conform<'t>(&self, data: &'t TextData) -> Cow<'t, TextData> {
+        if data.pipeline().id() != self.pipeline.id() {
+            if let Some(text) = data.original() {
+                let converted = TextData::with_pipeline(text, &self.pipeline, self.ngram_size);
+                let (start, end) = data.lines_view();
+                if (start, end) == converted.lines_view() {
+                    return Cow::Owned(converted);
+                }
+                return Cow::Owned(converted.with_view(start, end));
+            }
+            warn!("Text was preprocessed differently from the store, and can't be converted");
+        }
+        if data.ngram_size() == self.ngram_size {
+            return Cow::Borrowed(data);
+        }
+        if data.text_processed().is_none() {
+            warn!(
+                "Text uses {}-grams rather than the store's {}-grams, and can't be converted",
+                data.ngram_size(),
+                self.ngram_size
+            );
+            return Cow::Borrowed(data);
+        }
+        Cow::Owned(data.to_ngram_size(self.ngram_size))
+    }
+
+    /// Like `conform`, but taking ownership to avoid a copy when no
+    /// conversion is needed.
+    fn conform_owned(&self, data: TextData) -> TextData {
+        match self.conform(&data) {
+            Cow::Borrowed(_) => data,
+            Cow::Owned(converted) => converted,
         }
     }
 
@@ -91,12 +220,18 @@ impl Store {
     /// If the license with the given name already existed, it and all of its
     /// variants will be replaced.
     pub fn add_license(&mut self, name: String, data: TextData) {
+        let data = self.conform_owned(data);
+        self.index.add(&name, LicenseType::Original, 0, &data);
+        data.add_to_vocabulary(&mut self.vocabulary);
         let entry = LicenseEntry::new(data);
-        self.licenses.insert(name, entry);
+        if self.licenses.insert(name, entry).is_some() {
+            // the old entry's texts are still indexed
+            self.rebuild_index();
+        }
     }
 
-    /// Add a variant (a header or alternate formatting) of a given license to
-    /// the store.
+    /// Add a variant (a header, alternate formatting, or short form) of a
+    /// given license to the store.
     ///
     /// The license must already exist. This function cannot be used to replace
     /// the original/canonical text of the license.
@@ -106,15 +241,24 @@ impl Store {
         variant: LicenseType,
         data: TextData,
     ) -> Result<(), Error> {
+        let data = self.conform_owned(data);
         let entry = self
             .licenses
             .get_mut(name)
             .ok_or_else(|| format_err!("license {} not present in store", name))?;
+        let index = &mut self.index;
+        data.add_to_vocabulary(&mut self.vocabulary);
         match variant {
             LicenseType::Alternate => {
+                index.add(name, variant, entry.alternates.len(), &data);
                 entry.alternates.push(data);
             }
+            LicenseType::Short => {
+                index.add(name, variant, entry.short_forms.len(), &data);
+                entry.short_forms.push(data);
+            }
             LicenseType::Header => {
+                index.add(name, variant, entry.headers.len(), &data);
                 entry.headers.push(data);
             }
             _ => {
@@ -133,6 +277,177 @@ impl Store {
         Ok(&entry.aliases)
     }
 
+    /// Get the standard headers of a given license.
+    pub fn headers(&self, name: &str) -> Result<&[TextData], Error> {
+        let entry = self
+            .licenses
+            .get(name)
+            .ok_or_else(|| format_err!("license {} not present in store", name))?;
+        Ok(&entry.headers)
+    }
+
+    /// Get the alternate forms of a given license, such as reflowed texts or
+    /// texts with a preamble. See `load_variants`.
+    pub fn alternates(&self, name: &str) -> Result<&[TextData], Error> {
+        let entry = self
+            .licenses
+            .get(name)
+            .ok_or_else(|| format_err!("license {} not present in store", name))?;
+        Ok(&entry.alternates)
+    }
+
+    /// Get the short forms of a given license. See `load_variants`.
+    pub fn short_forms(&self, name: &str) -> Result<&[TextData], Error> {
+        let entry = self
+            .licenses
+            .get(name)
+            .ok_or_else(|| format_err!("license {} not present in store", name))?;
+        Ok(&entry.short_forms)
+    }
+
+    /// Add a license exception to the store.
+    ///
+    /// Exceptions aren't matched against texts on their own, but looked for
+    /// in texts alongside the licenses found there. See
+    /// `ScanResult.exceptions`.
+    pub fn add_exception(&mut self, name: String, data: TextData) {
+        let data = self.conform_owned(data);
+        data.add_to_vocabulary(&mut self.vocabulary);
+        self.exceptions.insert(name, LicenseEntry::new(data));
+    }
+
+    /// Get all license exceptions by name via iterator.
+    pub fn exceptions(&self) -> impl Iterator<Item = &String> + '_ {
+        self.exceptions.keys()
+    }
+
+    /// Get a license exception's TextData by name.
+    pub fn get_exception(&self, name: &str) -> Option<&TextData> {
+        Some(&self.exceptions.get(name)?.original)
+    }
+
+    /// Find the license exception an SPDX id refers to, the same way as
+    /// `resolve_id` does for licenses.
+    pub fn resolve_exception_id(&self, id: &str) -> Option<&str> {
+        resolve(&self.exceptions, id)
+    }
+
+    /// Find the license an SPDX id refers to: one with that name or alias,
+    /// compared case-insensitively as SPDX ids are. An id ending in `+` also
+    /// resolves to the license without it, if there's no exact match.
+    pub fn resolve_id(&self, id: &str) -> Option<&str> {
+        resolve(&self.licenses, id).or_else(|| resolve(&self.licenses, id.strip_suffix('+')?))
+    }
+
+    /// Get the descriptive metadata of a license, if it has any. Licenses
+    /// loaded with `load_spdx` have the SPDX license list's.
+    ///
+    /// The id may also be an alias, matched as with `resolve_id`. Aliases that
+    /// are licenses in their own right, like `GPL-2.0-or-later` for
+    /// `GPL-2.0-only`, have their own metadata; others share their license's.
+    pub fn metadata(&self, id: &str) -> Option<&LicenseMetadata> {
+        if let Some(entry) = self.licenses.get(id) {
+            return entry.metadata.as_ref();
+        }
+        let entry = &self.licenses[self.resolve_id(id)?];
+        let own = entry
+            .alias_metadata
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(id));
+        match own {
+            Some((_, metadata)) => Some(metadata),
+            None => entry.metadata.as_ref(),
+        }
+    }
+
+    /// Set the descriptive metadata of a license, or of one of its aliases.
+    pub fn set_metadata(&mut self, id: &str, metadata: LicenseMetadata) -> Result<(), Error> {
+        let name = resolve(&self.licenses, id)
+            .ok_or_else(|| format_err!("license {} not present in store", id))?
+            .to_owned();
+        let entry = self.licenses.get_mut(&name).unwrap();
+        match name.eq_ignore_ascii_case(id) {
+            true => entry.metadata = Some(metadata),
+            false => {
+                let alias = entry.aliases.iter().find(|a| a.eq_ignore_ascii_case(id));
+                let alias = alias.map_or(id, String::as_str).to_owned();
+                entry.alias_metadata.insert(alias, metadata);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the SPDX template of a license, if it has one. See
+    /// `ScanStrategy::match_templates`.
+    pub fn template(&self, name: &str) -> Option<&Template> {
+        self.licenses.get(name)?.template.as_ref()
+    }
+
+    /// Set the SPDX template of a license, for checking whether texts are an
+    /// exact match for it.
+    pub fn set_template(&mut self, name: &str, template: Template) -> Result<(), Error> {
+        let entry = self
+            .licenses
+            .get_mut(name)
+            .ok_or_else(|| format_err!("license {} not present in store", name))?;
+        entry.template = Some(template);
+        Ok(())
+    }
+
+    /// Add a deprecated SPDX id, such as `GPL-2.0+`, along with the id that
+    /// replaces it, such as `GPL-2.0-or-later`. Ids for a license with an
+    /// exception, such as `GPL-2.0-with-classpath-exception`, are replaced by
+    /// a `WITH` expression: `GPL-2.0-only WITH Classpath-exception-2.0`.
+    ///
+    /// The successor's license must be the name or alias of a license in the
+    /// store, and its exception, if any, an exception in the store. The
+    /// deprecated id becomes another alias of that license, so it still
+    /// resolves; see `successor` to find what replaces it.
+    pub fn add_deprecated(&mut self, id: String, successor: &str) -> Result<(), Error> {
+        let (license, exception) = split_with(successor);
+        if let Some(exception) = exception {
+            resolve(&self.exceptions, exception)
+                .ok_or_else(|| format_err!("exception {} not present in store", exception))?;
+        }
+        let name = resolve(&self.licenses, license)
+            .ok_or_else(|| format_err!("license {} not present in store", license))?
+            .to_owned();
+        let entry = self.licenses.get_mut(&name).unwrap();
+        if !entry.aliases.contains(&id) {
+            entry.aliases.push(id.clone());
+        }
+        self.deprecated.insert(id, successor.to_owned());
+        Ok(())
+    }
+
+    /// Get the id that replaces a deprecated SPDX id, compared
+    /// case-insensitively. Ids that aren't deprecated have no successor.
+    ///
+    /// This is a license expression when the deprecated id stood for a
+    /// license with an exception, as in `GPL-2.0-only WITH
+    /// Classpath-exception-2.0`.
+    pub fn successor(&self, id: &str) -> Option<&str> {
+        self.deprecated
+            .iter()
+            .find(|(deprecated, _)| deprecated.eq_ignore_ascii_case(id))
+            .map(|(_, successor)| successor.as_str())
+    }
+
+    /// Get the aliases of a license that are deprecated SPDX ids.
+    pub fn deprecated_aliases(&self, name: &str) -> Vec<&str> {
+        self.licenses
+            .get(name)
+            .map(|entry| {
+                entry
+                    .aliases
+                    .iter()
+                    .filter(|alias| self.deprecated.contains_key(*alias))
+                    .map(String::as_str)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     /// Set the list of aliases for a given license.
     pub fn set_aliases(&mut self, name: &str, aliases: Vec<String>) -> Result<(), Error> {
         let entry = self
@@ -143,3 +458,30 @@ impl Store {
         Ok(())
     }
 }
+
+/// Split a deprecated id's successor into its license and its exception, if
+/// it has one.
+pub(crate) fn split_with(successor: &str) -> (&str, Option<&str>) {
+    match successor.find(" WITH ") {
+        Some(i) => (&successor[..i], Some(&successor[i + 6..])),
+        None => (successor, None),
+    }
+}
+
+/// Find an entry by exact name, or failing that, by name or alias ignoring
+/// case.
+pub(crate) fn resolve<'a>(entries: &'a HashMap<String, LicenseEntry>, id: &str) -> Option<&'a str> {
+    if let Some((name, _)) = entries.get_key_value(id) {
+        return Some(name.as_str());
+    }
+    entries
+        .iter()
+        .find(|(name, entry)| {
+            name.eq_ignore_ascii_case(id)
+                || entry
+                    .aliases
+                    .iter()
+                    .any(|alias| alias.eq_ignore_ascii_case(id))
+        })
+        .map(|(name, _)| name.as_str())
+}
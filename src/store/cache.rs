@@ -1,16 +1,182 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{io::copy, io::prelude::*};
+use std::{collections::HashMap, io::copy, io::prelude::*};
 
-use failure::Error;
+use failure::{format_err, Error};
 use log::info;
 use rmp_serde::Serializer;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::store::base::Store;
+use crate::{
+    ngram::{gram_id_str, GramId, NgramSize, Vocabulary},
+    preproc::{Pipeline, PipelineSpec},
+    store::{
+        base::{LicenseEntry, Store},
+        index::{CachedIndex, Index},
+    },
+};
 
-const CACHE_VERSION: &[u8] = b"askalono-04";
+/// Bump this whenever `CacheData`, or anything it holds, changes shape, so
+/// that old caches are rejected up front rather than failing to decode.
+const CACHE_VERSION: &[u8] = b"askalono-06";
+
+/// The on-disk form of a `Store`.
+///
+/// N-gram IDs are hashes, which take the full 8 bytes each to serialize, and
+/// a store holds a great many of them. In the cache they're instead numbered
+/// from zero, with the text of each n-gram stored once in `vocabulary` and
+/// hashed again on load.
+#[derive(Serialize, Deserialize)]
+struct CacheData {
+    /// The n-gram size the store was built with, so that samples can be
+    /// broken up the same way.
+    ngram_size: NgramSize,
+    /// The names of the preprocessing steps the store was built with.
+    pipeline: PipelineSpec,
+    /// N-gram text, in order of cache ID.
+    vocabulary: Vec<String>,
+    /// Hashes of n-grams whose text isn't known, numbered after `vocabulary`.
+    unnamed: Vec<GramId>,
+    licenses: HashMap<String, LicenseEntry>,
+    exceptions: HashMap<String, LicenseEntry>,
+    deprecated: HashMap<String, String>,
+    index: CachedIndex,
+}
+
+impl CacheData {
+    fn new(store: &Store) -> CacheData {
+        let mut licenses = store.licenses.clone();
+        let mut exceptions = store.exceptions.clone();
+
+        let mut vocabulary: Vec<(GramId, &str)> = store.vocabulary.iter().collect();
+        vocabulary.sort_unstable_by_key(|&(_, gram)| gram);
+        let mut ids: HashMap<GramId, GramId> = vocabulary
+            .iter()
+            .enumerate()
+            .map(|(i, &(id, _))| (id, i as GramId))
+            .collect();
+
+        let mut unnamed = Vec::new();
+        for data in licenses
+            .values_mut()
+            .chain(exceptions.values_mut())
+            .flat_map(LicenseEntry::texts_mut)
+        {
+            for &(id, _) in data.ngrams() {
+                let next = ids.len() as GramId;
+                ids.entry(id).or_insert_with(|| {
+                    unnamed.push(id);
+                    next
+                });
+            }
+        }
+
+        let to_cache = |id| ids[&id];
+        for data in licenses
+            .values_mut()
+            .chain(exceptions.values_mut())
+            .flat_map(LicenseEntry::texts_mut)
+        {
+            data.ngrams_mut().remap(&to_cache);
+        }
+        let index = store
+            .index
+            .to_cached(&|id| to_cache(id) as usize, ids.len());
+
+        CacheData {
+            ngram_size: store.ngram_size,
+            pipeline: store.pipeline.spec(),
+            vocabulary: vocabulary
+                .into_iter()
+                .map(|(_, gram)| gram.to_owned())
+                .collect(),
+            unnamed,
+            licenses,
+            exceptions,
+            deprecated: store.deprecated.clone(),
+            index,
+        }
+    }
+
+    fn into_store(self, pipeline: Option<&Pipeline>) -> Result<Store, Error> {
+        let CacheData {
+            ngram_size,
+            pipeline: spec,
+            vocabulary,
+            unnamed,
+            mut licenses,
+            mut exceptions,
+            deprecated,
+            index,
+        } = self;
+
+        let mut store_vocabulary = Vocabulary::default();
+        let mut ids: Vec<GramId> = Vec::with_capacity(vocabulary.len() + unnamed.len());
+        for gram in vocabulary {
+            let id = gram_id_str(&gram);
+            ids.push(id);
+            store_vocabulary.insert(id, gram);
+        }
+        ids.extend(unnamed);
+
+        // a corrupt cache could reference IDs that don't exist; check up
+        // front rather than panicking while remapping
+        let known = ids.len() as GramId;
+        let valid = licenses
+            .values_mut()
+            .chain(exceptions.values_mut())
+            .flat_map(LicenseEntry::texts_mut)
+            .all(|data| data.ngrams().into_iter().all(|&(id, _)| id < known));
+        if !valid {
+            return Err(format_err!("cache references unknown n-grams"));
+        }
+
+        let pipeline = Pipeline::resolve(&spec, pipeline)?;
+        let from_cache = |id| ids[id as usize];
+        for data in licenses
+            .values_mut()
+            .chain(exceptions.values_mut())
+            .flat_map(LicenseEntry::texts_mut)
+        {
+            data.ngrams_mut().remap(&from_cache);
+            data.set_pipeline(&pipeline);
+        }
+
+        let index = Index::from_cached(index, &ids)?;
+        index.check_texts(&licenses)?;
+
+        Ok(Store {
+            licenses,
+            index,
+            vocabulary: store_vocabulary,
+            ngram_size,
+            pipeline,
+            exceptions,
+            deprecated,
+        })
+    }
+}
+
+/// Check a cache's header, then decompress and decode it.
+fn read_cache<R>(mut readable: R) -> Result<CacheData, Error>
+where
+    R: Read + Sized,
+{
+    let mut header = [0u8; 11];
+    readable.read_exact(&mut header)?;
+
+    if header != CACHE_VERSION {
+        failure::bail!("cache version mismatch");
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    let dec = zstd::Decoder::new(readable)?;
+    #[cfg(feature = "gzip")]
+    let dec = flate2::read::GzDecoder::new(readable);
+
+    Ok(rmp_serde::decode::from_read(dec)?)
+}
 
 impl Store {
     /// Create a store from a cache file.
@@ -20,24 +186,24 @@ impl Store {
     /// the full SPDX set from disk in 200-300 ms. The cache will be
     /// sanity-checked to ensure it was generated with a similar version of
     /// askalono.
-    pub fn from_cache<R>(mut readable: R) -> Result<Store, Error>
+    pub fn from_cache<R>(readable: R) -> Result<Store, Error>
     where
         R: Read + Sized,
     {
-        let mut header = [0u8; 11];
-        readable.read_exact(&mut header)?;
-
-        if header != CACHE_VERSION {
-            failure::bail!("cache version mismatch");
-        }
-
-        #[cfg(not(feature = "gzip"))]
-        let dec = zstd::Decoder::new(readable)?;
-        #[cfg(feature = "gzip")]
-        let dec = flate2::read::GzDecoder::new(readable);
+        read_cache(readable)?.into_store(None)
+    }
 
-        let store = rmp_serde::decode::from_read(dec)?;
-        Ok(store)
+    /// Create a store from a cache file that was built with a custom
+    /// preprocessing pipeline.
+    ///
+    /// The pipeline must have the same steps, in the same order, as the one
+    /// the store was built with. `from_cache` can only load caches whose
+    /// pipeline is made up of askalono's standard steps.
+    pub fn from_cache_with_pipeline<R>(readable: R, pipeline: &Pipeline) -> Result<Store, Error>
+    where
+        R: Read + Sized,
+    {
+        read_cache(readable)?.into_store(Some(pipeline))
     }
 
     /// Serialize the current store.
@@ -52,7 +218,7 @@ impl Store {
             // This currently sits around 3.7MiB, so go up to 4 to fit comfortably
             let mut buf = Vec::with_capacity(4 * 1024 * 1024);
             let mut serializer = Serializer::new(&mut buf);
-            self.serialize(&mut serializer)?;
+            CacheData::new(self).serialize(&mut serializer)?;
             buf
         };
 
@@ -71,3 +237,205 @@ impl Store {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::LicenseMetadata, template::Template};
+
+    #[test]
+    fn round_trip_keeps_index() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc".into());
+        store.add_license("license-2".into(), "aaaaa bbbbb ddddd".into());
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+
+        assert_eq!(2, loaded.len());
+        for gram in &["aaaaa bbbbb", "bbbbb ccccc", "unseen gram"] {
+            let id = gram_id_str(gram);
+            assert_eq!(store.index.idf(id), loaded.index.idf(id));
+        }
+        let exact = loaded.find_exact(&"aaaaa bbbbb ddddd".into());
+        assert_eq!("license-2", exact.expect("fingerprint is kept").name);
+    }
+
+    #[test]
+    fn round_trip_keeps_ngram_size() {
+        let mut store = Store::with_ngram_size(NgramSize::Fixed(3));
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc ddddd".into());
+        store.add_license("license-2".into(), "aaaaa bbbbb ccccc eeeee".into());
+        let lsh = crate::LshConfig {
+            bands: 4,
+            rows: 2,
+            fallback_below: 0.5,
+        };
+        store.enable_lsh(lsh).unwrap();
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+        assert_eq!(NgramSize::Fixed(3), loaded.ngram_size());
+        assert_eq!(Some(lsh), loaded.lsh_config());
+
+        // samples are built with bigrams, and converted to match
+        let matched = loaded.analyze(&"aaaaa bbbbb ccccc ddddd".into());
+        assert_eq!("license-1", matched.name);
+        assert_eq!(1f32, matched.score);
+        assert_eq!(NgramSize::Fixed(3), matched.data.ngram_size());
+    }
+
+    #[test]
+    fn round_trip_keeps_pipeline() {
+        use crate::preproc::{PipelineStage, Preprocessor};
+        use std::borrow::Cow;
+
+        struct DropVendor;
+        impl Preprocessor for DropVendor {
+            fn name(&self) -> &str {
+                "drop_vendor"
+            }
+            fn apply<'a>(&self, text: Cow<'a, str>) -> Cow<'a, str> {
+                text.replace("ACME", "").into()
+            }
+        }
+
+        let pipeline = Pipeline::builder()
+            .insert_before(PipelineStage::Aggressive, "lowercaseify", DropVendor)
+            .build()
+            .unwrap();
+        let mut store = Store::with_pipeline(pipeline.clone(), NgramSize::default());
+        store.add_license("license-1".into(), "aaaaa ACME bbbbb ccccc".into());
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+
+        // the custom step can't be found without the pipeline it came from
+        assert!(Store::from_cache(buf.as_slice()).is_err());
+        let other = Pipeline::builder().build().unwrap();
+        assert!(Store::from_cache_with_pipeline(buf.as_slice(), &other).is_err());
+
+        let loaded = Store::from_cache_with_pipeline(buf.as_slice(), &pipeline).unwrap();
+        assert_eq!(pipeline, *loaded.pipeline());
+        let matched = loaded.analyze(&"aaaaa bbbbb ccccc".into());
+        assert_eq!(1f32, matched.score);
+
+        // pipelines of standard steps need no help
+        let pipeline = Pipeline::builder()
+            .remove(PipelineStage::Aggressive, "remove_title_line")
+            .build()
+            .unwrap();
+        let store = Store::with_pipeline(pipeline.clone(), NgramSize::default());
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        assert_eq!(
+            pipeline,
+            *Store::from_cache(buf.as_slice()).unwrap().pipeline()
+        );
+    }
+
+    #[test]
+    fn round_trip_keeps_exceptions() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc".into());
+        store.add_exception("exception-1".into(), "ddddd eeeee fffff".into());
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+
+        let exception = loaded.get_exception("exception-1").unwrap();
+        let original = store.get_exception("exception-1").unwrap();
+        assert!(exception.eq_data(original));
+        let found = loaded.analyze_exceptions(&"aaaaa ddddd eeeee fffff".into());
+        assert_eq!(1.0, found[0].score);
+    }
+
+    #[test]
+    fn round_trip_keeps_metadata() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc".into());
+        store.add_license("license-2".into(), "ddddd eeeee fffff".into());
+        let metadata = LicenseMetadata {
+            full_name: "License One".into(),
+            osi_approved: true,
+            fsf_libre: None,
+            see_also: vec!["https://example.com/license-1".into()],
+            reference_number: Some(1),
+        };
+        store.set_metadata("license-1", metadata.clone()).unwrap();
+        store
+            .set_aliases("license-1", vec!["license-1-plus".into(), "one".into()])
+            .unwrap();
+        let alias = LicenseMetadata {
+            full_name: "License One Plus".into(),
+            ..metadata.clone()
+        };
+        store.set_metadata("license-1-plus", alias.clone()).unwrap();
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+
+        assert_eq!(Some(&metadata), loaded.metadata("license-1"));
+        assert_eq!(Some(&alias), loaded.metadata("LICENSE-1-PLUS"));
+        assert_eq!(Some(&metadata), loaded.metadata("one"));
+        assert_eq!(None, loaded.metadata("license-2"));
+    }
+
+    #[test]
+    fn round_trip_keeps_deprecated_ids() {
+        let mut store = Store::new();
+        store.add_license("license-2.0".into(), "aaaaa bbbbb ccccc".into());
+        store
+            .add_deprecated("license-2".into(), "license-2.0")
+            .unwrap();
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+
+        assert_eq!(Some("license-2.0"), loaded.successor("license-2"));
+        assert_eq!(Some("license-2.0"), loaded.resolve_id("license-2"));
+    }
+
+    #[test]
+    fn round_trip_keeps_templates() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc".into());
+        let template: Template = "aaaaa <<var;name=\"b\";original=\"bbbbb\";match=\"b+\">> \
+                                  <<beginOptional>>ccccc<<endOptional>>"
+            .parse()
+            .unwrap();
+        store.set_template("license-1", template.clone()).unwrap();
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+
+        let loaded = loaded.template("license-1").unwrap();
+        assert_eq!(&template, loaded);
+        assert!(loaded.matches("aaaaa bb"));
+    }
+
+    #[test]
+    fn round_trip_keeps_ngrams() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc".into());
+        // texts loaded without their text don't contribute to the vocabulary
+        let header = crate::license::TextData::from("ddddd eeeee").without_text();
+        store
+            .add_variant("license-1", crate::LicenseType::Header, header)
+            .unwrap();
+
+        let mut buf = Vec::new();
+        store.to_cache(&mut buf).unwrap();
+        let loaded = Store::from_cache(buf.as_slice()).unwrap();
+
+        let (original, loaded) = (&store.licenses["license-1"], &loaded.licenses["license-1"]);
+        assert_eq!(original.original.ngrams(), loaded.original.ngrams());
+        assert_eq!(original.headers[0].ngrams(), loaded.headers[0].ngrams());
+    }
+}
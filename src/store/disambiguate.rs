@@ -0,0 +1,199 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashSet;
+
+use log::debug;
+use serde::Serialize;
+
+use crate::{
+    license::TextData,
+    ngram::{gram_id, GramId, Vocabulary},
+    store::{analyze::Match, base::Store},
+};
+
+/// How much better (as a fraction of all distinguishing n-grams) the winning
+/// license's clause evidence must be than the runner-up's to be trusted.
+const CLAUSE_MARGIN: f32 = 0.25;
+
+/// The outcome of resolving a family of similarly-scoring licenses by
+/// looking at the clauses that set them apart.
+///
+/// Produced during `ScanStrategy.scan` when `disambiguate` is enabled.
+#[derive(Serialize, Debug, Clone)]
+pub struct Disambiguation<'a> {
+    /// The license that the distinguishing clauses pointed to.
+    pub name: &'a str,
+    /// All licenses that were weighed against each other, in their original
+    /// order of score.
+    pub considered: Vec<&'a str>,
+    /// Clauses unique to the chosen license that were found in the text.
+    pub present: Vec<String>,
+    /// Clauses unique to the other licenses that were missing from the text.
+    pub absent: Vec<String>,
+}
+
+impl Store {
+    /// Pick between closely-scored licenses by checking which of their
+    /// distinguishing phrases appear in the text.
+    ///
+    /// A phrase is distinguishing if it appears in exactly one of the given
+    /// candidates. Each candidate is rated on how well the text agrees with
+    /// it: its own distinguishing phrases should be present, while those of
+    /// every other candidate should be absent. Returns `None` if the evidence
+    /// doesn't clearly favor a single license.
+    pub(crate) fn disambiguate<'a>(
+        &'a self,
+        text: &TextData,
+        family: &[Match<'a>],
+    ) -> Option<Disambiguation<'a>> {
+        if family.len() < 2 {
+            return None;
+        }
+
+        let sample = text.ngrams();
+        let distinct: Vec<HashSet<GramId>> = family
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                m.data
+                    .ngrams()
+                    .into_iter()
+                    .map(|&(gram, _)| gram)
+                    .filter(|gram| {
+                        family
+                            .iter()
+                            .enumerate()
+                            .all(|(j, other)| i == j || other.data.ngrams().get(*gram) == 0)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let total: usize = distinct.iter().map(HashSet::len).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let present: Vec<usize> = distinct
+            .iter()
+            .map(|grams| grams.iter().filter(|&&gram| sample.get(gram) > 0).count())
+            .collect();
+        let present_total: usize = present.iter().sum();
+
+        // agreement: own phrases present, plus everyone else's phrases absent
+        let agreement: Vec<f32> = distinct
+            .iter()
+            .zip(&present)
+            .map(|(grams, &hits)| {
+                let others = total - grams.len();
+                let others_absent = others - (present_total - hits);
+                (hits + others_absent) as f32 / total as f32
+            })
+            .collect();
+        debug!("Clause agreement for {:?}: {:?}", family, agreement);
+
+        let mut order: Vec<usize> = (0..family.len()).collect();
+        // stable sort keeps the original score order for ties
+        order.sort_by(|&a, &b| agreement[b].partial_cmp(&agreement[a]).unwrap());
+        let (best, runner_up) = (order[0], order[1]);
+        if agreement[best] - agreement[runner_up] < CLAUSE_MARGIN {
+            return None;
+        }
+
+        let found: HashSet<GramId> = distinct[best]
+            .iter()
+            .filter(|&&gram| sample.get(gram) > 0)
+            .cloned()
+            .collect();
+        let absent = family
+            .iter()
+            .zip(&distinct)
+            .enumerate()
+            .filter(|&(i, _)| i != best)
+            .flat_map(|(_, (m, grams))| {
+                let missing = grams
+                    .iter()
+                    .filter(|&&gram| sample.get(gram) == 0)
+                    .cloned()
+                    .collect();
+                phrases(m.data, &missing, &self.vocabulary)
+            })
+            .collect();
+
+        Some(Disambiguation {
+            name: family[best].name,
+            considered: family.iter().map(|m| m.name).collect(),
+            present: phrases(family[best].data, &found, &self.vocabulary),
+            absent,
+        })
+    }
+}
+
+/// Stitch a set of n-grams back together into phrases, using the ordering of
+/// the words in the text they came from.
+///
+/// If the text isn't available, the n-grams are looked up in the vocabulary
+/// and returned individually.
+fn phrases(data: &TextData, grams: &HashSet<GramId>, vocabulary: &Vocabulary) -> Vec<String> {
+    if grams.is_empty() {
+        return Vec::new();
+    }
+    let text = match data.text_processed() {
+        Some(text) => text,
+        None => {
+            let mut sorted: Vec<String> = grams.iter().map(|&g| vocabulary.describe(g)).collect();
+            sorted.sort();
+            return sorted;
+        }
+    };
+
+    let words: Vec<&str> = text.split(' ').collect();
+    let mut covered = vec![false; words.len()];
+    for n in data.ngrams().ngram_size().sizes().filter(|&n| n > 0) {
+        for (i, window) in words.windows(n).enumerate() {
+            if grams.contains(&gram_id(window)) {
+                covered[i..i + n].iter_mut().for_each(|c| *c = true);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for (word, &hit) in words.iter().zip(&covered) {
+        if hit {
+            current.push(word);
+        } else if !current.is_empty() {
+            out.push(current.join(" "));
+            current.clear();
+        }
+    }
+    if !current.is_empty() {
+        out.push(current.join(" "));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngram::{gram_id_str, NgramSize};
+
+    #[test]
+    fn phrases_are_stitched() {
+        let data = TextData::from("one two three four five six");
+        let grams: HashSet<GramId> = ["two three", "three four", "six seven"]
+            .iter()
+            .map(|gram| gram_id_str(gram))
+            .collect();
+        let mut vocabulary = Vocabulary::default();
+        vocabulary.register("one two three four", NgramSize::Fixed(2));
+        assert_eq!(vec!["two three four"], phrases(&data, &grams, &vocabulary));
+
+        let data = data.without_text();
+        let found = phrases(&data, &grams, &vocabulary);
+        assert_eq!(3, found.len());
+        assert!(found[0].starts_with('#'));
+        assert_eq!(vec!["three four", "two three"], &found[1..]);
+    }
+}
@@ -0,0 +1,416 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    license::{LicenseType, TextData},
+    ngram::{GramId, NgramSet, NgramSize},
+    store::{
+        base::{LicenseEntry, Store},
+        lsh::{Lsh, LshConfig},
+    },
+};
+
+/// An inverted index of every text in a `Store`, mapping each n-gram to the
+/// texts it appears in.
+///
+/// This lets analysis skip texts that have nothing in common with a sample,
+/// and count the n-grams shared with all the others in a single pass over
+/// the sample's n-grams, rather than comparing against each text in turn.
+/// The length of each posting list doubles as the document frequency of its
+/// n-gram, used to weight rare n-grams.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Index {
+    docs: Vec<IndexedText>,
+    postings: HashMap<GramId, Vec<Posting>>,
+    /// Fingerprints of each text's processed text, pointing to every
+    /// document with that fingerprint.
+    fingerprints: HashMap<u64, Vec<u32>>,
+    /// An optional locality-sensitive hashing index over the same documents.
+    lsh: Option<Lsh>,
+}
+
+/// A text as known to the index, pointing back to its place in the store.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct IndexedText {
+    pub name: String,
+    pub kind: LicenseType,
+    /// Position within the entry's alternates or headers; unused for the
+    /// original text.
+    pub position: usize,
+    /// Total number of n-grams (including duplicates) in the text.
+    pub size: usize,
+    pub ngram_size: NgramSize,
+}
+
+/// A document ID paired with the number of times an n-gram appears in it.
+type Posting = (u32, u32);
+
+/// An index as stored in a cache.
+///
+/// Posting lists are kept in order of the cache's n-gram numbering rather
+/// than keyed by ID, and each is flattened into alternating counts and deltas
+/// between document IDs. Both serialize much smaller than the originals.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedIndex {
+    docs: Vec<IndexedText>,
+    postings: Vec<Vec<u32>>,
+    fingerprints: HashMap<u64, Vec<u32>>,
+    lsh: Option<Lsh>,
+}
+
+impl Index {
+    /// Index every text in the given licenses, along with an LSH index if
+    /// configured.
+    pub fn build(licenses: &HashMap<String, LicenseEntry>, lsh: Option<LshConfig>) -> Index {
+        let mut index = Index {
+            lsh: lsh.map(Lsh::new),
+            ..Index::default()
+        };
+        for (name, entry) in licenses {
+            index.add(name, LicenseType::Original, 0, &entry.original);
+            for (position, alt) in entry.alternates.iter().enumerate() {
+                index.add(name, LicenseType::Alternate, position, alt);
+            }
+            for (position, short) in entry.short_forms.iter().enumerate() {
+                index.add(name, LicenseType::Short, position, short);
+            }
+            for (position, header) in entry.headers.iter().enumerate() {
+                index.add(name, LicenseType::Header, position, header);
+            }
+        }
+        index
+    }
+
+    /// Add a single text to the index.
+    pub fn add(&mut self, name: &str, kind: LicenseType, position: usize, data: &TextData) {
+        let doc = self.docs.len() as u32;
+        let ngrams = data.ngrams();
+        for &(gram, count) in ngrams {
+            self.postings.entry(gram).or_default().push((doc, count));
+        }
+        if !ngrams.is_empty() {
+            let docs = self.fingerprints.entry(data.fingerprint()).or_default();
+            docs.push(doc);
+        }
+        if let Some(lsh) = &mut self.lsh {
+            lsh.add(doc, ngrams);
+        }
+        self.docs.push(IndexedText {
+            name: name.to_owned(),
+            kind,
+            position,
+            size: ngrams.len(),
+            ngram_size: ngrams.ngram_size(),
+        });
+    }
+
+    pub fn docs(&self) -> &[IndexedText] {
+        &self.docs
+    }
+
+    pub fn lsh(&self) -> Option<&Lsh> {
+        self.lsh.as_ref()
+    }
+
+    /// Find every text with the given fingerprint.
+    ///
+    /// Texts can share a fingerprint, and the order they were indexed in
+    /// isn't stable, so original texts come first, then by license name.
+    pub fn exact(&self, fingerprint: u64) -> Vec<&IndexedText> {
+        let mut docs: Vec<&IndexedText> = self
+            .fingerprints
+            .get(&fingerprint)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|&doc| &self.docs[doc as usize])
+            .collect();
+        docs.sort_by_key(|doc| {
+            let original = doc.kind == LicenseType::Original;
+            (
+                !original,
+                &doc.name,
+                doc.kind == LicenseType::Header,
+                doc.kind == LicenseType::Short,
+                doc.position,
+            )
+        });
+        docs
+    }
+
+    /// Count the n-grams (including duplicates) each indexed text shares with
+    /// the given set. The result is indexed in the same order as `docs`.
+    pub fn overlaps(&self, ngrams: &NgramSet) -> Vec<u32> {
+        let mut overlaps = vec![0u32; self.docs.len()];
+        for &(gram, count) in ngrams {
+            if let Some(postings) = self.postings.get(&gram) {
+                for &(doc, doc_count) in postings {
+                    overlaps[doc as usize] += doc_count.min(count);
+                }
+            }
+        }
+        overlaps
+    }
+
+    /// Get the smoothed inverse document frequency of an n-gram.
+    ///
+    /// N-grams never seen before get the highest weight.
+    pub fn idf(&self, gram: GramId) -> f32 {
+        let df = self.postings.get(&gram).map_or(0, Vec::len);
+        ((1 + self.docs.len()) as f32 / (1 + df) as f32).ln() + 1.0
+    }
+
+    /// Convert to the form stored in a cache, given a function numbering each
+    /// n-gram from zero and the total number of n-grams.
+    pub fn to_cached(&self, id: &dyn Fn(GramId) -> usize, grams: usize) -> CachedIndex {
+        let mut postings = vec![Vec::new(); grams];
+        for (&gram, list) in &self.postings {
+            let mut last = 0;
+            postings[id(gram)] = list
+                .iter()
+                .flat_map(|&(doc, count)| {
+                    let delta = doc - last;
+                    last = doc;
+                    vec![delta, count]
+                })
+                .collect();
+        }
+        CachedIndex {
+            docs: self.docs.clone(),
+            postings,
+            fingerprints: self.fingerprints.clone(),
+            lsh: self.lsh.clone(),
+        }
+    }
+
+    /// Restore an index from its cached form, given the n-gram ID for each
+    /// position in the cache.
+    pub fn from_cached(cached: CachedIndex, ids: &[GramId]) -> Result<Index, Error> {
+        if cached.postings.len() > ids.len() {
+            return Err(format_err!("cached index references unknown n-grams"));
+        }
+
+        let docs = cached.docs.len() as u32;
+        let mut postings = HashMap::with_capacity(cached.postings.len());
+        for (&gram, flat) in ids.iter().zip(cached.postings) {
+            if flat.is_empty() {
+                continue;
+            }
+            if flat.len() % 2 != 0 {
+                return Err(format_err!("cached index has a truncated posting list"));
+            }
+            let mut doc = 0u32;
+            let mut list = Vec::with_capacity(flat.len() / 2);
+            for pair in flat.chunks(2) {
+                doc = match doc.checked_add(pair[0]) {
+                    Some(doc) if doc < docs => doc,
+                    _ => return Err(format_err!("cached index references unknown texts")),
+                };
+                list.push((doc, pair[1]));
+            }
+            postings.insert(gram, list);
+        }
+        let fingerprinted = cached.fingerprints.values().flatten();
+        if fingerprinted.into_iter().any(|&doc| doc >= docs) {
+            return Err(format_err!("cached index references unknown texts"));
+        }
+        let lsh = match cached.lsh {
+            Some(lsh) => Some(lsh.restore(docs)?),
+            None => None,
+        };
+        Ok(Index {
+            docs: cached.docs,
+            postings,
+            fingerprints: cached.fingerprints,
+            lsh,
+        })
+    }
+
+    /// Check that every indexed text exists among the given licenses, which
+    /// a corrupt cache can't be relied on for.
+    pub fn check_texts(&self, licenses: &HashMap<String, LicenseEntry>) -> Result<(), Error> {
+        for doc in &self.docs {
+            let entry = licenses.get(&doc.name).ok_or_else(|| {
+                format_err!("cached index references unknown license {}", doc.name)
+            })?;
+            let known = match doc.kind {
+                LicenseType::Original => true,
+                LicenseType::Alternate => doc.position < entry.alternates.len(),
+                LicenseType::Short => doc.position < entry.short_forms.len(),
+                LicenseType::Header => doc.position < entry.headers.len(),
+            };
+            if !known {
+                return Err(format_err!("cached index references unknown texts"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Store {
+    /// Rebuild the n-gram index from scratch.
+    ///
+    /// This must be called after changing `licenses` directly, rather than
+    /// through methods that keep the index up to date.
+    pub(crate) fn rebuild_index(&mut self) {
+        let lsh = self.index.lsh().map(Lsh::config);
+        self.index = Index::build(&self.licenses, lsh);
+    }
+
+    /// Build a locality-sensitive hashing index over the store's texts.
+    ///
+    /// With many thousands of texts in a store, scoring each one during
+    /// analysis gets slow. The LSH index instead finds texts likely to be
+    /// similar to a sample, and only those are scored; see `LshConfig` for
+    /// how to balance speed and accuracy. If none are found, or the best of
+    /// them scores below `LshConfig::fallback_below`, analysis falls back to
+    /// scoring everything.
+    ///
+    /// Results found through the index may leave out texts that score
+    /// poorly, so `analyze_top` can return fewer matches than asked for. The
+    /// index is kept up to date as licenses are added, and saved in the
+    /// cache.
+    ///
+    /// This fails if `config` has no bands or rows, or too many values in a
+    /// signature; see `LshConfig`.
+    pub fn enable_lsh(&mut self, config: LshConfig) -> Result<(), Error> {
+        config.check()?;
+        self.index = Index::build(&self.licenses, Some(config));
+        Ok(())
+    }
+
+    /// Remove the locality-sensitive hashing index, if any.
+    pub fn disable_lsh(&mut self) {
+        self.index = Index::build(&self.licenses, None);
+    }
+
+    /// Get the settings of the locality-sensitive hashing index, if enabled.
+    pub fn lsh_config(&self) -> Option<LshConfig> {
+        self.index.lsh().map(Lsh::config)
+    }
+
+    /// Look up the text an index entry refers to.
+    pub(crate) fn indexed_text(&self, doc: &IndexedText) -> &TextData {
+        let entry = &self.licenses[&doc.name];
+        match doc.kind {
+            LicenseType::Original => &entry.original,
+            LicenseType::Alternate => &entry.alternates[doc.position],
+            LicenseType::Short => &entry.short_forms[doc.position],
+            LicenseType::Header => &entry.headers[doc.position],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngram::gram_id_str;
+
+    #[test]
+    fn overlaps_match_direct_comparison() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc aaaaa bbbbb".into());
+        store.add_license("license-2".into(), "ccccc ddddd eeeee".into());
+        store
+            .add_variant("license-1", LicenseType::Header, "aaaaa bbbbb".into())
+            .unwrap();
+
+        let sample = TextData::from("aaaaa bbbbb ccccc ddddd");
+        let overlaps = store.index.overlaps(sample.ngrams());
+        for (doc, overlap) in store.index.docs().iter().zip(overlaps) {
+            let data = store.indexed_text(doc);
+            let direct = data.match_score(&sample);
+            let indexed = NgramSet::overlap_score(
+                crate::ngram::SimilarityMetric::Dice,
+                overlap,
+                doc.size,
+                sample.ngrams().len(),
+            )
+            .unwrap();
+            assert_eq!(direct, indexed, "{} {:?}", doc.name, doc.kind);
+        }
+    }
+
+    #[test]
+    fn rare_grams_weigh_more() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "of the software".into());
+        store.add_license("license-2".into(), "of the copyleft".into());
+        let index = &store.index;
+
+        assert!(index.idf(gram_id_str("of the")) < index.idf(gram_id_str("the copyleft")));
+        assert!(index.idf(gram_id_str("the copyleft")) < index.idf(gram_id_str("never seen")));
+
+        // replacing a license drops its n-grams
+        store.add_license("license-2".into(), "something else".into());
+        let index = &store.index;
+        assert_eq!(
+            index.idf(gram_id_str("the software")),
+            index.idf(gram_id_str("of the"))
+        );
+        assert_eq!(
+            index.idf(gram_id_str("never seen")),
+            index.idf(gram_id_str("the copyleft"))
+        );
+    }
+
+    #[test]
+    fn corrupt_postings_are_rejected() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb".into());
+        let ids: Vec<GramId> = store.index.postings.keys().cloned().collect();
+        let cached = |postings: Vec<u32>| {
+            let mut cached = store.index.to_cached(&|_| 0, 1);
+            cached.postings = vec![postings];
+            Index::from_cached(cached, &ids)
+        };
+
+        assert!(cached(vec![0, 1]).is_ok());
+        assert!(cached(vec![0, 1, 0]).is_err());
+        assert!(cached(vec![1, 1]).is_err());
+        assert!(cached(vec![0, 1, u32::MAX, 1]).is_err());
+    }
+
+    #[test]
+    fn corrupt_lsh_is_rejected() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb".into());
+        store.enable_lsh(LshConfig::default()).unwrap();
+        let ids: Vec<GramId> = store.index.postings.keys().cloned().collect();
+        let cached = |lsh: Lsh| {
+            let mut cached = store.index.to_cached(&|_| 0, 1);
+            cached.lsh = Some(lsh);
+            Index::from_cached(cached, &ids)
+        };
+
+        let sample = TextData::from("aaaaa bbbbb");
+        let mut lsh = Lsh::new(LshConfig::default());
+        lsh.add(0, sample.ngrams());
+        assert!(cached(lsh.clone()).is_ok());
+        lsh.add(1, sample.ngrams());
+        assert!(cached(lsh).is_err());
+    }
+
+    #[test]
+    fn mismatched_texts_are_rejected() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb".into());
+        store
+            .add_variant("license-1", LicenseType::Header, "aaaaa".into())
+            .unwrap();
+        let check = |edit: &dyn Fn(&mut IndexedText)| {
+            let mut index = Index::build(&store.licenses, None);
+            index.docs.iter_mut().for_each(edit);
+            index.check_texts(&store.licenses)
+        };
+
+        assert!(check(&|_| ()).is_ok());
+        assert!(check(&|doc| doc.name = "license-2".into()).is_err());
+        assert!(check(&|doc| doc.position += 1).is_err());
+        assert!(check(&|doc| doc.kind = LicenseType::Alternate).is_err());
+    }
+}
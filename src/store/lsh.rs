@@ -0,0 +1,235 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{HashMap, HashSet};
+
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::ngram::NgramSet;
+
+/// Settings for a store's locality-sensitive hashing index.
+///
+/// Each text's n-grams are summarized by a MinHash signature of
+/// `bands * rows` values, split into `bands` groups of `rows`. Texts that
+/// agree on every value in at least one band become candidates for a full
+/// comparison, so the chance that a text whose n-grams overlap a sample's by
+/// a fraction `s` (by Jaccard similarity) gets compared is roughly
+/// `1 - (1 - s^rows)^bands`.
+///
+/// More bands find more distant matches at the cost of comparing against
+/// more texts; more rows do the opposite. Both must be at least 1, with no
+/// more than 65536 values in a signature. See `Store::enable_lsh`.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LshConfig {
+    /// Number of bands in each signature.
+    pub bands: usize,
+    /// Number of MinHash values in each band.
+    pub rows: usize,
+    /// If the best candidate found through the index scores below this,
+    /// every text in the store is scored instead. Set it to 0 to always
+    /// trust the index, or above 1 to use the index only to skip work for
+    /// samples with no close match at all.
+    pub fallback_below: f32,
+}
+
+impl Default for LshConfig {
+    /// 32 bands of 4 rows: texts with half their n-grams in common are
+    /// compared about 87% of the time, and those with a quarter in common
+    /// about 12% of the time.
+    fn default() -> LshConfig {
+        LshConfig {
+            bands: 32,
+            rows: 4,
+            fallback_below: 0.8,
+        }
+    }
+}
+
+/// The most MinHash values a signature may have.
+const MAX_SIGNATURE: usize = 1 << 16;
+
+impl LshConfig {
+    /// Check that the settings describe a signature that can be computed.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        match self.bands.checked_mul(self.rows) {
+            Some(len) if self.bands > 0 && self.rows > 0 && len <= MAX_SIGNATURE => Ok(()),
+            _ => Err(format_err!(
+                "LSH needs at least one band and row, and at most {} values in all; got {} bands of {} rows",
+                MAX_SIGNATURE,
+                self.bands,
+                self.rows
+            )),
+        }
+    }
+}
+
+/// MinHash signatures of indexed texts, bucketed by band.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Lsh {
+    config: LshConfig,
+    /// For each band, the documents sharing each band hash.
+    buckets: Vec<HashMap<u64, Vec<u32>>>,
+    /// The seed of each MinHash value, derived from the settings.
+    #[serde(skip)]
+    seeds: Vec<u64>,
+}
+
+impl Lsh {
+    /// Create an empty index. The settings must have passed
+    /// `LshConfig::check`.
+    pub fn new(config: LshConfig) -> Lsh {
+        Lsh {
+            config,
+            buckets: vec![HashMap::new(); config.bands],
+            seeds: seeds(config),
+        }
+    }
+
+    pub fn config(&self) -> LshConfig {
+        self.config
+    }
+
+    /// Check an index read from a cache, and ready it for use: it needs
+    /// valid settings and a set of buckets for each band, and may only refer
+    /// to documents below `docs`.
+    pub fn restore(mut self, docs: u32) -> Result<Lsh, Error> {
+        if self.config.check().is_err() || self.buckets.len() != self.config.bands {
+            return Err(format_err!("cached LSH index doesn't match its settings"));
+        }
+        let bucketed = self.buckets.iter().flat_map(HashMap::values).flatten();
+        if bucketed.into_iter().any(|&doc| doc >= docs) {
+            return Err(format_err!("cached LSH index references unknown texts"));
+        }
+        self.seeds = seeds(self.config);
+        Ok(self)
+    }
+
+    /// Add a document's n-grams under the given ID.
+    pub fn add(&mut self, doc: u32, ngrams: &NgramSet) {
+        if ngrams.is_empty() {
+            return;
+        }
+        let keys = self.band_keys(ngrams);
+        for (bucket, key) in self.buckets.iter_mut().zip(keys) {
+            bucket.entry(key).or_default().push(doc);
+        }
+    }
+
+    /// Find the documents sharing at least one band with the given n-grams,
+    /// in ascending order of ID.
+    pub fn query(&self, ngrams: &NgramSet) -> Vec<u32> {
+        if ngrams.is_empty() {
+            return Vec::new();
+        }
+        let mut found = HashSet::new();
+        for (bucket, key) in self.buckets.iter().zip(self.band_keys(ngrams)) {
+            if let Some(docs) = bucket.get(&key) {
+                found.extend(docs);
+            }
+        }
+        let mut found: Vec<u32> = found.into_iter().collect();
+        found.sort_unstable();
+        found
+    }
+
+    /// Compute the MinHash signature of a set of n-grams, then hash each band
+    /// of it down to a single bucket key.
+    fn band_keys(&self, ngrams: &NgramSet) -> Vec<u64> {
+        let mut signature = vec![u64::MAX; self.seeds.len()];
+        for &(id, _) in ngrams {
+            for (min, seed) in signature.iter_mut().zip(&self.seeds) {
+                *min = (*min).min(mix(id ^ seed));
+            }
+        }
+
+        signature
+            .chunks(self.config.rows)
+            .enumerate()
+            .map(|(band, values)| {
+                values
+                    .iter()
+                    .fold(band as u64, |key, &value| mix(key ^ value))
+            })
+            .collect()
+    }
+}
+
+/// The seed of each MinHash value in a signature.
+fn seeds(config: LshConfig) -> Vec<u64> {
+    (0..config.bands * config.rows)
+        .map(|i| mix(i as u64))
+        .collect()
+}
+
+/// Scramble a 64-bit value (the SplitMix64 finalizer). Hashing n-gram IDs
+/// with this under different seeds gives the independent permutations that
+/// MinHash needs.
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngram::NgramSize;
+
+    fn words(range: std::ops::Range<usize>) -> NgramSet {
+        let text: Vec<String> = range.map(|i| format!("w{}", i)).collect();
+        NgramSet::from_str(&text.join(" "), NgramSize::Fixed(2))
+    }
+
+    #[test]
+    fn similar_texts_collide() {
+        let mut lsh = Lsh::new(LshConfig::default());
+        lsh.add(0, &words(0..200));
+        lsh.add(1, &words(1000..1200));
+        lsh.add(2, &words(2000..2200));
+
+        // ~90% of n-grams in common with the first text, none with the rest
+        assert_eq!(vec![0], lsh.query(&words(10..200)));
+        assert!(lsh.query(&words(5000..5200)).is_empty());
+        assert!(lsh.query(&NgramSet::new(NgramSize::Fixed(2))).is_empty());
+    }
+
+    #[test]
+    fn mismatched_shapes_are_rejected() {
+        let mut lsh = Lsh::new(LshConfig::default());
+        lsh.add(0, &words(0..200));
+        assert!(lsh.clone().restore(0).is_err());
+        let restored = lsh.clone().restore(1).unwrap();
+        assert_eq!(vec![0], restored.query(&words(10..200)));
+
+        lsh.buckets.pop();
+        assert!(lsh.restore(1).is_err());
+
+        let lsh = Lsh {
+            config: LshConfig {
+                bands: 2,
+                rows: 0,
+                fallback_below: 0.8,
+            },
+            buckets: vec![HashMap::new(); 2],
+            seeds: Vec::new(),
+        };
+        assert!(lsh.restore(1).is_err());
+    }
+
+    #[test]
+    fn unusable_settings_are_rejected() {
+        let config = |bands, rows| LshConfig {
+            bands,
+            rows,
+            fallback_below: 0.8,
+        };
+        assert!(LshConfig::default().check().is_ok());
+        assert!(config(1, MAX_SIGNATURE).check().is_ok());
+        assert!(config(0, 4).check().is_err());
+        assert!(config(32, 0).check().is_err());
+        assert!(config(2, MAX_SIGNATURE).check().is_err());
+        assert!(config(usize::MAX, 2).check().is_err());
+    }
+}
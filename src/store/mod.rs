@@ -4,8 +4,23 @@
 mod analyze;
 mod base;
 mod cache;
+mod disambiguate;
+mod index;
+mod lsh;
+mod text;
 
+#[cfg(test)]
+pub(crate) mod test_dir;
+
+#[cfg(feature = "scancode")]
+mod scancode;
 #[cfg(feature = "spdx")]
 mod spdx;
 
-pub use self::{analyze::Match, base::Store};
+pub(crate) use self::base::split_with;
+pub use self::{
+    analyze::Match,
+    base::{LicenseMetadata, Store},
+    disambiguate::Disambiguation,
+    lsh::LshConfig,
+};
@@ -0,0 +1,449 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashMap, ffi::OsStr, fs::read_to_string, path::Path};
+
+use failure::{bail, Error};
+use log::{debug, info};
+
+use crate::{
+    license::LicenseType,
+    store::{
+        base::{resolve, LicenseEntry, LicenseMetadata, Store},
+        text::files_with_extension,
+    },
+};
+
+/// The fewest words a ScanCode notice or reference rule needs to be kept.
+const MIN_HEADER_WORDS: usize = 5;
+
+impl Store {
+    /// Fill the store with licenses from ScanCode's license database.
+    ///
+    /// `dir` is the `src/licensedcode/data` directory of ScanCode toolkit,
+    /// holding `licenses` and `rules` directories. Each `<key>.LICENSE` file
+    /// becomes a license, named for its `spdx_license_key` if it has one, with
+    /// its ScanCode key and any `other_spdx_license_keys` as aliases. Rules
+    /// for a single license are added to it: `is_license_notice` and
+    /// `is_license_reference` rules as headers, and `is_license_text` rules as
+    /// alternates. Other rules, and deprecated licenses, are skipped, as are
+    /// notices and references that ScanCode gives a `relevance` under 100 or
+    /// that are shorter than `MIN_HEADER_WORDS`; those are mentions such as
+    /// "MIT license" that would match any sentence sharing a few words.
+    ///
+    /// Both ScanCode's layouts are read: YAML front matter at the top of each
+    /// file, and the older separate `<key>.yml` files.
+    ///
+    /// Licenses already in the store are kept, though ScanCode's rules for
+    /// them are still added, so this can be used on top of `load_spdx`. A
+    /// ScanCode license is taken to be one already stored if any of its ids
+    /// is a stored license's name or alias, or if its text is identical to
+    /// one; its ids not yet known become aliases of that license.
+    ///
+    /// This is only available with the `scancode` feature. `include_texts`
+    /// keeps the texts in the store, as with `load_spdx`.
+    pub fn load_scancode(&mut self, dir: &Path, include_texts: bool) -> Result<(), Error> {
+        // ScanCode keys, and the name of the license each went to
+        let mut keys: HashMap<String, String> = HashMap::new();
+
+        for path in files_with_extension(&dir.join("licenses"), "LICENSE")? {
+            let (fields, text) = read_scancode_file(&path)?;
+            let key = match fields.scalar("key") {
+                Some(key) => key.to_owned(),
+                None => stem(&path),
+            };
+            if fields.flag("is_deprecated") {
+                debug!("Skipping {} (deprecated)", key);
+                continue;
+            }
+            if text.trim().is_empty() {
+                debug!("Skipping {} (no text)", key);
+                continue;
+            }
+
+            let name = fields.scalar("spdx_license_key").unwrap_or(&key).to_owned();
+            let ids: Vec<&str> = std::iter::once(name.as_str())
+                .chain(std::iter::once(key.as_str()))
+                .chain(fields.list("other_spdx_license_keys"))
+                .collect();
+
+            // a license already stored, by name, alias, or an identical text
+            let mut existing = ids
+                .iter()
+                .find_map(|id| resolve(&self.licenses, id))
+                .map(str::to_owned);
+            let content = match existing {
+                Some(_) => None,
+                None => Some(self.text_data(&text, include_texts)),
+            };
+            if let Some(content) = &content {
+                existing = self
+                    .licenses
+                    .iter()
+                    .find(|(_, entry)| entry.original.eq_data(content))
+                    .map(|(stored, _)| stored.clone());
+            }
+            if let Some(stored) = existing {
+                debug!(
+                    "{} already stored as {}; only adding its rules",
+                    name, stored
+                );
+                add_aliases(self, &stored, &ids);
+                keys.insert(key, stored);
+                continue;
+            }
+
+            info!("Processing {}", name);
+            let mut entry = LicenseEntry::new(content.unwrap());
+            entry.metadata = Some(metadata(&fields, &name));
+            self.licenses.insert(name.clone(), entry);
+            add_aliases(self, &name, &ids);
+            keys.insert(key, name);
+        }
+
+        let rules = dir.join("rules");
+        let rules = match rules.is_dir() {
+            true => files_with_extension(&rules, "RULE")?,
+            false => Vec::new(),
+        };
+        for path in rules {
+            let (fields, text) = read_scancode_file(&path)?;
+            let kind = if fields.flag("is_license_notice") || fields.flag("is_license_reference") {
+                LicenseType::Header
+            } else if fields.flag("is_license_text") {
+                LicenseType::Alternate
+            } else {
+                continue;
+            };
+            // only rules for a single license; the rest are combinations
+            let name = match fields
+                .scalar("license_expression")
+                .and_then(|k| keys.get(k))
+            {
+                Some(name) if !fields.flag("is_false_positive") => name.clone(),
+                _ => continue,
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            if kind == LicenseType::Header && !is_specific(&fields, &text) {
+                debug!("Skipping {} (too short to match on)", path.display());
+                continue;
+            }
+
+            let content = self.text_data(&text, include_texts);
+            let entry = self.licenses.get_mut(&name).unwrap();
+            match kind {
+                LicenseType::Header => entry.headers.push(content),
+                _ => entry.alternates.push(content),
+            }
+        }
+
+        self.rebuild_index();
+        Ok(())
+    }
+}
+
+/// Add ids to a license's aliases, skipping those that already refer to a
+/// license in the store.
+fn add_aliases(store: &mut Store, name: &str, ids: &[&str]) {
+    for id in ids {
+        if resolve(&store.licenses, id).is_none() {
+            let entry = store.licenses.get_mut(name).unwrap();
+            entry.aliases.push((*id).to_owned());
+        }
+    }
+}
+
+/// Whether a notice or reference rule says enough to be matched on its own.
+fn is_specific(fields: &Fields, text: &str) -> bool {
+    let relevance = fields
+        .scalar("relevance")
+        .and_then(|r| r.parse::<u32>().ok())
+        .unwrap_or(100);
+    relevance >= 100 && text.split_whitespace().count() >= MIN_HEADER_WORDS
+}
+
+/// Build a license's metadata from its ScanCode fields.
+fn metadata(fields: &Fields, name: &str) -> LicenseMetadata {
+    let see_also = fields
+        .scalar("homepage_url")
+        .into_iter()
+        .chain(fields.list("text_urls"))
+        .chain(fields.list("osi_url"))
+        .map(str::to_owned)
+        .collect();
+    LicenseMetadata {
+        full_name: fields.scalar("name").unwrap_or(name).to_owned(),
+        osi_approved: fields.scalar("osi_license_key").is_some(),
+        fsf_libre: None,
+        see_also,
+        reference_number: None,
+    }
+}
+
+/// Read a ScanCode license or rule file, giving its fields and its text.
+fn read_scancode_file(path: &Path) -> Result<(Fields, String), Error> {
+    let content = read_to_string(path)?;
+    if let Some(rest) = content.strip_prefix("---") {
+        let end = match rest.find("\n---") {
+            Some(end) => end,
+            None => bail!("{} has unterminated front matter", path.display()),
+        };
+        let text = rest[end + 4..].trim_start_matches(['\r', '\n']);
+        return Ok((Fields::parse(&rest[..end]), text.to_owned()));
+    }
+
+    // the older layout keeps the fields in a file of their own
+    let fields = match read_to_string(path.with_extension("yml")) {
+        Ok(yaml) => Fields::parse(&yaml),
+        Err(_) => Fields::default(),
+    };
+    Ok((fields, content))
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// The fields of ScanCode's YAML: top-level keys with a scalar value or a
+/// list of them, which is all ScanCode uses. Anything else, such as
+/// multi-line text, is skipped.
+#[derive(Default, Debug)]
+struct Fields {
+    scalars: HashMap<String, String>,
+    lists: HashMap<String, Vec<String>>,
+}
+
+impl Fields {
+    fn parse(yaml: &str) -> Fields {
+        let mut fields = Fields::default();
+        let mut list_key: Option<String> = None;
+        for line in yaml.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with(char::is_whitespace) || trimmed.starts_with("- ") {
+                if let (Some(key), Some(item)) = (&list_key, trimmed.strip_prefix("- ")) {
+                    let list = fields.lists.entry(key.clone()).or_default();
+                    list.push(unquote(item.trim()));
+                }
+                continue;
+            }
+
+            list_key = None;
+            let (key, value) = match trimmed.find(':') {
+                Some(colon) => (&trimmed[..colon], trimmed[colon + 1..].trim()),
+                None => continue,
+            };
+            match value {
+                "" => list_key = Some(key.to_owned()),
+                // block scalars aren't needed; skip them
+                _ if value.starts_with(['|', '>']) => {}
+                _ => {
+                    fields.scalars.insert(key.to_owned(), unquote(value));
+                }
+            }
+        }
+        fields
+    }
+
+    fn scalar(&self, key: &str) -> Option<&str> {
+        self.scalars
+            .get(key)
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
+
+    fn list<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a str> + 'a {
+        self.lists
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(self.scalar(key))
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        match self.scalar(key) {
+            Some(value) => ["yes", "true", "on"]
+                .iter()
+                .any(|yes| value.eq_ignore_ascii_case(yes)),
+            None => false,
+        }
+    }
+}
+
+/// Take the quotes off a YAML scalar.
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return value[1..value.len() - 1].replace("''", "'");
+    }
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return value[1..value.len() - 1]
+            .replace("\\\"", "\"")
+            .replace("\\\\", "\\");
+    }
+    value.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_dir::TestDir;
+
+    #[test]
+    fn yaml_fields_are_read() {
+        let fields = Fields::parse(
+            "key: bsd-new\nname: 'BSD-3-Clause ''New'''\nis_deprecated: no\n\
+             notes: |\n    some: text\nother_spdx_license_keys:\n    - LicenseRef-BSD\n  \
+             - \"BSD\"\ntext_urls:\n- http://example.com\nminimum_coverage: 80\n",
+        );
+        assert_eq!(Some("bsd-new"), fields.scalar("key"));
+        assert_eq!(Some("BSD-3-Clause 'New'"), fields.scalar("name"));
+        assert!(!fields.flag("is_deprecated"));
+        assert_eq!(None, fields.scalar("notes"));
+        assert_eq!(None, fields.scalar("some"));
+        assert_eq!(
+            vec!["LicenseRef-BSD", "BSD"],
+            fields.list("other_spdx_license_keys").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["http://example.com"],
+            fields.list("text_urls").collect::<Vec<_>>()
+        );
+        assert_eq!(Some("80"), fields.scalar("minimum_coverage"));
+    }
+
+    #[test]
+    fn database_is_loaded() {
+        let files = [
+            (
+                "licenses/acme.LICENSE",
+                "---\nkey: acme\nname: ACME License\nspdx_license_key: LicenseRef-scancode-acme\n\
+                 osi_license_key: ACME\ntext_urls:\n    - https://acme.example/license\n---\n\
+                 the acme license lets you use this software for anything at all.\n",
+            ),
+            (
+                "licenses/old.LICENSE",
+                "---\nkey: old\nis_deprecated: yes\n---\nan old license no one uses any more.\n",
+            ),
+            (
+                "licenses/split.LICENSE",
+                "a license kept in the older layout.\n",
+            ),
+            ("licenses/split.yml", "key: split\nname: Split\n"),
+            (
+                "rules/acme_1.RULE",
+                "---\nlicense_expression: acme\nis_license_notice: yes\n---\n\
+                 licensed under the acme license\n",
+            ),
+            (
+                "rules/acme_2.RULE",
+                "---\nlicense_expression: acme\nis_license_text: yes\n---\n\
+                 acme license: use this software for anything\n",
+            ),
+            (
+                "rules/acme_3.RULE",
+                "---\nlicense_expression: acme OR split\nis_license_notice: yes\n---\n\
+                 licensed under acme or split\n",
+            ),
+            (
+                "rules/acme_4.RULE",
+                "---\nlicense_expression: acme\nis_license_tag: yes\n---\nlicense: acme\n",
+            ),
+            (
+                "rules/acme_5.RULE",
+                "---\nlicense_expression: acme\nis_license_reference: yes\n---\nacme license\n",
+            ),
+            (
+                "rules/acme_6.RULE",
+                "---\nlicense_expression: acme\nis_license_notice: yes\nrelevance: 80\n---\n\
+                 this file is covered by the acme license terms\n",
+            ),
+        ];
+        let dir = TestDir::new("scancode", &files);
+
+        let mut store = Store::new();
+        store.load_scancode(dir.path(), true).unwrap();
+
+        assert_eq!(2, store.len());
+        let entry = &store.licenses["LicenseRef-scancode-acme"];
+        assert_eq!(vec!["acme"], entry.aliases);
+        assert_eq!((1, 1), (entry.headers.len(), entry.alternates.len()));
+        let metadata = store.metadata("LicenseRef-scancode-acme").unwrap();
+        assert_eq!("ACME License", metadata.full_name);
+        assert!(metadata.osi_approved);
+        assert_eq!(vec!["https://acme.example/license"], metadata.see_also);
+        assert_eq!("Split", store.metadata("split").unwrap().full_name);
+
+        let found = store.analyze(&"licensed under the acme license".into());
+        assert_eq!("LicenseRef-scancode-acme", found.name);
+        assert_eq!(LicenseType::Header, found.license_type);
+
+        // a short reference would otherwise match any sentence naming it
+        let found = store.analyze(&"the acme license server is down".into());
+        assert!(found.score < 0.8, "{:?} scored {}", found.name, found.score);
+    }
+
+    #[test]
+    fn stored_licenses_are_reused() {
+        let files = [
+            (
+                "licenses/bsd-new.LICENSE",
+                "---
+key: bsd-new
+spdx_license_key: BSD-3-Clause
+---
+redistribution is \
+                 permitted provided these conditions are met.
+",
+            ),
+            (
+                "licenses/mit-old.LICENSE",
+                "---
+key: mit-old
+---
+permission is hereby granted to deal in the \
+                 software without restriction.
+",
+            ),
+            (
+                "rules/mit_1.RULE",
+                "---
+license_expression: mit-old
+is_license_notice: yes
+---
+\
+                 released under the mit license
+",
+            ),
+        ];
+        let dir = TestDir::new("scancode-stored", &files);
+
+        let mut store = Store::new();
+        store.add_license(
+            "BSD-3-Clause-Stored".into(),
+            "a different text entirely, not a match for anything.".into(),
+        );
+        store
+            .set_aliases("BSD-3-Clause-Stored", vec!["bsd-3-clause".into()])
+            .unwrap();
+        store.add_license(
+            "MIT".into(),
+            "permission is hereby granted to deal in the software without restriction.".into(),
+        );
+        store.load_scancode(dir.path(), true).unwrap();
+
+        // by alias, and by identical text
+        assert_eq!(2, store.len());
+        assert_eq!(Some("BSD-3-Clause-Stored"), store.resolve_id("bsd-new"));
+        assert_eq!(vec!["mit-old"], *store.aliases("MIT").unwrap());
+        assert_eq!(1, store.headers("MIT").unwrap().len());
+    }
+}
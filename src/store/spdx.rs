@@ -1,19 +1,18 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{
-    ffi::OsStr,
-    fs::{read_dir, File},
-    io::prelude::*,
-    path::Path,
-};
+use std::{ffi::OsStr, fs::File, io::prelude::*, path::Path};
 
 use failure::{format_err, Error};
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use crate::{
     license::TextData,
-    store::base::{LicenseEntry, Store},
+    store::{
+        base::{LicenseEntry, LicenseMetadata, Store},
+        text::files_with_extension,
+    },
+    template::Template,
 };
 
 impl Store {
@@ -22,7 +21,14 @@ impl Store {
     /// This function is very specific to the format of SPDX's
     /// `license-list-data` repository. It reads all JSON files in the
     /// `json/details` directory and creates entries inside the store for
-    /// matching.
+    /// matching. Deprecated ids become aliases of the licenses that replace
+    /// them; see `Store::successor`.
+    ///
+    /// Deprecated ids for a license with an exception, such as
+    /// `GPL-2.0-with-classpath-exception`, are replaced with the license
+    /// `WITH` the exception. That needs the exceptions in the store already,
+    /// so call `load_spdx_exceptions` first; those whose exception can't be
+    /// found are skipped with a warning.
     ///
     /// This is intended to be used during build of askalono, so it's not
     /// available unless the `spdx` feature is enabled.
@@ -34,17 +40,8 @@ impl Store {
     pub fn load_spdx(&mut self, dir: &Path, include_texts: bool) -> Result<(), Error> {
         use serde_json::{from_str, Value};
 
-        // locate all json files in the directory
-        let mut paths: Vec<_> = read_dir(dir)?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path())
-            .filter(|p| p.is_file() && p.extension().unwrap_or_else(|| OsStr::new("")) == "json")
-            .collect();
-
-        // sort without extensions; otherwise dashes and dots muck it up
-        paths.sort_by(|a, b| a.file_stem().unwrap().cmp(b.file_stem().unwrap()));
-
-        for path in paths {
+        let mut deprecated = Vec::new();
+        for path in files_with_extension(dir, "json")? {
             let mut f = File::open(path)?;
             let mut data = String::new();
             f.read_to_string(&mut data)?;
@@ -54,24 +51,30 @@ impl Store {
                 .as_str()
                 .ok_or_else(|| format_err!("missing licenseId"))?;
 
-            let deprecated = val["isDeprecatedLicenseId"]
+            let is_deprecated = val["isDeprecatedLicenseId"]
                 .as_bool()
                 .ok_or_else(|| format_err!("missing isDeprecatedLicenseId"))?;
-            if deprecated {
-                debug!("Skipping {} (deprecated)", name);
-                continue;
-            }
 
             let text = val["licenseText"]
                 .as_str()
                 .ok_or_else(|| format_err!("missing licenseText"))?;
             let header = val["standardLicenseHeader"].as_str();
 
+            // deprecated ids are looked at once all of their possible
+            // successors are in
+            if is_deprecated {
+                let content = TextData::with_pipeline(text, &self.pipeline, self.ngram_size);
+                deprecated.push((name.to_owned(), content, metadata(name, &val)));
+                continue;
+            }
+
             info!("Processing {}", name);
 
+            let content = TextData::with_pipeline(text, &self.pipeline, self.ngram_size);
+            content.add_to_vocabulary(&mut self.vocabulary);
             let content = match include_texts {
-                true => TextData::new(text),
-                false => TextData::new(text).without_text(),
+                true => content,
+                false => content.without_text(),
             };
 
             // check if an identical license is already present
@@ -79,6 +82,8 @@ impl Store {
             self.licenses.iter_mut().for_each(|(key, ref mut value)| {
                 if value.original.eq_data(&content) {
                     value.aliases.push(name.to_string());
+                    let metadata = metadata(name, &val);
+                    value.alias_metadata.insert(name.to_owned(), metadata);
                     info!("{} already stored; added as an alias for {}", name, key);
                     already_existed = true;
                 }
@@ -92,16 +97,263 @@ impl Store {
                 .licenses
                 .entry(name.to_owned())
                 .or_insert_with(|| LicenseEntry::new(content));
+            license.metadata = Some(metadata(name, &val));
+            if let Some(markup) = val["standardLicenseTemplate"].as_str() {
+                match markup.parse() {
+                    Ok(template) => license.template = Some(template),
+                    Err(e) => warn!("Skipping template of {}: {}", name, e),
+                }
+            }
 
             if let Some(header_text) = header {
+                let header_data =
+                    TextData::with_pipeline(header_text, &self.pipeline, self.ngram_size);
+                header_data.add_to_vocabulary(&mut self.vocabulary);
                 let header_data = match include_texts {
-                    false => TextData::new(header_text),
-                    true => TextData::new(header_text).without_text(),
+                    false => header_data,
+                    true => header_data.without_text(),
                 };
                 license.headers = vec![header_data];
             }
         }
 
+        for (name, content, metadata) in deprecated {
+            match self.find_successor(&name, &content) {
+                Some(successor) => {
+                    info!(
+                        "{} is deprecated; added as an alias for {}",
+                        name, successor
+                    );
+                    self.add_deprecated(name.clone(), &successor)?;
+                    self.set_metadata(&name, metadata)?;
+                }
+                None if name.contains("-with-") => warn!(
+                    "Skipping {} (deprecated, and its exception isn't in the store)",
+                    name
+                ),
+                None => debug!("Skipping {} (deprecated, with no successor)", name),
+            }
+        }
+
+        self.rebuild_index();
+        Ok(())
+    }
+
+    /// Find the license that replaces a deprecated one: by SPDX's naming
+    /// (`GPL-2.0` became `GPL-2.0-only`, and `GPL-2.0+` `GPL-2.0-or-later`),
+    /// or failing that, by having the same text.
+    ///
+    /// A license with an exception (`GPL-2.0-with-classpath-exception`) is
+    /// replaced by its license with the stored exception named like it whose
+    /// text it has most of (`GPL-2.0-only WITH Classpath-exception-2.0`).
+    fn find_successor(&self, name: &str, content: &TextData) -> Option<String> {
+        if let Some(i) = name.find("-with-") {
+            let (base, exception) = (&name[..i], name[i + 6..].to_lowercase());
+            let license = match self.find_successor(base, content) {
+                Some(license) => license,
+                None => self.resolve_id(base)?.to_owned(),
+            };
+            let exception = self
+                .analyze_exceptions(content)
+                .into_iter()
+                .find(|m| m.score > 0.0 && m.name.to_lowercase().starts_with(&exception))?;
+            return Some(format!("{} WITH {}", license, exception.name));
+        }
+
+        let renamed = match name.strip_suffix('+') {
+            Some(base) => format!("{}-or-later", base),
+            None => format!("{}-only", name),
+        };
+        if self.resolve_id(&renamed).is_some() {
+            return Some(renamed);
+        }
+        self.licenses
+            .iter()
+            .find(|(_, entry)| entry.original.eq_data(content))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Read license templates from SPDX's `license-list-XML` repository,
+    /// from the XML files in its `src` directory.
+    ///
+    /// `load_spdx` already reads the templates of SPDX's JSON data. These
+    /// replace them, for licenses already in the store; files for licenses
+    /// the store doesn't have are skipped. Like `load_spdx`, this is only
+    /// available with the `spdx` feature.
+    pub fn load_spdx_templates(&mut self, dir: &Path) -> Result<(), Error> {
+        for path in files_with_extension(dir, "xml")? {
+            let mut f = File::open(&path)?;
+            let mut data = String::new();
+            f.read_to_string(&mut data)?;
+
+            let name = match path.file_stem().and_then(OsStr::to_str) {
+                Some(stem) if self.licenses.contains_key(stem) => stem.to_owned(),
+                _ => {
+                    debug!("Skipping {} (not in store)", path.display());
+                    continue;
+                }
+            };
+            match Template::from_xml(&data) {
+                Ok(template) => self.set_template(&name, template)?,
+                Err(e) => warn!("Skipping template of {}: {}", name, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fill the store with SPDX license exceptions, from the JSON files in
+    /// the `json/exceptions` directory of SPDX's `license-list-data`
+    /// repository.
+    ///
+    /// Like `load_spdx`, this is only available with the `spdx` feature, and
+    /// `include_texts` keeps the exceptions' text in the store.
+    pub fn load_spdx_exceptions(&mut self, dir: &Path, include_texts: bool) -> Result<(), Error> {
+        use serde_json::{from_str, Value};
+
+        for path in files_with_extension(dir, "json")? {
+            let mut f = File::open(path)?;
+            let mut data = String::new();
+            f.read_to_string(&mut data)?;
+            let val: Value = from_str(&data)?;
+
+            let name = val["licenseExceptionId"]
+                .as_str()
+                .ok_or_else(|| format_err!("missing licenseExceptionId"))?;
+            if val["isDeprecatedLicenseId"].as_bool().unwrap_or(false) {
+                debug!("Skipping {} (deprecated)", name);
+                continue;
+            }
+            let text = val["licenseExceptionText"]
+                .as_str()
+                .ok_or_else(|| format_err!("missing licenseExceptionText"))?;
+
+            info!("Processing exception {}", name);
+
+            let content = self.text_data(text, include_texts);
+            self.add_exception(name.to_owned(), content);
+        }
+
         Ok(())
     }
 }
+
+/// Read the descriptive fields of a license's SPDX JSON data.
+fn metadata(name: &str, val: &serde_json::Value) -> LicenseMetadata {
+    LicenseMetadata {
+        full_name: val["name"].as_str().unwrap_or(name).to_owned(),
+        osi_approved: val["isOsiApproved"].as_bool().unwrap_or(false),
+        fsf_libre: val["isFsfLibre"].as_bool(),
+        see_also: val["seeAlso"]
+            .as_array()
+            .map(|urls| {
+                urls.iter()
+                    .filter_map(|url| url.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        // older releases of the list have this as a string
+        reference_number: match &val["referenceNumber"] {
+            serde_json::Value::String(number) => number.parse().ok(),
+            number => number.as_u64().map(|n| n as u32),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ngram::gram_id_str, store::test_dir::TestDir};
+
+    #[test]
+    fn exceptions_keep_their_vocabulary() {
+        let dir = TestDir::new(
+            "spdx-exceptions",
+            &[(
+                "Linking-exception.json",
+                r#"{"licenseExceptionId": "Linking-exception",
+                    "licenseExceptionText": "Linking this library statically"}"#,
+            )],
+        );
+        let mut store = Store::new();
+        store.load_spdx_exceptions(dir.path(), false).unwrap();
+
+        let exception = store.get_exception("Linking-exception").unwrap();
+        assert!(exception.text_processed().is_none());
+        let id = gram_id_str("library statically");
+        assert_eq!(Some("library statically"), store.vocabulary.get(id));
+    }
+
+    #[test]
+    fn deprecated_ids_keep_their_exceptions() {
+        let license = |id: &str, deprecated: bool, text: &str| {
+            format!(
+                r#"{{"licenseId": "{}", "isDeprecatedLicenseId": {}, "licenseText": "{}"}}"#,
+                id, deprecated, text
+            )
+        };
+        let gpl = "the gnu general public license version 2";
+        let classpath = "linking this library statically or dynamically with other modules";
+        let gcc = "the gcc runtime library exception grants extra permissions";
+        let licenses = TestDir::new(
+            "spdx-with",
+            &[
+                ("GPL-2.0-only.json", &license("GPL-2.0-only", false, gpl)),
+                (
+                    "GPL-2.0-with-classpath-exception.json",
+                    &license(
+                        "GPL-2.0-with-classpath-exception",
+                        true,
+                        &format!("{} {}", gpl, classpath),
+                    ),
+                ),
+                (
+                    "GPL-2.0-with-font-exception.json",
+                    &license("GPL-2.0-with-font-exception", true, gpl),
+                ),
+            ],
+        );
+        let exception = |id: &str, text: &str| {
+            format!(
+                r#"{{"licenseExceptionId": "{}", "licenseExceptionText": "{}"}}"#,
+                id, text
+            )
+        };
+        let exceptions = TestDir::new(
+            "spdx-with-exceptions",
+            &[
+                (
+                    "Classpath-exception-2.0.json",
+                    &exception("Classpath-exception-2.0", classpath),
+                ),
+                (
+                    "GCC-exception-2.0.json",
+                    &exception("GCC-exception-2.0", gcc),
+                ),
+            ],
+        );
+
+        let mut store = Store::new();
+        store
+            .load_spdx_exceptions(exceptions.path(), false)
+            .unwrap();
+        store.load_spdx(licenses.path(), false).unwrap();
+
+        assert_eq!(
+            Some("GPL-2.0-only WITH Classpath-exception-2.0"),
+            store.successor("GPL-2.0-with-classpath-exception")
+        );
+        assert_eq!(
+            Some("GPL-2.0-only"),
+            store.resolve_id("GPL-2.0-with-classpath-exception")
+        );
+        // its exception isn't in the store
+        assert_eq!(None, store.successor("GPL-2.0-with-font-exception"));
+
+        let expression: crate::Expression = "gpl-2.0-with-classpath-exception".parse().unwrap();
+        assert_eq!(
+            "GPL-2.0-only WITH Classpath-exception-2.0",
+            expression.resolve(&store).unwrap().to_string()
+        );
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{create_dir_all, remove_dir_all, write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A temporary directory of files for a test to load from, removed when it
+/// goes out of scope -- including when the test fails partway through.
+pub(crate) struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Create a directory unique to this test run, holding the given files.
+    /// File names can include subdirectories.
+    pub fn new(name: &str, files: &[(&str, &str)]) -> TestDir {
+        let dir = std::env::temp_dir().join(format!("askalono-{}-{}", name, process::id()));
+        // left over from an aborted run with a recycled pid
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+
+        let dir = TestDir(dir);
+        for (name, text) in files {
+            dir.write(name, text);
+        }
+        dir
+    }
+
+    /// Add a file to the directory, or replace one.
+    pub fn write(&self, name: &str, text: &str) {
+        let path = self.0.join(name);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, text).unwrap();
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use failure::{bail, format_err, Error};
+use log::{debug, info};
+
+use crate::{
+    license::TextData,
+    store::base::{resolve, LicenseEntry, Store},
+};
+
+/// What a file in a text directory holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum FileKind {
+    Original,
+    Aliases,
+    Header,
+    Alternate,
+    Short,
+}
+
+impl Store {
+    /// Fill the store with licenses from a directory of plain text files.
+    ///
+    /// Each license is a `<ID>.txt` file, named for its identifier, as in
+    /// SPDX's `license-list-data/text` directory. Next to it may be:
+    ///
+    /// * `<ID>.header.txt`, the license's standard header. Several may be
+    ///   given as `<ID>.header.<anything>.txt`.
+    /// * `<ID>.alternate.txt`, another form of the license's text, likewise.
+    /// * `<ID>.short.txt`, a short form of the license, likewise. See
+    ///   `load_variants`.
+    /// * `<ID>.aliases`, other identifiers for the license, one per line.
+    ///   Blank lines and lines starting with `#` are ignored.
+    ///
+    /// Licenses whose text is identical to one already loaded are added as
+    /// an alias of it, as with `load_spdx`. SPDX's `deprecated_<ID>.txt`
+    /// files are skipped.
+    ///
+    /// `include_texts`, if true, will keep normalized license text data inside
+    /// the store, as with `load_spdx`.
+    pub fn load_text_dir(&mut self, dir: &Path, include_texts: bool) -> Result<(), Error> {
+        let files = text_files(dir)?;
+        let added = self.add_text_files(files, include_texts);
+
+        // licenses loaded before any failure are kept, so index them either way
+        self.rebuild_index();
+        added
+    }
+
+    /// Add alternate forms and headers to licenses already in the store, from
+    /// a directory of plain text files kept alongside the license data.
+    ///
+    /// Files are named as for `load_text_dir`, by the identifier (or an alias)
+    /// of the license they belong to. Alternates are named for their form:
+    ///
+    /// * `<ID>.alternate.reflowed.txt`, the text rewrapped or reformatted,
+    ///   such as a Markdown rendering.
+    /// * `<ID>.alternate.preamble.txt`, the text with the preamble projects
+    ///   commonly put before it.
+    ///
+    /// Further forms can be given any name, and several of one form can be
+    /// told apart with a suffix, as in `<ID>.alternate.preamble.2.txt`.
+    ///
+    /// Short forms, the sentence pasted into READMEs in place of the full
+    /// text, are `<ID>.short[.<anything>].txt`. Those of different licenses
+    /// often differ only in the license's name, so a short form only matches
+    /// a text that names its license: every word of the license's id, one of
+    /// its aliases, or its full name must appear in the text. Texts without
+    /// their lines kept (see `TextData::without_text`) never match one.
+    ///
+    /// Headers are `<ID>.header[.<anything>].txt`, and `<ID>.aliases` files
+    /// are read too. License texts (`<ID>.txt`) aren't allowed here; use
+    /// `load_text_dir` for those.
+    ///
+    /// `include_texts`, if true, will keep normalized license text data inside
+    /// the store, as with `load_spdx`.
+    pub fn load_variants(&mut self, dir: &Path, include_texts: bool) -> Result<(), Error> {
+        let files = text_files(dir)?;
+        if let Some((_, _, path)) = files.iter().find(|f| f.0 == FileKind::Original) {
+            bail!("{} isn't a header or alternate", path.display());
+        }
+        let added = self.add_variant_files(files, include_texts);
+
+        self.rebuild_index();
+        added
+    }
+
+    /// Add the licenses in a list of files, then their aliases, headers, and
+    /// alternates. The index is left for the caller to rebuild.
+    fn add_text_files(
+        &mut self,
+        files: Vec<(FileKind, String, PathBuf)>,
+        include_texts: bool,
+    ) -> Result<(), Error> {
+        let mut variants = Vec::new();
+        for (kind, id, path) in files {
+            if kind != FileKind::Original {
+                variants.push((kind, id, path));
+                continue;
+            }
+            let text = read_to_string(&path)?;
+            info!("Processing {}", id);
+            let content = self.text_data(&text, include_texts);
+            self.add_original(id, content);
+        }
+        self.add_variant_files(variants, include_texts)
+    }
+
+    /// Add the aliases, headers, and alternates in a list of files to the
+    /// licenses they name. The index is left for the caller to rebuild.
+    fn add_variant_files(
+        &mut self,
+        files: Vec<(FileKind, String, PathBuf)>,
+        include_texts: bool,
+    ) -> Result<(), Error> {
+        for (kind, id, path) in files {
+            let name = resolve(&self.licenses, &id)
+                .ok_or_else(|| format_err!("{} has no license text {}.txt", path.display(), id))?
+                .to_owned();
+            let text = read_to_string(&path)?;
+            if kind == FileKind::Aliases {
+                let entry = self.licenses.get_mut(&name).unwrap();
+                let aliases = text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'));
+                for alias in aliases {
+                    if !entry.aliases.iter().any(|a| a == alias) {
+                        entry.aliases.push(alias.to_owned());
+                    }
+                }
+                continue;
+            }
+
+            debug!("Processing {}", path.display());
+            let content = self.text_data(&text, include_texts);
+            let entry = self.licenses.get_mut(&name).unwrap();
+            match kind {
+                FileKind::Header => entry.headers.push(content),
+                FileKind::Short => entry.short_forms.push(content),
+                _ => entry.alternates.push(content),
+            }
+        }
+        Ok(())
+    }
+
+    /// Process a text for the store, adding it to the vocabulary.
+    pub(super) fn text_data(&mut self, text: &str, include_texts: bool) -> TextData {
+        let content = TextData::with_pipeline(text, &self.pipeline, self.ngram_size);
+        content.add_to_vocabulary(&mut self.vocabulary);
+        match include_texts {
+            true => content,
+            false => content.without_text(),
+        }
+    }
+
+    /// Add a license's text, or an alias if the store has the same text under
+    /// another name. The index is left for the caller to rebuild.
+    fn add_original(&mut self, name: String, content: TextData) {
+        let existing = self
+            .licenses
+            .iter_mut()
+            .find(|(_, entry)| entry.original.eq_data(&content));
+        if let Some((key, entry)) = existing {
+            info!("{} already stored; added as an alias for {}", name, key);
+            entry.aliases.push(name);
+            return;
+        }
+        self.licenses.insert(name, LicenseEntry::new(content));
+    }
+}
+
+/// List the files in a directory with an extension, sorted by name.
+#[cfg(any(feature = "spdx", feature = "scancode"))]
+pub(super) fn files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut paths: Vec<_> = read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension() == Some(std::ffi::OsStr::new(extension)))
+        .collect();
+
+    // sort without extensions; otherwise dashes and dots muck it up
+    paths.sort_by(|a, b| a.file_stem().unwrap().cmp(b.file_stem().unwrap()));
+    Ok(paths)
+}
+
+/// List the license files in a directory, originals first, so the rest have
+/// a license to go with.
+fn text_files(dir: &Path) -> Result<Vec<(FileKind, String, PathBuf)>, Error> {
+    let mut files = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if path.is_file() => name.to_owned(),
+            _ => continue,
+        };
+        match classify(&name) {
+            Some((kind, id)) => files.push((kind, id.to_owned(), path)),
+            None => debug!("Skipping {}", path.display()),
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Work out what a file holds and which license it's for from its name.
+fn classify(file_name: &str) -> Option<(FileKind, &str)> {
+    if let Some(id) = file_name.strip_suffix(".aliases") {
+        return Some((FileKind::Aliases, id));
+    }
+    let base = file_name.strip_suffix(".txt")?;
+    if base.starts_with("deprecated_") {
+        return None;
+    }
+    for &(marker, kind) in &[
+        (".header", FileKind::Header),
+        (".alternate", FileKind::Alternate),
+        (".short", FileKind::Short),
+    ] {
+        if let Some(i) = base.find(marker) {
+            let rest = &base[i + marker.len()..];
+            if rest.is_empty() || rest.starts_with('.') {
+                return Some((kind, &base[..i]));
+            }
+        }
+    }
+    Some((FileKind::Original, base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{license::LicenseType, store::test_dir::TestDir};
+
+    #[test]
+    fn files_are_classified() {
+        assert_eq!(
+            Some((FileKind::Original, "GPL-2.0")),
+            classify("GPL-2.0.txt")
+        );
+        assert_eq!(
+            Some((FileKind::Header, "GPL-2.0")),
+            classify("GPL-2.0.header.txt")
+        );
+        assert_eq!(
+            Some((FileKind::Alternate, "MIT")),
+            classify("MIT.alternate.reflowed.txt")
+        );
+        assert_eq!(Some((FileKind::Short, "MIT")), classify("MIT.short.txt"));
+        assert_eq!(Some((FileKind::Aliases, "MIT")), classify("MIT.aliases"));
+        assert_eq!(
+            Some((FileKind::Original, "X.headers")),
+            classify("X.headers.txt")
+        );
+        assert_eq!(None, classify("deprecated_GPL-2.0.txt"));
+        assert_eq!(None, classify("README.md"));
+    }
+
+    #[test]
+    fn text_dir_is_loaded() {
+        let files = [
+            (
+                "Internal-1.0.txt",
+                "you may use this only inside the company",
+            ),
+            (
+                "Internal-1.0.aliases",
+                "# older names\nInternal\n\nACME-Internal\n",
+            ),
+            (
+                "Internal-1.0.header.txt",
+                "licensed under the internal license",
+            ),
+            ("Internal-1.0.alternate.short.txt", "internal use only"),
+            (
+                "Internal-Copy.txt",
+                "you may use this only inside the company",
+            ),
+            ("deprecated_Old.txt", "an old license"),
+        ];
+        let dir = TestDir::new("text", &files);
+
+        let mut store = Store::new();
+        store.load_text_dir(dir.path(), true).unwrap();
+        assert_eq!(1, store.len());
+        assert_eq!(
+            vec!["Internal-Copy", "Internal", "ACME-Internal"],
+            *store.aliases("Internal-1.0").unwrap()
+        );
+        let entry = &store.licenses["Internal-1.0"];
+        assert_eq!(1, entry.headers.len());
+        assert_eq!(1, entry.alternates.len());
+        let found = store.analyze(&"internal use only".into());
+        assert_eq!(LicenseType::Alternate, found.license_type);
+
+        // what loaded before the failure is still indexed
+        dir.write("Nope.header.txt", "orphaned");
+        let mut store = Store::new();
+        assert!(store.load_text_dir(dir.path(), false).is_err());
+        let found = store.analyze(&"you may use this only inside the company".into());
+        assert_eq!(("Internal-1.0", 1.0), (found.name, found.score));
+    }
+
+    #[test]
+    fn variants_are_loaded() {
+        let files = [
+            (
+                "Internal-1.0.alternate.short.txt",
+                "this project is licensed under the internal license",
+            ),
+            (
+                "internal-1.0.alternate.preamble.txt",
+                "read this first. you may use this only inside the company",
+            ),
+            ("Internal-1.0.header.txt", "see the internal license file"),
+        ];
+        let dir = TestDir::new("variants", &files);
+
+        let mut store = Store::new();
+        store.add_license(
+            "Internal-1.0".into(),
+            "you may use this only inside the company".into(),
+        );
+        store.load_variants(dir.path(), true).unwrap();
+        assert_eq!(2, store.alternates("Internal-1.0").unwrap().len());
+        assert_eq!(1, store.headers("Internal-1.0").unwrap().len());
+        assert!(store.headers("Nope").is_err());
+        let found = store.analyze(&"licensed under the internal license".into());
+        assert_eq!(LicenseType::Alternate, found.license_type);
+
+        dir.write("Internal-2.0.txt", "a license text");
+        assert!(store.load_variants(dir.path(), true).is_err());
+    }
+
+    #[test]
+    fn shipped_variants_only_match_their_license() {
+        let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/datasets/variants"));
+        let mut store = Store::new();
+        // the reflowed forms stand in for SPDX's texts
+        for id in &["Apache-2.0", "BSD-2-Clause", "BSD-3-Clause", "MIT"] {
+            let path = dir.join(format!("{}.alternate.reflowed.txt", id));
+            store.add_license((*id).into(), read_to_string(path).unwrap().into());
+        }
+        store.load_variants(dir, false).unwrap();
+
+        for (id, text) in &[
+            (
+                "MIT",
+                "This project is licensed under the terms of the MIT license. See the LICENSE \
+                 file for details.",
+            ),
+            (
+                "Apache-2.0",
+                "Licensed under the Apache License, Version 2.0. See the LICENSE file for the \
+                 full text.",
+            ),
+            (
+                "BSD-2-Clause",
+                "This software is distributed under the terms of the 2-clause BSD license. See \
+                 the LICENSE file for the full text.",
+            ),
+        ] {
+            let found = store.analyze(&(*text).into());
+            assert_eq!((*id, LicenseType::Short), (found.name, found.license_type));
+            assert!(found.score >= 0.8, "{} scored {}", found.name, found.score);
+        }
+
+        // short forms of other licenses read much the same
+        for text in &[
+            "This project is licensed under the terms of the GPL license. See the LICENSE \
+             file for details.",
+            "This project is licensed under the terms of the ISC license. See the LICENSE \
+             file for details.",
+            "This software is distributed under the terms of the 4-clause BSD license \
+             (\"Original BSD License\"). See the LICENSE file for the full text.",
+        ] {
+            let found = store.analyze(&(*text).into());
+            assert!(found.score < 0.8, "{} scored {}", found.name, found.score);
+        }
+    }
+}
@@ -9,10 +9,24 @@ use log::{info, trace};
 use serde::Serialize;
 
 use crate::{
+    expression::{offers_choice, Expression},
+    gnu::{gnu_version, GnuVersion, NO_NOTICE_PENALTY},
     license::{LicenseType, TextData},
-    store::{Match, Store},
+    ngram::SimilarityMetric,
+    residual::Residual,
+    span::Span,
+    store::{split_with, Disambiguation, LicenseMetadata, Match, Store},
+    tag::SpdxTag,
 };
 
+/// How close in score other licenses need to be to the best match to be
+/// considered part of the same family during disambiguation.
+const FAMILY_WINDOW: f32 = 0.05;
+
+/// How many lines around the licenses found in a text are searched for
+/// wording that offers a choice between them.
+const CHOICE_WINDOW: usize = 10;
+
 /// A struct describing a license that was identified, as well as its type.
 #[derive(Serialize, Clone)]
 pub struct IdentifiedLicense<'a> {
@@ -22,6 +36,9 @@ pub struct IdentifiedLicense<'a> {
     pub kind: LicenseType,
     /// A reference to the license data inside the store.
     pub data: &'a TextData,
+    /// The license's descriptive metadata, such as its full name and OSI
+    /// approval, if the store has any. See `Store::metadata`.
+    pub metadata: Option<&'a LicenseMetadata>,
 }
 
 impl<'a> fmt::Debug for IdentifiedLicense<'a> {
@@ -29,6 +46,7 @@ impl<'a> fmt::Debug for IdentifiedLicense<'a> {
         f.debug_struct("IdentifiedLicense")
             .field("name", &self.name)
             .field("kind", &self.kind)
+            .field("metadata", &self.metadata)
             .finish()
     }
 }
@@ -43,8 +61,106 @@ pub struct ScanResult<'a> {
     /// The identified license of the overall text, or None if nothing met the
     /// confidence threshold.
     pub license: Option<IdentifiedLicense<'a>>,
+    /// Where the identified license is in the original text, if one was
+    /// identified. This covers the text's whole view, less any blank lines at
+    /// either end.
+    pub span: Option<Span>,
+    /// Sentences of the text that the identified license doesn't explain,
+    /// if `residuals` was enabled.
+    pub residuals: Vec<Residual>,
     /// Any licenses discovered inside the text, if `optimize` was enabled.
     pub containing: Vec<ContainedResult<'a>>,
+    /// Present if other licenses scored too close to the identified license
+    /// to reliably tell them apart. See `ScanStrategy.ambiguity_margin`.
+    pub ambiguity: Option<Ambiguity<'a>>,
+    /// Present if the identified license was chosen from a family of similar
+    /// licenses by looking at their distinguishing clauses. See
+    /// `ScanStrategy.disambiguate`.
+    pub disambiguation: Option<Disambiguation<'a>>,
+    /// Any `SPDX-License-Identifier` tags in the text, checked against the
+    /// store and the licenses identified above.
+    pub tags: Vec<TagResult<'a>>,
+    /// License exceptions found in the text, best first. These aren't
+    /// looked for unless the store has some; see `Store::add_exception`.
+    pub exceptions: Vec<IdentifiedException<'a>>,
+    /// The version terms of the identified license, if it's a GNU license
+    /// the store has in both `-only` and `-or-later` forms. If no notice was
+    /// found to decide them from, `score` is reduced. See `GnuVersion`.
+    pub gnu_version: Option<GnuVersion<'a>>,
+    /// Whether the text is an exact match for the identified license's SPDX
+    /// template, under the SPDX matching guidelines. Only checked if
+    /// `match_templates` was enabled and the store has a template for the
+    /// license.
+    pub template_match: Option<bool>,
+    /// An SPDX expression for all of the licenses identified, overall and
+    /// contained, with any exceptions found next to them. Several licenses
+    /// are joined with `OR` if the text near them offers a choice ("at your
+    /// option", "dual licensed under either"), and with `AND` otherwise.
+    pub expression: Option<Expression>,
+}
+
+/// An `SPDX-License-Identifier` tag found in a scanned text, checked against
+/// the store and the licenses identified from the text itself.
+#[derive(Serialize, Debug, Clone)]
+pub struct TagResult<'a> {
+    /// The tag as found in the text. Its ids are checked below even if its
+    /// expression couldn't be parsed.
+    pub tag: SpdxTag,
+    /// The license in the store each of the tag's license ids refers to, in
+    /// the same order as `tag.licenses`. An id is `None` if the store doesn't
+    /// know it, either because it's misspelled or not an SPDX id at all, or
+    /// because it's a `LicenseRef-` to a license of the document's own.
+    pub resolved: Vec<Option<&'a str>>,
+    /// The id that replaces each of the tag's license ids, in the same order
+    /// as `tag.licenses`, for ids that are deprecated. See `Store::successor`.
+    pub successors: Vec<Option<&'a str>>,
+    /// The exception in the store each of the tag's exception ids refers to,
+    /// in the same order as `tag.exceptions`.
+    pub resolved_exceptions: Vec<Option<&'a str>>,
+    /// Licenses identified from the text that the tag doesn't mention. A tag
+    /// and text that disagree like this deserve a closer look. A GNU license
+    /// whose version terms were decided from a notice is listed by its
+    /// `-only` or `-or-later` id, unless the tag names that same form.
+    pub contradictions: Vec<&'a str>,
+}
+
+/// A license exception found in a scanned text.
+#[derive(Serialize, Debug, Clone)]
+pub struct IdentifiedException<'a> {
+    /// The identifier of the exception.
+    pub name: &'a str,
+    /// The fraction of the exception's text found, from 0.0 to 1.0.
+    pub score: f32,
+    /// A 0-indexed (inclusive, exclusive) range of line numbers where the
+    /// exception was found, like `ContainedResult.line_range`.
+    pub line_range: (usize, usize),
+    /// Where the exception was found in the original text, if the text was
+    /// given to the scan.
+    pub span: Option<Span>,
+}
+
+/// A report of licenses that scored nearly the same against a text.
+///
+/// When this is present on a `ScanResult`, the reported license was picked
+/// from a near-tie, and may warrant a closer look by a human.
+#[derive(Serialize, Debug, Clone)]
+pub struct Ambiguity<'a> {
+    /// The difference in score between the identified license and the
+    /// next-best competing license. For a `ContainedResult`, this is negative
+    /// if a competitor scored better within the license's line range.
+    pub margin: f32,
+    /// All licenses that scored within the strategy's ambiguity margin of the
+    /// best match, including the best match itself, in descending order.
+    pub candidates: Vec<ScoredLicense<'a>>,
+}
+
+/// A license paired with the score it achieved against a text.
+#[derive(Serialize, Debug, Clone)]
+pub struct ScoredLicense<'a> {
+    /// The confidence of the match from 0.0 to 1.0.
+    pub score: f32,
+    /// The license that was scored.
+    pub license: IdentifiedLicense<'a>,
 }
 
 /// A struct describing a single license identified within a larger text.
@@ -59,6 +175,26 @@ pub struct ContainedResult<'a> {
     ///
     /// See `TextData.lines_view()` for more information.
     pub line_range: (usize, usize),
+    /// Where the license was identified in the original text, with byte,
+    /// char, and column precision. This is `None` only if the text given to
+    /// the scan was stripped of its text.
+    pub span: Option<Span>,
+    /// Sentences within the line range that the license doesn't explain, if
+    /// `residuals` was enabled.
+    pub residuals: Vec<Residual>,
+    /// The version terms of the license, decided from the lines around it.
+    /// See `ScanResult.gnu_version`.
+    pub gnu_version: Option<GnuVersion<'a>>,
+    /// Whether the line range is an exact match for the license's SPDX
+    /// template. See `ScanResult.template_match`.
+    pub template_match: Option<bool>,
+    /// Present if other licenses scored too close to this one within the
+    /// line range to reliably tell them apart. See `ScanResult.ambiguity`.
+    pub ambiguity: Option<Ambiguity<'a>>,
+    /// Present if this license was chosen from a family of similar licenses
+    /// by their distinguishing clauses within the line range. See
+    /// `ScanResult.disambiguation`.
+    pub disambiguation: Option<Disambiguation<'a>>,
 }
 
 /// A `ScanStrategy` can be used as a high-level wrapped over a `Store`'s
@@ -92,6 +228,11 @@ pub struct ScanStrategy<'a> {
     optimize: bool,
     max_passes: u16,
     step_size: usize,
+    ambiguity_margin: f32,
+    disambiguate: bool,
+    metric: SimilarityMetric,
+    residuals: bool,
+    match_templates: bool,
 }
 
 /// Available scanning strategy modes.
@@ -121,6 +262,11 @@ impl<'a> ScanStrategy<'a> {
             optimize: false,
             max_passes: 10,
             step_size: 5,
+            ambiguity_margin: 0.01,
+            disambiguate: true,
+            metric: SimilarityMetric::Dice,
+            residuals: false,
+            match_templates: false,
         }
     }
 
@@ -192,38 +338,335 @@ impl<'a> ScanStrategy<'a> {
         self
     }
 
+    /// Configure how close other licenses must score to the best match for
+    /// the result to be flagged as ambiguous.
+    ///
+    /// If any other license scores within this margin of the identified
+    /// license, the `ScanResult` will carry an `Ambiguity` report listing the
+    /// competitors. The default is 0.01; set this to a negative value to
+    /// disable the check entirely.
+    pub fn ambiguity_margin(mut self, ambiguity_margin: f32) -> Self {
+        self.ambiguity_margin = ambiguity_margin;
+        self
+    }
+
+    /// Indicate whether closely-related licenses should be told apart by the
+    /// clauses that distinguish them.
+    ///
+    /// Licenses that differ by a single clause (such as BSD-3-Clause and
+    /// BSD-4-Clause) can score nearly the same. With this enabled, licenses
+    /// scoring close to the best match are compared phrase-by-phrase, and the
+    /// one whose unique clauses are actually present in the text wins. The
+    /// decision is reported in `ScanResult.disambiguation`, or for licenses
+    /// found within a text (and all of those found by `ScanMode::TopDown`),
+    /// `ContainedResult.disambiguation`. This is enabled by default.
+    pub fn disambiguate(mut self, disambiguate: bool) -> Self {
+        self.disambiguate = disambiguate;
+        self
+    }
+
+    /// Set the similarity metric used to compare texts against the store.
+    ///
+    /// The default is `SimilarityMetric::Dice`. Other metrics produce scores
+    /// on a different scale, so the confidence threshold may need adjusting
+    /// too. `SimilarityMetric::Containment` is handy with `Elimination` mode
+    /// to spot a license inside a larger file without optimizing, but isn't
+    /// suited to `TopDown`, where any small window of a license would match
+    /// perfectly.
+    ///
+    /// Locating a license within a text with `optimize` always uses Dice.
+    pub fn metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Indicate whether to report the sentences of a text that an identified
+    /// license doesn't explain.
+    ///
+    /// This catches things like custom riders attached to standard licenses,
+    /// which barely move the score. The sentences are reported in
+    /// `ScanResult.residuals` and `ContainedResult.residuals`; see
+    /// `TextData::residuals` for how they're found. Removed sentences are
+    /// only reported if the store kept the text of its licenses.
+    pub fn residuals(mut self, residuals: bool) -> Self {
+        self.residuals = residuals;
+        self
+    }
+
+    /// Indicate whether to check identified licenses against their SPDX
+    /// templates, for an exact match under the SPDX matching guidelines.
+    ///
+    /// Scores say how close a text is to a license; this says whether it's a
+    /// copy of it, with only the differences the guidelines allow -- a
+    /// copyright holder filled in, an optional clause left out. The verdict
+    /// is reported in `ScanResult.template_match` and
+    /// `ContainedResult.template_match`, for licenses the store has a
+    /// template for and only where the match is against a license's full
+    /// text, not a header or alternate. This needs the text of the scanned
+    /// content.
+    pub fn match_templates(mut self, match_templates: bool) -> Self {
+        self.match_templates = match_templates;
+        self
+    }
+
     /// Scan the given text content using this strategy's configured
     /// preferences.
     ///
     /// Returns a `ScanResult` containing all discovered information.
     pub fn scan(&self, text: &TextData) -> Result<ScanResult, Error> {
-        match self.mode {
-            ScanMode::Elimination => Ok(self.scan_elimination(text)),
-            ScanMode::TopDown => Ok(self.scan_topdown(text)),
+        // compare with n-grams of the same size as the store's licenses
+        let text = self.store.conform(text);
+        let mut result = match self.mode {
+            ScanMode::Elimination => self.scan_elimination(&text),
+            ScanMode::TopDown => self.scan_topdown(&text),
+        };
+        self.resolve_gnu_versions(&text, &mut result);
+        if self.match_templates {
+            self.check_templates(&text, &mut result);
+        }
+        result.tags = self.check_tags(&text, &result);
+        result.exceptions = self.find_exceptions(&text);
+        result.expression = propose_expression(&text, &result);
+        Ok(result)
+    }
+
+    /// Decide the version terms of any GNU licenses identified, reducing the
+    /// scores of those without a notice to decide them from.
+    fn resolve_gnu_versions<'b>(&'b self, text: &TextData, result: &mut ScanResult<'b>) {
+        if let Some(ref mut license) = result.license {
+            result.gnu_version =
+                gnu_version(self.store, text, license.name, license.kind, license.data);
+            if let Some(ref version) = result.gnu_version {
+                // `-or-later` may have a name of its own
+                license.metadata = self.store.metadata(version.id);
+                if !version.from_notice {
+                    result.score *= NO_NOTICE_PENALTY;
+                }
+            }
+        }
+
+        for contained in &mut result.containing {
+            // a notice usually sits just above or below the license text
+            let nearby = match text.text_processed() {
+                Some(_) => {
+                    let (start, end) = contained.line_range;
+                    let end = (end + CHOICE_WINDOW).min(text.lines().len());
+                    text.with_view(start.saturating_sub(CHOICE_WINDOW), end)
+                }
+                None => text.clone(),
+            };
+            let license = &contained.license;
+            contained.gnu_version = gnu_version(
+                self.store,
+                &nearby,
+                license.name,
+                license.kind,
+                license.data,
+            );
+            if let Some(ref version) = contained.gnu_version {
+                contained.license.metadata = self.store.metadata(version.id);
+                if !version.from_notice {
+                    contained.score *= NO_NOTICE_PENALTY;
+                }
+            }
+        }
+    }
+
+    /// Check the licenses identified in a text against their templates.
+    fn check_templates(&self, text: &TextData, result: &mut ScanResult<'_>) {
+        let original = match text.original() {
+            Some(original) => original,
+            None => return,
+        };
+        // templates are of the full license text, so only a match against
+        // that can be checked
+        let check = |license: &IdentifiedLicense<'_>, span: Option<Span>| {
+            if license.kind != LicenseType::Original {
+                return None;
+            }
+            let template = self.store.template(license.name)?;
+            Some(template.matches(&original[span?.bytes()]))
+        };
+
+        if let Some(ref license) = result.license {
+            result.template_match = check(license, result.span);
+        }
+        for contained in &mut result.containing {
+            contained.template_match = check(&contained.license, contained.span);
+        }
+    }
+
+    /// Look for the store's license exceptions in a text, narrowing down
+    /// where each one is if the text is there to search.
+    fn find_exceptions<'b>(&'b self, text: &TextData) -> Vec<IdentifiedException<'b>> {
+        self.store
+            .analyze_exceptions(text)
+            .into_iter()
+            .take_while(|m| m.score > self.confidence_threshold)
+            .map(|m| {
+                let line_range = match text.text_processed() {
+                    Some(_) => text.optimize_bounds(m.data).0.lines_view(),
+                    None => text.lines_view(),
+                };
+                info!("Found exception {} at {:?}", m.name, line_range);
+                IdentifiedException {
+                    name: m.name,
+                    score: m.score,
+                    line_range,
+                    span: text.span(line_range),
+                }
+            })
+            .collect()
+    }
+
+    /// Find the SPDX tags of a text, and check them against the store and
+    /// the licenses found in the text.
+    fn check_tags<'b>(&'b self, text: &TextData, result: &ScanResult<'b>) -> Vec<TagResult<'b>> {
+        // each license found, with its version terms if a notice decided them
+        // (an assumed `-only` is no reason to doubt a tag)
+        let found: Vec<(&'b str, Option<&'b str>)> = result
+            .license
+            .iter()
+            .map(|license| (license, &result.gnu_version))
+            .chain(
+                result
+                    .containing
+                    .iter()
+                    .map(|c| (&c.license, &c.gnu_version)),
+            )
+            .map(|(license, version)| {
+                let decided = version.as_ref().filter(|v| v.from_notice).map(|v| v.id);
+                (license.name, decided)
+            })
+            .collect();
+
+        text.spdx_tags()
+            .into_iter()
+            .map(|tag| {
+                let resolved: Vec<Option<&'b str>> = tag
+                    .licenses
+                    .iter()
+                    .map(|id| self.store.resolve_id(id))
+                    .collect();
+                let successors = tag
+                    .licenses
+                    .iter()
+                    .map(|id| self.store.successor(id))
+                    .collect();
+                let terms: Vec<String> = tag.licenses.iter().map(|id| self.gnu_term(id)).collect();
+                let mut contradictions: Vec<&'b str> = Vec::new();
+                for &(name, version) in &found {
+                    let (mentioned, id) = match version {
+                        Some(id) => (terms.iter().any(|t| t.eq_ignore_ascii_case(id)), id),
+                        None => (resolved.contains(&Some(name)), name),
+                    };
+                    if !mentioned && !contradictions.contains(&id) {
+                        contradictions.push(id);
+                    }
+                }
+                let resolved_exceptions = tag
+                    .exceptions
+                    .iter()
+                    .map(|id| self.store.resolve_exception_id(id))
+                    .collect();
+                TagResult {
+                    tag,
+                    resolved,
+                    successors,
+                    resolved_exceptions,
+                    contradictions,
+                }
+            })
+            .collect()
+    }
+
+    /// Spell out the version terms a tag's license id stands for, folding
+    /// deprecated ids and `+` into their `-only` or `-or-later` forms, as in
+    /// `Expression::resolve`.
+    fn gnu_term(&self, id: &str) -> String {
+        // a successor may come with an exception, which doesn't matter here
+        if let Some(successor) = self.store.successor(id) {
+            return split_with(successor).0.to_owned();
+        }
+        let (id, or_later) = match id.strip_suffix('+') {
+            Some(base) => (base, true),
+            None => (id, false),
+        };
+        let id = self.store.successor(id).map_or(id, |s| split_with(s).0);
+        match id.strip_suffix("-only") {
+            Some(base) if or_later => format!("{}-or-later", base),
+            _ => id.to_owned(),
         }
     }
 
     fn scan_elimination(&self, text: &TextData) -> ScanResult {
-        let mut analysis = self.store.analyze(text);
-        let score = analysis.score;
+        // a verbatim copy of a license needs no scoring; only other licenses
+        // with the very same text can tie with it
+        let mut candidates = self.store.find_exact_all(text);
+        if candidates.is_empty() {
+            candidates = self.store.analyze_candidates(text, self.metric);
+        }
+        let mut analysis = candidates[0].clone();
+        let mut score = analysis.score;
         let mut license = None;
+        let mut span = None;
+        let mut residuals = Vec::new();
         let mut containing = Vec::new();
+        let mut ambiguity = None;
+        let mut disambiguation = None;
         info!("Elimination top-level analysis: {:?}", analysis);
 
         // meets confidence threshold? record that
         if analysis.score > self.confidence_threshold {
+            // check if similar licenses are better told apart by their clauses
+            if self.disambiguate {
+                let family: Vec<Match<'_>> = candidates
+                    .iter()
+                    .take_while(|m| {
+                        m.score > self.confidence_threshold && score - m.score <= FAMILY_WINDOW
+                    })
+                    .cloned()
+                    .collect();
+                disambiguation = self.store.disambiguate(text, &family);
+                if let Some(ref resolved) = disambiguation {
+                    info!("Disambiguated by clauses: {:?}", resolved);
+                    analysis = family
+                        .into_iter()
+                        .find(|m| m.name == resolved.name)
+                        .expect("resolved license is part of the family");
+                    score = analysis.score;
+                }
+            }
+
             license = Some(IdentifiedLicense {
                 name: analysis.name,
                 kind: analysis.license_type,
                 data: analysis.data,
+                metadata: self.store.metadata(analysis.name),
             });
+            span = text.span(text.lines_view());
+            residuals = self.find_residuals(text, analysis.data);
+
+            // a decision made by clauses is a better tie-breaker than nothing
+            if disambiguation.is_none() {
+                ambiguity = self.find_ambiguity(&candidates);
+            }
 
             // above the shallow limit -> exit
             if analysis.score > self.shallow_limit {
                 return ScanResult {
                     score,
                     license,
+                    span,
+                    residuals,
                     containing,
+                    ambiguity,
+                    disambiguation,
+                    tags: Vec::new(),
+                    exceptions: Vec::new(),
+                    gnu_version: None,
+                    template_match: None,
+                    expression: None,
                 };
             }
         }
@@ -247,27 +690,94 @@ impl<'a> ScanStrategy<'a> {
                     optimized.lines_view().0,
                     optimized.lines_view().1
                 );
-                containing.push(ContainedResult {
-                    score: optimized_score,
-                    license: IdentifiedLicense {
-                        name: analysis.name,
-                        kind: analysis.license_type,
-                        data: analysis.data,
-                    },
-                    line_range: optimized.lines_view(),
-                });
+                containing.push(self.contained_result(&optimized, &analysis, optimized_score));
 
                 // and white-out + reanalyze for next iteration
                 current_text = Cow::Owned(optimized.white_out());
-                analysis = self.store.analyze(&current_text);
+                analysis = self.store.analyze_with(&current_text, self.metric);
             }
         }
 
         ScanResult {
             score,
             license,
+            span,
+            residuals,
             containing,
+            ambiguity,
+            disambiguation,
+            tags: Vec::new(),
+            exceptions: Vec::new(),
+            gnu_version: None,
+            template_match: None,
+            expression: None,
+        }
+    }
+
+    /// Find the residual sentences of a match, if asked to and if the text
+    /// is there to find them in.
+    fn find_residuals(&self, text: &TextData, license: &TextData) -> Vec<Residual> {
+        match self.residuals && text.text_processed().is_some() {
+            true => text.residuals(license),
+            false => Vec::new(),
+        }
+    }
+
+    /// Check whether any runner-up candidates scored within the ambiguity
+    /// margin of the best match. `candidates` must be sorted best-first.
+    fn find_ambiguity<'b>(&'b self, candidates: &[Match<'b>]) -> Option<Ambiguity<'b>> {
+        let best = candidates.first()?.score;
+        let close: Vec<ScoredLicense<'b>> = candidates
+            .iter()
+            .take_while(|m| best - m.score <= self.ambiguity_margin)
+            .map(|m| ScoredLicense {
+                score: m.score,
+                license: IdentifiedLicense {
+                    name: m.name,
+                    kind: m.license_type,
+                    data: m.data,
+                    metadata: self.store.metadata(m.name),
+                },
+            })
+            .collect();
+
+        if close.len() < 2 {
+            return None;
         }
+
+        info!("Ambiguous match between {} licenses", close.len());
+        Some(Ambiguity {
+            margin: best - close[1].score,
+            candidates: close,
+        })
+    }
+
+    /// Check whether other licenses score within the ambiguity margin of a
+    /// license located within a text, comparing against just the lines it
+    /// was found in.
+    fn find_contained_ambiguity<'b>(
+        &'b self,
+        optimized: &TextData,
+        matched: &Match<'b>,
+        score: f32,
+    ) -> Option<Ambiguity<'b>> {
+        if self.ambiguity_margin < 0.0 {
+            return None;
+        }
+        // locating a license is done with dice, so compare with it too; the
+        // located license goes first with the score it was reported with
+        let mut candidates = self
+            .store
+            .analyze_candidates(optimized, SimilarityMetric::Dice);
+        candidates.retain(|m| m.name != matched.name);
+        candidates.insert(
+            0,
+            Match {
+                score,
+                ..matched.clone()
+            },
+        );
+        self.find_ambiguity(&candidates)
     }
 
     fn scan_topdown(&self, text: &TextData) -> ScanResult {
@@ -291,7 +801,16 @@ impl<'a> ScanStrategy<'a> {
         ScanResult {
             score: 0.0,
             license: None,
+            span: None,
+            residuals: Vec::new(),
             containing,
+            ambiguity: None,
+            disambiguation: None,
+            tags: Vec::new(),
+            exceptions: Vec::new(),
+            gnu_version: None,
+            template_match: None,
+            expression: None,
         }
     }
 
@@ -316,7 +835,7 @@ impl<'a> ScanStrategy<'a> {
             // ...and also the end of window to find high scores.
             for end in (start..=text_end).step_by(self.step_size) {
                 let view = text.with_view(start, end);
-                let analysis = self.store.analyze(&view);
+                let analysis = self.store.analyze_with(&view, self.metric);
 
                 // just getting a feel for the data at this point, not yet
                 // optimizing the view.
@@ -371,21 +890,162 @@ impl<'a> ScanStrategy<'a> {
             return None;
         }
 
-        Some(ContainedResult {
-            score: optimized_score,
+        Some(self.contained_result(&optimized, &matched, optimized_score))
+    }
+
+    /// Report a license located within a text, first telling it apart from
+    /// any similar licenses by their clauses in the lines it was found in.
+    fn contained_result<'b>(
+        &'b self,
+        optimized: &TextData,
+        matched: &Match<'b>,
+        score: f32,
+    ) -> ContainedResult<'b> {
+        let located = Match {
+            score,
+            ..matched.clone()
+        };
+        let (located, disambiguation) = if self.disambiguate {
+            self.disambiguate_contained(optimized, located)
+        } else {
+            (located, None)
+        };
+
+        // as at the top level, a decision made by clauses settles ambiguity
+        let mut ambiguity = None;
+        if disambiguation.is_none() {
+            ambiguity = self.find_contained_ambiguity(optimized, &located, located.score);
+        }
+        ContainedResult {
+            score: located.score,
             license: IdentifiedLicense {
-                name: matched.name,
-                kind: matched.license_type,
-                data: matched.data,
+                name: located.name,
+                kind: located.license_type,
+                data: located.data,
+                metadata: self.store.metadata(located.name),
             },
             line_range: optimized.lines_view(),
+            span: optimized.span(optimized.lines_view()),
+            residuals: self.find_residuals(optimized, located.data),
+            gnu_version: None,
+            template_match: None,
+            ambiguity,
+            disambiguation,
+        }
+    }
+
+    /// Tell a license located within a text apart from any licenses similar
+    /// enough to it by their clauses, comparing against just the lines it was
+    /// found in. Returns the license the clauses point to, or the located one
+    /// if they don't settle it.
+    fn disambiguate_contained<'b>(
+        &'b self,
+        optimized: &TextData,
+        located: Match<'b>,
+    ) -> (Match<'b>, Option<Disambiguation<'b>>) {
+        // locating a license is done with dice, so compare with it too
+        let mut family = vec![located.clone()];
+        family.extend(
+            self.store
+                .analyze_candidates(optimized, SimilarityMetric::Dice)
+                .into_iter()
+                .filter(|m| m.name != located.name)
+                .take_while(|m| {
+                    m.score > self.confidence_threshold && located.score - m.score <= FAMILY_WINDOW
+                }),
+        );
+        let disambiguation = self.store.disambiguate(optimized, &family);
+        match disambiguation {
+            Some(ref resolved) => {
+                info!("Disambiguated contained license by clauses: {:?}", resolved);
+                let resolved = family
+                    .into_iter()
+                    .find(|m| m.name == resolved.name)
+                    .expect("resolved license is part of the family");
+                (resolved, disambiguation)
+            }
+            None => (located, None),
+        }
+    }
+}
+
+/// Combine the licenses found in a text into an expression, looking for
+/// wording near them that offers a choice between them. Each exception found
+/// is attached to the nearest license that doesn't have one yet.
+fn propose_expression(text: &TextData, result: &ScanResult<'_>) -> Option<Expression> {
+    let mut found: Vec<(&str, (usize, usize), Option<&str>)> = Vec::new();
+    fn id<'a>(name: &'a str, version: &Option<GnuVersion<'a>>) -> &'a str {
+        version.as_ref().map_or(name, |v| v.id)
+    }
+    let overall = result
+        .license
+        .iter()
+        .map(|l| (id(l.name, &result.gnu_version), text.lines_view()));
+    let contained = result
+        .containing
+        .iter()
+        .map(|c| (id(c.license.name, &c.gnu_version), c.line_range));
+    for (name, range) in overall.chain(contained) {
+        if !found.iter().any(|f| f.0 == name) {
+            found.push((name, range, None));
+        }
+    }
+
+    for exception in &result.exceptions {
+        let (start, end) = exception.line_range;
+        let distance = |range: (usize, usize)| match range {
+            (_, e) if e <= start => start - e,
+            (s, _) if s >= end => s - end,
+            _ => 0,
+        };
+        let nearest = found
+            .iter_mut()
+            .filter(|f| f.2.is_none())
+            .min_by_key(|f| distance(f.1));
+        if let Some(license) = nearest {
+            license.2 = Some(exception.name);
+        }
+    }
+
+    let ranges: Vec<(usize, usize)> = result.containing.iter().map(|c| c.line_range).collect();
+    let mut terms: Vec<Expression> = found
+        .into_iter()
+        .map(|(name, _, exception)| Expression::License {
+            id: name.to_owned(),
+            or_later: false,
+            exception: exception.map(str::to_owned),
         })
+        .collect();
+    if terms.len() < 2 {
+        return terms.pop();
     }
+
+    // licenses themselves may offer options ("or, at your option, any later
+    // version"), so only look at the lines around them
+    let first = ranges.iter().map(|r| r.0).min().unwrap_or(0);
+    let last = ranges.iter().map(|r| r.1).max().unwrap_or(0);
+    let window = first.saturating_sub(CHOICE_WINDOW)..last + CHOICE_WINDOW;
+    let nearby: Vec<&str> = text
+        .original()
+        .unwrap_or("")
+        .split('\n')
+        .enumerate()
+        .filter(|(line, _)| {
+            window.contains(line) && !ranges.iter().any(|r| (r.0..r.1).contains(line))
+        })
+        .map(|(_, content)| content)
+        .collect();
+
+    Some(match offers_choice(&nearby.join("\n")) {
+        true => Expression::Or(terms),
+        false => Expression::And(terms),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ngram::NgramSize, residual::ResidualKind};
     use env_logger;
 
     #[test]
@@ -538,8 +1198,495 @@ mod tests {
         );
     }
 
-    fn create_dummy_store() -> Store {
+    #[test]
+    fn ambiguous_matches() {
+        let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc ddddd".into());
+        store.add_license("license-2".into(), "aaaaa bbbbb ccccc eeeee".into());
+        let test_data = TextData::new("aaaaa bbbbb ccccc");
+
+        // both licenses are equally good (or bad) matches for this
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+        let result = strategy.scan(&test_data).unwrap();
+        let ambiguity = result.ambiguity.expect("result is ambiguous");
+        assert_eq!(0.0, ambiguity.margin);
+        assert_eq!(2, ambiguity.candidates.len());
+
+        // an exact match is clear enough
+        let test_data = TextData::new("aaaaa bbbbb ccccc ddddd");
+        let result = strategy.scan(&test_data).unwrap();
+        assert!(result.ambiguity.is_none(), "result is not ambiguous");
+
+        // unless another license has the same text
+        store.add_license("license-0".into(), "aaaaa bbbbb ccccc ddddd".into());
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+        let result = strategy.scan(&test_data).unwrap();
+        assert_eq!("license-0", result.license.unwrap().name);
+        let ambiguity = result.ambiguity.expect("identical texts are ambiguous");
+        assert_eq!(2, ambiguity.candidates.len());
+
+        // and the check can be switched off
+        let strategy = ScanStrategy::new(&store)
+            .confidence_threshold(0.5)
+            .ambiguity_margin(-1.0);
+        let result = strategy.scan(&TextData::new("aaaaa bbbbb ccccc")).unwrap();
+        assert!(result.ambiguity.is_none(), "ambiguity check is disabled");
+    }
+
+    #[test]
+    fn contained_matches_can_be_ambiguous() {
         let mut store = Store::new();
+        store.add_license("license-1".into(), "aaaaa bbbbb ccccc ddddd".into());
+        store.add_license("license-2".into(), "aaaaa bbbbb ccccc eeeee".into());
+        let test_data =
+            TextData::new("lorem ipsum dolor\nsit amet\naaaaa bbbbb ccccc\nconsectetur");
+
+        let elimination = ScanStrategy::new(&store)
+            .confidence_threshold(0.5)
+            .optimize(true)
+            .shallow_limit(1.0);
+        let topdown = ScanStrategy::new(&store)
+            .mode(ScanMode::TopDown)
+            .confidence_threshold(0.5)
+            .step_size(1);
+        for strategy in &[&elimination, &topdown] {
+            let result = strategy.scan(&test_data).unwrap();
+            assert!(result.license.is_none());
+            let contained = &result.containing[0];
+            assert_eq!((2, 3), contained.line_range);
+            let ambiguity = contained.ambiguity.as_ref().expect("match is ambiguous");
+            assert_eq!(0.0, ambiguity.margin);
+            assert_eq!(contained.license.name, ambiguity.candidates[0].license.name);
+            assert_eq!(2, ambiguity.candidates.len());
+        }
+
+        // one that stands apart isn't
+        let test_data =
+            TextData::new("lorem ipsum dolor\nsit amet\naaaaa bbbbb ccccc ddddd\nconsectetur");
+        let result = elimination.scan(&test_data).unwrap();
+        let contained = &result.containing[0];
+        assert_eq!("license-1", contained.license.name);
+        assert!(contained.ambiguity.is_none());
+    }
+
+    #[test]
+    fn disambiguate_by_clauses() {
+        let mut store = Store::new();
+        let base = "redistribution and use in source and binary forms with or without \
+                    modification are permitted provided that the following conditions \
+                    are met redistributions of source code must retain the above notice \
+                    this list of conditions and the following disclaimer redistributions \
+                    in binary form must reproduce the above notice this list of conditions \
+                    and the following disclaimer in the documentation and or other \
+                    materials provided with the distribution this software is provided by \
+                    the holders and contributors as is and any express or \
+                    implied warranties including but not limited to the implied warranties \
+                    of merchantability and fitness for a particular purpose are disclaimed";
+        store.add_license("license-3".into(), base.into());
+        store.add_license(
+            "license-4".into(),
+            format!(
+                "{} all advertising materials mentioning features must display an acknowledgement",
+                base
+            )
+            .into(),
+        );
+
+        // a slightly mangled copy of license-4 scores well against both...
+        let test_data = TextData::new(&format!(
+            "{} and all advertising materials mentioning features must display an acknowledgement",
+            base.replace("binary forms", "binary")
+        ));
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+        let candidates = store.analyze_top(&test_data, 2);
+        assert!(candidates[0].score - candidates[1].score < FAMILY_WINDOW);
+
+        // ...but the advertising clause gives it away
+        let result = strategy.scan(&test_data).unwrap();
+        assert_eq!("license-4", result.license.unwrap().name);
+        let resolved = result.disambiguation.expect("result was disambiguated");
+        assert_eq!("license-4", resolved.name);
+        assert_eq!(2, resolved.considered.len());
+        assert!(resolved.present[0].contains("advertising materials"));
+        assert!(result.ambiguity.is_none());
+
+        // and the reverse: no advertising clause means license-3
+        let test_data = TextData::new(&base.replace("binary forms", "binary"));
+        let result = strategy.scan(&test_data).unwrap();
+        assert_eq!("license-3", result.license.unwrap().name);
+
+        // turning it off falls back to plain scoring
+        let strategy = strategy.disambiguate(false);
+        let result = strategy.scan(&test_data).unwrap();
+        assert!(result.disambiguation.is_none());
+    }
+
+    #[test]
+    fn disambiguate_contained_licenses() {
+        let mut store = Store::new();
+        let base = "redistribution and use in source and binary forms with or without \
+                    modification are permitted provided that the following conditions \
+                    are met redistributions of source code must retain the above notice \
+                    this list of conditions and the following disclaimer redistributions \
+                    in binary form must reproduce the above notice this list of conditions \
+                    and the following disclaimer in the documentation and or other \
+                    materials provided with the distribution this software is provided by \
+                    the holders and contributors as is and any express or \
+                    implied warranties including but not limited to the implied warranties \
+                    of merchantability and fitness for a particular purpose are disclaimed";
+        store.add_license("license-3".into(), base.into());
+        store.add_license(
+            "license-4".into(),
+            format!(
+                "{} all advertising materials mentioning features must display an acknowledgement",
+                base
+            )
+            .into(),
+        );
+
+        // a mangled license-4 surrounded by unrelated lines
+        let test_data = TextData::new(&format!(
+            "lorem ipsum dolor sit amet\nconsectetur adipiscing elit\n{}\nand all advertising \
+             materials mentioning features must display an acknowledgement\nsed do eiusmod \
+             tempor incididunt\nut labore et dolore magna aliqua",
+            base.replace("binary forms", "binary")
+        ));
+
+        let elimination = ScanStrategy::new(&store)
+            .confidence_threshold(0.5)
+            .optimize(true)
+            .shallow_limit(1.0);
+        let topdown = ScanStrategy::new(&store)
+            .mode(ScanMode::TopDown)
+            .confidence_threshold(0.5)
+            .step_size(1);
+        for strategy in &[&elimination, &topdown] {
+            let result = strategy.scan(&test_data).unwrap();
+            let contained = &result.containing[0];
+            assert_eq!("license-4", contained.license.name);
+            let resolved = contained
+                .disambiguation
+                .as_ref()
+                .expect("contained result was disambiguated");
+            assert_eq!("license-4", resolved.name);
+            assert!(resolved.present[0].contains("advertising materials"));
+            assert!(contained.ambiguity.is_none());
+        }
+    }
+
+    #[test]
+    fn containment_finds_embedded_license() {
+        let store = create_dummy_store();
+        let test_data =
+            TextData::new("lorem\nipsum abc def ghi jkl\n1234 5678 1234\n0000\n1010101010\n\n8888 9999\nwhatsit hello\narst neio qwfp colemak is the best keyboard layout");
+
+        // dice doesn't see this without optimizing...
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.9);
+        let result = strategy.scan(&test_data).unwrap();
+        assert!(result.license.is_none(), "result license is None");
+
+        // ...but containment does
+        let strategy = strategy.metric(SimilarityMetric::Containment);
+        let result = strategy.scan(&test_data).unwrap();
+        assert_eq!(
+            result.license.expect("result has a license").name,
+            "license-2"
+        );
+    }
+
+    #[test]
+    fn spans_point_into_original_text() {
+        let store = create_dummy_store();
+        let text = "lorem\r\n  ipsum\r\n\r\n    aaaaa\r\n    bbbbb\r\n    ccccc\r\n\r\nhello";
+        let test_data = TextData::new(text);
+
+        let strategy = ScanStrategy::new(&store)
+            .confidence_threshold(0.5)
+            .optimize(true)
+            .shallow_limit(1.0);
+        let result = strategy.scan(&test_data).unwrap();
+        let contained = &result.containing[0];
+        assert_eq!("license-1", contained.license.name);
+        let span = contained.span.expect("sample has text");
+        assert_eq!("aaaaa\r\n    bbbbb\r\n    ccccc", &text[span.bytes()]);
+        assert_eq!((3, 4), (span.start.line, span.start.column));
+        assert_eq!((5, 9), (span.end.line, span.end.column));
+
+        // an overall match spans the whole text, trimmed
+        let text = "\n  aaaaa\n  bbbbb ccccc  \n";
+        let result = strategy.scan(&TextData::new(text)).unwrap();
+        assert!(result.license.is_some());
+        assert_eq!("aaaaa\n  bbbbb ccccc", &text[result.span.unwrap().bytes()]);
+    }
+
+    #[test]
+    fn scan_with_store_ngram_size() {
+        let test_data =
+            TextData::new("lorem\nipsum abc def ghi jkl\n1234 5678 1234\n0000\n1010101010\n\n8888 9999\nwhatsit hello\naaaaa\nbbbbb\nccccc");
+
+        for size in &[
+            NgramSize::Fixed(1),
+            NgramSize::Fixed(3),
+            NgramSize::MultiResolution,
+        ] {
+            let store = create_dummy_store_sized(*size);
+            let strategy = ScanStrategy::new(&store)
+                .confidence_threshold(0.5)
+                .optimize(true)
+                .shallow_limit(1.0);
+            let result = strategy.scan(&test_data).unwrap();
+            let mut found: Vec<&str> = result.containing.iter().map(|c| c.license.name).collect();
+            found.sort();
+            assert_eq!(vec!["license-1", "license-2"], found, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn residuals_are_opt_in() {
+        let mut store = Store::new();
+        let license = "permission is granted to use this software for any purpose. \
+                       the software is provided as is without warranty of any kind.";
+        store.add_license("license-5".into(), license.into());
+        let text = format!("{}\n\nno use in spacecraft is permitted.", license);
+        let test_data = TextData::new(&text);
+
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+        let result = strategy.scan(&test_data).unwrap();
+        assert!(result.license.is_some());
+        assert!(result.residuals.is_empty());
+
+        let strategy = strategy.residuals(true);
+        let result = strategy.scan(&test_data).unwrap();
+        assert_eq!(1, result.residuals.len());
+        let residual = &result.residuals[0];
+        assert_eq!(ResidualKind::Added, residual.kind);
+        assert_eq!("no use in spacecraft is permitted", residual.text);
+    }
+
+    #[test]
+    fn tags_are_checked() {
+        let mut store = create_dummy_store();
+        let license = "permission is granted to use this software for any purpose. \
+                       the software is provided as is without warranty of any kind.";
+        store.add_license("license-5".into(), license.into());
+        store
+            .set_aliases("license-2", vec!["Numbers-1.0".into()])
+            .unwrap();
+        store
+            .add_deprecated("Old-License-5".into(), "license-5")
+            .unwrap();
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+
+        let text = format!(
+            "// SPDX-License-Identifier: LICENSE-5 OR numbers-1.0+ OR Nope\n{}",
+            license
+        );
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        assert_eq!("license-5", result.license.as_ref().unwrap().name);
+        let tag = &result.tags[0];
+        assert_eq!(
+            vec![Some("license-5"), Some("license-2"), None],
+            tag.resolved
+        );
+        assert!(tag.contradictions.is_empty());
+        assert!(tag.tag.parsed.is_ok());
+
+        let text = format!(
+            "// SPDX-License-Identifier: license-5 AND
+{}",
+            license
+        );
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        let tag = &result.tags[0];
+        assert!(tag.tag.parsed.is_err(), "dangling operator is an error");
+        assert_eq!(vec![Some("license-5")], tag.resolved);
+
+        let text = format!("# SPDX-License-Identifier: license-1\n{}", license);
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        assert_eq!(vec!["license-5"], result.tags[0].contradictions);
+
+        let text = format!("# SPDX-License-Identifier: old-license-5\n{}", license);
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        let tag = &result.tags[0];
+        assert_eq!(vec![Some("license-5")], tag.resolved);
+        assert_eq!(vec![Some("license-5")], tag.successors);
+        assert!(tag.contradictions.is_empty());
+    }
+
+    #[test]
+    fn expression_from_contained() {
+        let store = create_dummy_store();
+        let strategy = ScanStrategy::new(&store)
+            .confidence_threshold(0.5)
+            .optimize(true)
+            .shallow_limit(1.0);
+        let licenses = "1234 5678 1234\n0000\n1010101010\n\n8888 9999\n\
+                        whatsit hello\narst neio qwfp colemak is the best keyboard layout\n\
+                        aaaaa\nbbbbb\nccccc";
+
+        let text = format!("lorem\nipsum abc def ghi jkl\n{}", licenses);
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        let expression = result.expression.unwrap().normalize();
+        assert_eq!("license-1 AND license-2", expression.to_string());
+
+        let text = format!("lorem\nipsum, at your option\n{}", licenses);
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        let expression = result.expression.unwrap().normalize();
+        assert_eq!("license-1 OR license-2", expression.to_string());
+    }
+
+    #[test]
+    fn exceptions_join_the_expression() {
+        let mut store = Store::new();
+        let license = "permission is granted to use this software for any purpose. \
+                       the software is provided as is without warranty of any kind.";
+        let exception = "as a special exception, linking this library with other \
+                         modules does not make the combined work subject to the license.";
+        store.add_license("license-5".into(), license.into());
+        store.add_exception("Linking-exception".into(), exception.into());
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+
+        let result = strategy.scan(&TextData::new(license)).unwrap();
+        assert!(result.exceptions.is_empty());
+        assert_eq!("license-5", result.expression.unwrap().to_string());
+
+        let text = format!("{}\n\n{}", license, exception);
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        assert_eq!("license-5", result.license.as_ref().unwrap().name);
+        let found = &result.exceptions[0];
+        assert_eq!("Linking-exception", found.name);
+        assert_eq!(1.0, found.score);
+        assert_eq!(exception, &text[found.span.unwrap().bytes()]);
+        assert_eq!(
+            "license-5 WITH Linking-exception",
+            result.expression.unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn gnu_versions_come_from_notices() {
+        let mut store = Store::new();
+        let license = "you may copy and distribute verbatim copies of the program. \
+                       to apply these terms, attach a notice published by the free \
+                       software foundation; either version 2 of the license, or \
+                       (at your option) any later version.";
+        store.add_license("GPL-2.0-only".into(), license.into());
+        store
+            .set_aliases("GPL-2.0-only", vec!["GPL-2.0-or-later".into()])
+            .unwrap();
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+
+        let result = strategy.scan(&TextData::new(license)).unwrap();
+        let version = result.gnu_version.unwrap();
+        assert_eq!(("GPL-2.0-only", false), (version.id, version.from_notice));
+        assert_eq!(NO_NOTICE_PENALTY, result.score);
+
+        let text = format!(
+            "this program is free software, as published by the free software \
+             foundation, version 2 or any later version.\n\n{}",
+            license
+        );
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        assert_eq!("GPL-2.0-only", result.license.as_ref().unwrap().name);
+        assert!(result.gnu_version.unwrap().from_notice);
+        assert_eq!("GPL-2.0-or-later", result.expression.unwrap().to_string());
+
+        // tags need to agree with the notice's version terms
+        let contradictions = |tag: &str, notice: &str| {
+            let text = format!(
+                "// SPDX-License-Identifier: {}\n{}\n\n{}",
+                tag, notice, license
+            );
+            let result = strategy.scan(&TextData::new(&text)).unwrap();
+            result.tags[0].contradictions.clone()
+        };
+        let later = "this program is free software, as published by the free software \
+                     foundation, version 2 or any later version.";
+        let only = "this program is free software, as published by the free software \
+                    foundation, version 2 of the license.";
+        assert!(contradictions("GPL-2.0-or-later", later).is_empty());
+        assert!(contradictions("GPL-2.0-only+", later).is_empty());
+        assert_eq!(
+            vec!["GPL-2.0-or-later"],
+            contradictions("GPL-2.0-only", later)
+        );
+        assert!(contradictions("GPL-2.0-only", only).is_empty());
+        assert_eq!(
+            vec!["GPL-2.0-only"],
+            contradictions("GPL-2.0-or-later", only)
+        );
+        // without a notice, the tag is all there is to go by
+        assert!(contradictions("GPL-2.0-or-later", "").is_empty());
+
+        // the -or-later form keeps a name of its own
+        let metadata = |full_name: &str| LicenseMetadata {
+            full_name: full_name.into(),
+            ..LicenseMetadata::default()
+        };
+        store
+            .set_metadata("GPL-2.0-only", metadata("GPL v2 only"))
+            .unwrap();
+        store
+            .set_metadata("gpl-2.0-or-later", metadata("GPL v2 or later"))
+            .unwrap();
+        assert_eq!(
+            "GPL v2 only",
+            store.metadata("GPL-2.0-only").unwrap().full_name
+        );
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        let license = result.license.unwrap();
+        assert_eq!("GPL v2 or later", license.metadata.unwrap().full_name);
+    }
+
+    #[test]
+    fn templates_give_exact_matches() {
+        let mut store = Store::new();
+        let license = "Copyright (c) 2020 Jane Doe\n\n\
+                       permission is granted to use this software for any purpose. \
+                       the software is provided as is without warranty of any kind.";
+        store.add_license("license-5".into(), license.into());
+        let template = "<<var;name=\"copyright\";original=\"Copyright (c) <year>\";\
+                        match=\".{0,100}\">>\n\n\
+                        permission is granted to use this software for any purpose. \
+                        the software is provided as is without warranty of any kind.";
+        store
+            .set_template("license-5", template.parse().unwrap())
+            .unwrap();
+
+        let strategy = ScanStrategy::new(&store).confidence_threshold(0.5);
+        let result = strategy.scan(&TextData::new(license)).unwrap();
+        assert_eq!(None, result.template_match);
+
+        let strategy = strategy.match_templates(true);
+        let text = license.replace("2020 Jane Doe", "1999-2001 ACME, Inc.");
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        assert_eq!(Some(true), result.template_match);
+
+        let text = license.replace("any purpose", "any good purpose");
+        let result = strategy.scan(&TextData::new(&text)).unwrap();
+        assert_eq!("license-5", result.license.as_ref().unwrap().name);
+        assert_eq!(Some(false), result.template_match);
+
+        // a header isn't the text the template is of
+        let header = "this software is licensed under the license-5 terms";
+        store
+            .add_variant("license-5", LicenseType::Header, header.into())
+            .unwrap();
+        let strategy = ScanStrategy::new(&store)
+            .confidence_threshold(0.5)
+            .match_templates(true);
+        let result = strategy.scan(&TextData::new(header)).unwrap();
+        assert_eq!(LicenseType::Header, result.license.as_ref().unwrap().kind);
+        assert_eq!(None, result.template_match);
+    }
+
+    fn create_dummy_store() -> Store {
+        create_dummy_store_sized(NgramSize::default())
+    }
+
+    fn create_dummy_store_sized(ngram_size: NgramSize) -> Store {
+        let mut store = Store::with_ngram_size(ngram_size);
         store.add_license("license-1".into(), "aaaaa\nbbbbb\nccccc".into());
         store.add_license(
             "license-2".into(),
@@ -0,0 +1,168 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use failure::Error;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::Expression,
+    span::{Position, Span},
+};
+
+lazy_static! {
+    static ref RX_TAG: Regex = Regex::new(r"(?i)SPDX-License-Identifier\s*:\s*").unwrap();
+}
+
+/// Trailing comment markers to take off the end of a tag's expression.
+const CLOSERS: &[&str] = &["*/", "-->", "-}", "]]", "\"\"\"", "'''"];
+
+/// An `SPDX-License-Identifier` tag found in a text.
+///
+/// ```
+/// use askalono::TextData;
+///
+/// let text = "/* SPDX-License-Identifier: Apache-2.0 WITH LLVM-exception OR MIT */";
+/// let tags = TextData::from(text).spdx_tags();
+/// assert_eq!("Apache-2.0 WITH LLVM-exception OR MIT", tags[0].expression);
+/// assert_eq!(vec!["Apache-2.0", "MIT"], tags[0].licenses);
+/// assert_eq!(vec!["LLVM-exception"], tags[0].exceptions);
+/// assert_eq!(vec!["Apache-2.0", "MIT"], tags[0].parsed.as_ref().unwrap().licenses());
+///
+/// // `WITH` can only follow a single license, but the ids are still listed
+/// let text = "/* SPDX-License-Identifier: (Apache-2.0 OR MIT) WITH LLVM-exception */";
+/// let tags = TextData::from(text).spdx_tags();
+/// assert!(tags[0].parsed.is_err());
+/// assert_eq!(vec!["Apache-2.0", "MIT"], tags[0].licenses);
+/// assert_eq!(vec!["LLVM-exception"], tags[0].exceptions);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpdxTag {
+    /// The license expression, as written after the tag.
+    pub expression: String,
+    /// The expression, parsed, or why it isn't a valid SPDX license
+    /// expression.
+    pub parsed: Result<Expression, String>,
+    /// Where the expression is in the original text.
+    pub span: Span,
+    /// The license ids in the expression, in order of appearance. These are
+    /// picked out even if the expression couldn't be parsed.
+    pub licenses: Vec<String>,
+    /// The exception ids in the expression (those following `WITH`).
+    pub exceptions: Vec<String>,
+}
+
+/// Find the `SPDX-License-Identifier` tags in a text, one per line at most.
+pub(crate) fn find_tags(text: &str) -> Vec<SpdxTag> {
+    let mut tags = Vec::new();
+    let (mut byte, mut char) = (0, 0);
+    for (line, content) in text.split('\n').enumerate() {
+        if let Some(found) = RX_TAG.find(content) {
+            let rest = &content[found.end()..];
+            let expression = trim_closers(rest);
+            if !expression.is_empty() {
+                let position = |offset: usize| {
+                    let column = content[..offset].chars().count();
+                    Position {
+                        line,
+                        column,
+                        byte: byte + offset,
+                        char: char + column,
+                    }
+                };
+                let (licenses, exceptions) = split_ids(expression);
+                tags.push(SpdxTag {
+                    expression: expression.to_owned(),
+                    parsed: expression.parse().map_err(|e: Error| e.to_string()),
+                    span: Span {
+                        start: position(found.end()),
+                        end: position(found.end() + expression.len()),
+                    },
+                    licenses,
+                    exceptions,
+                });
+            }
+        }
+
+        byte += content.len() + 1;
+        char += content.chars().count() + 1;
+    }
+    tags
+}
+
+/// Take any closing comment markers off the end of a tag line.
+fn trim_closers(mut text: &str) -> &str {
+    loop {
+        text = text.trim_end();
+        match CLOSERS.iter().find(|closer| text.ends_with(*closer)) {
+            Some(closer) => text = &text[..text.len() - closer.len()],
+            None => return text,
+        }
+    }
+}
+
+/// Pick the license and exception ids out of an expression, leaving out
+/// operators and parentheses. Duplicates are only listed once.
+fn split_ids(expression: &str) -> (Vec<String>, Vec<String>) {
+    let (mut licenses, mut exceptions) = (Vec::new(), Vec::<String>::new());
+    let mut after_with = false;
+    for token in expression
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+    {
+        if token.eq_ignore_ascii_case("AND") || token.eq_ignore_ascii_case("OR") {
+            continue;
+        }
+        if token.eq_ignore_ascii_case("WITH") {
+            after_with = true;
+            continue;
+        }
+        let ids = match after_with {
+            true => &mut exceptions,
+            false => &mut licenses,
+        };
+        if !ids.iter().any(|id| id == token) {
+            ids.push(token.to_owned());
+        }
+        after_with = false;
+    }
+    (licenses, exceptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_found_in_comments() {
+        let text = "#!/bin/sh\n  # spdx-license-identifier:  GPL-2.0+ OR  MIT  \n<!-- SPDX-License-Identifier: Apache-2.0 -->\n// SPDX-License-Identifier:\n";
+        let tags = find_tags(text);
+        assert_eq!(2, tags.len());
+
+        assert_eq!("GPL-2.0+ OR  MIT", tags[0].expression);
+        assert_eq!(vec!["GPL-2.0+", "MIT"], tags[0].licenses);
+        assert_eq!("GPL-2.0+ OR  MIT", &text[tags[0].span.bytes()]);
+        assert_eq!(
+            (1, 30),
+            (tags[0].span.start.line, tags[0].span.start.column)
+        );
+
+        assert_eq!("Apache-2.0", &text[tags[1].span.bytes()]);
+        assert!(tags[1].exceptions.is_empty());
+        assert_eq!(Ok(Expression::license("Apache-2.0")), tags[1].parsed);
+    }
+
+    #[test]
+    fn malformed_tags_are_kept() {
+        let text = "// SPDX-License-Identifier: MIT OR
+                    // SPDX-License-Identifier: (MIT OR Apache-2.0) WITH LLVM-exception
+";
+        let tags = find_tags(text);
+        assert_eq!(2, tags.len());
+        assert!(tags.iter().all(|tag| tag.parsed.is_err()));
+        assert_eq!(vec!["MIT"], tags[0].licenses);
+        assert_eq!(vec!["MIT", "Apache-2.0"], tags[1].licenses);
+        assert_eq!(vec!["LLVM-exception"], tags[1].exceptions);
+    }
+}
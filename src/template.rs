@@ -0,0 +1,556 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+use failure::{bail, format_err, Error};
+use log::warn;
+use regex::{Regex, RegexBuilder};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// How large a compiled template may get. Variable text is often allowed to
+/// be thousands of characters long, which makes for large automata.
+const TEMPLATE_SIZE_LIMIT: usize = 256 * (1 << 20);
+
+/// What a variable region matches when its template doesn't say.
+const DEFAULT_MATCH: &str = ".*";
+
+/// What a copyright notice may be, under the SPDX matching guidelines.
+const COPYRIGHT_MATCH: &str = ".{0,5000}";
+
+/// What a list item's bullet or number may be, under the SPDX matching
+/// guidelines.
+const BULLET_MATCH: &str = r"\S{1,10}";
+
+/// An SPDX license template, for telling whether a text is an exact match for
+/// a license under the [SPDX matching guidelines][guidelines].
+///
+/// A template is a license's text, with regions that may vary from copy to
+/// copy -- the copyright holder, an organization's name -- marked as
+/// variables, and regions that may be left out altogether marked as optional.
+/// Templates are parsed from SPDX's markup (the `standardLicenseTemplate` of
+/// the JSON data) with `str::parse`, or from the `<text>` of SPDX's XML
+/// license files with `from_xml`.
+///
+/// When comparing, case, whitespace, and the style of quotes and dashes are
+/// ignored, as the guidelines allow.
+///
+/// ```
+/// use askalono::Template;
+///
+/// let template: Template = "<<beginOptional>>The Example License<<endOptional>>\n\
+///     Copyright <<var;name=\"holder\";original=\"Jane Doe\";match=\".{0,100}\">>\n\
+///     You may do as you like."
+///     .parse()
+///     .unwrap();
+/// assert!(template.matches("Copyright  ACME Corp.\nYou may do  as you like."));
+/// assert!(!template.matches("Copyright ACME Corp.\nYou may do as you please."));
+/// ```
+///
+/// [guidelines]: https://spdx.github.io/spdx-spec/v2.3/license-matching-guidelines-and-templates/
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+    /// The template as a regular expression over normalized text, compiled
+    /// on first use. `None` if it doesn't compile.
+    regex: OnceLock<Option<Regex>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    /// Text that must be present.
+    Text(String),
+    /// Text that may vary, within what `pattern` matches.
+    Var {
+        name: String,
+        original: String,
+        pattern: String,
+    },
+    /// Parts that may be left out.
+    Optional(Vec<Part>),
+}
+
+impl Template {
+    /// Parse the `<text>` of a license from SPDX's `license-list-XML`
+    /// repository. The whole license file may be given; only its `<text>` is
+    /// read.
+    ///
+    /// `<alt>` tags become variable regions and `<optional>` tags optional
+    /// ones. As the matching guidelines allow, a license's title is optional,
+    /// its copyright notice may be any notice or none, and list items may
+    /// have any bullet.
+    pub fn from_xml(xml: &str) -> Result<Template, Error> {
+        let body = match (xml.find("<text>"), xml.rfind("</text>")) {
+            (Some(start), Some(end)) if start < end => &xml[start + "<text>".len()..end],
+            _ if !xml.contains("<text") => xml,
+            _ => bail!("no license text found"),
+        };
+        let mut parser = XmlParser { rest: body };
+        let parts = parser.parse_until(None)?;
+        Ok(Template::new(parts))
+    }
+
+    fn new(parts: Vec<Part>) -> Template {
+        Template {
+            parts,
+            regex: OnceLock::new(),
+        }
+    }
+
+    /// Check whether a text is an exact match for this template, under the
+    /// SPDX matching guidelines.
+    ///
+    /// The whole text must match, save for whitespace at either end. A
+    /// template that can't be compiled -- one with a variable pattern that
+    /// isn't a valid regular expression, say -- matches nothing.
+    pub fn matches(&self, text: &str) -> bool {
+        match self.regex() {
+            Some(regex) => regex.is_match(&normalize(text)),
+            None => false,
+        }
+    }
+
+    /// Get the names of the template's variable regions, in order.
+    pub fn variables(&self) -> Vec<&str> {
+        fn visit<'a>(parts: &'a [Part], names: &mut Vec<&'a str>) {
+            for part in parts {
+                match part {
+                    Part::Var { name, .. } => names.push(name),
+                    Part::Optional(inner) => visit(inner, names),
+                    Part::Text(_) => {}
+                }
+            }
+        }
+        let mut names = Vec::new();
+        visit(&self.parts, &mut names);
+        names
+    }
+
+    fn regex(&self) -> Option<&Regex> {
+        self.regex
+            .get_or_init(|| {
+                let mut pattern = String::from(r"(?is)^");
+                push_pattern(&self.parts, &mut pattern);
+                pattern.push_str(r"\s*$");
+                RegexBuilder::new(&pattern)
+                    .size_limit(TEMPLATE_SIZE_LIMIT)
+                    .build()
+                    .map_err(|e| warn!("Template doesn't compile: {}", e))
+                    .ok()
+            })
+            .as_ref()
+    }
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Template) -> bool {
+        self.parts == other.parts
+    }
+}
+
+/// Build a regular expression for a template's parts, over normalized text.
+fn push_pattern(parts: &[Part], pattern: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => {
+                for word in normalize(text).split(' ').filter(|w| !w.is_empty()) {
+                    pattern.push_str(r"\s*");
+                    pattern.push_str(&regex::escape(word));
+                }
+            }
+            Part::Var { pattern: var, .. } => {
+                pattern.push_str(r"\s*(?:");
+                pattern.push_str(var);
+                pattern.push(')');
+            }
+            Part::Optional(inner) => {
+                pattern.push_str("(?:");
+                push_pattern(inner, pattern);
+                pattern.push_str(")?");
+            }
+        }
+    }
+}
+
+/// Normalize a text the way the matching guidelines allow: all whitespace is
+/// alike, as are all quotes, all dashes, and `http` and `https`.
+fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.chars() {
+        let c = match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '`' | '\u{B4}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{AB}' | '\u{BB}' => '"',
+            '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2014}' | '\u{2015}'
+            | '\u{2212}' => '-',
+            '\u{A9}' => {
+                normalized.push_str("(c)");
+                continue;
+            }
+            c if c.is_whitespace() => ' ',
+            c => c,
+        };
+        normalized.push(c);
+    }
+    normalized.replace("https://", "http://")
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    /// Parse SPDX template markup: `<<var;name="...";original="...";
+    /// match="...">>` for variable regions, and `<<beginOptional>>` and
+    /// `<<endOptional>>` around optional ones.
+    fn from_str(markup: &str) -> Result<Template, Error> {
+        let mut stack: Vec<Vec<Part>> = vec![Vec::new()];
+        let mut rest = markup;
+        while let Some(start) = rest.find("<<") {
+            if start > 0 {
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .push(Part::Text(rest[..start].to_owned()));
+            }
+            let (rule, after) = split_rule(&rest[start + 2..])?;
+            let fields = parse_fields(rule)?;
+            match fields[0].0.as_str() {
+                "var" => stack.last_mut().unwrap().push(Part::Var {
+                    name: field(&fields, "name").unwrap_or_default(),
+                    original: field(&fields, "original").unwrap_or_default(),
+                    pattern: field(&fields, "match").unwrap_or_else(|| DEFAULT_MATCH.to_owned()),
+                }),
+                "beginOptional" => stack.push(Vec::new()),
+                "endOptional" => {
+                    if stack.len() < 2 {
+                        bail!("endOptional without beginOptional");
+                    }
+                    let inner = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Part::Optional(inner));
+                }
+                other => bail!("unknown template rule {}", other),
+            }
+            rest = after;
+        }
+        if !rest.is_empty() {
+            stack.last_mut().unwrap().push(Part::Text(rest.to_owned()));
+        }
+        if stack.len() > 1 {
+            bail!("beginOptional without endOptional");
+        }
+        Ok(Template::new(stack.pop().unwrap()))
+    }
+}
+
+/// Split a template rule from the text after it, minding quoted values that
+/// may contain `>>`.
+fn split_rule(text: &str) -> Result<(&str, &str), Error> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '>' if !quoted && text[i..].starts_with(">>") => {
+                return Ok((&text[..i], &text[i + 2..]));
+            }
+            _ => {}
+        }
+    }
+    Err(format_err!("unterminated template rule"))
+}
+
+/// Split a rule into its `;`-separated fields: the rule's kind, then
+/// `key="value"` pairs.
+fn parse_fields(rule: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut fields = Vec::new();
+    let mut rest = rule.trim();
+    while !rest.is_empty() {
+        let end = rest.find([';', '=']).unwrap_or(rest.len());
+        let key = rest[..end].trim().to_owned();
+        rest = &rest[end..];
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let after = after
+                .strip_prefix('"')
+                .ok_or_else(|| format_err!("unquoted value for {}", key))?;
+            let mut chars = after.char_indices();
+            let mut close = None;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        // keep escapes; they may be part of a pattern
+                        value.push(c);
+                        if let Some((_, next)) = chars.next() {
+                            value.push(next);
+                        }
+                    }
+                    '"' => {
+                        close = Some(i);
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            let close = close.ok_or_else(|| format_err!("unterminated value for {}", key))?;
+            rest = &after[close + 1..];
+        }
+        fields.push((key, value));
+        rest = rest.trim_start();
+        rest = rest.strip_prefix(';').unwrap_or(rest).trim_start();
+    }
+    if fields.is_empty() {
+        bail!("empty template rule");
+    }
+    Ok(fields)
+}
+
+/// Get a field's value. Only patterns keep their escapes; other values are
+/// plain text.
+fn field(fields: &[(String, String)], key: &str) -> Option<String> {
+    let (_, value) = fields.iter().find(|(k, _)| k == key)?;
+    Some(match key {
+        "match" => value.clone(),
+        _ => value.replace("\\\"", "\""),
+    })
+}
+
+impl fmt::Display for Template {
+    /// Write the template as SPDX template markup.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_parts(f: &mut fmt::Formatter<'_>, parts: &[Part]) -> fmt::Result {
+            for part in parts {
+                match part {
+                    Part::Text(text) => write!(f, "{}", text)?,
+                    Part::Var {
+                        name,
+                        original,
+                        pattern,
+                    } => write!(
+                        f,
+                        "<<var;name=\"{}\";original=\"{}\";match=\"{}\">>",
+                        name,
+                        original.replace('"', "\\\""),
+                        pattern.replace('"', "\\\"").replace("\\\\\"", "\\\"")
+                    )?,
+                    Part::Optional(inner) => {
+                        write!(f, "<<beginOptional>>")?;
+                        write_parts(f, inner)?;
+                        write!(f, "<<endOptional>>")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        write_parts(f, &self.parts)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Template, D::Error> {
+        let markup = String::deserialize(deserializer)?;
+        markup.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A reader for the markup of SPDX's XML license files, which is simple
+/// enough to not need a full XML parser.
+struct XmlParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> XmlParser<'a> {
+    /// Read parts up to the closing tag given, or the end of the text.
+    fn parse_until(&mut self, closing: Option<&str>) -> Result<Vec<Part>, Error> {
+        let mut parts = Vec::new();
+        loop {
+            let start = match self.rest.find('<') {
+                Some(start) => start,
+                None => {
+                    if let Some(tag) = closing {
+                        bail!("missing </{}>", tag);
+                    }
+                    push_text(&mut parts, &decode(self.rest));
+                    self.rest = "";
+                    return Ok(parts);
+                }
+            };
+            push_text(&mut parts, &decode(&self.rest[..start]));
+            let end = self.rest[start..]
+                .find('>')
+                .ok_or_else(|| format_err!("unterminated tag"))?
+                + start;
+            let tag = &self.rest[start + 1..end];
+            self.rest = &self.rest[end + 1..];
+
+            if tag.starts_with('!') || tag.starts_with('?') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                if Some(name.trim()) == closing {
+                    return Ok(parts);
+                }
+                bail!("unexpected </{}>", name.trim());
+            }
+
+            let empty = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let name = tag.split_whitespace().next().unwrap_or("");
+            let inner = match empty {
+                true => Vec::new(),
+                false => self.parse_until(Some(name))?,
+            };
+            match name {
+                "alt" => parts.push(Part::Var {
+                    name: attribute(tag, "name").unwrap_or_default(),
+                    original: flatten(&inner),
+                    pattern: attribute(tag, "match").unwrap_or_else(|| DEFAULT_MATCH.to_owned()),
+                }),
+                "optional" | "titleText" => parts.push(Part::Optional(inner)),
+                "copyrightText" => parts.push(Part::Optional(vec![Part::Var {
+                    name: "copyright".to_owned(),
+                    original: flatten(&inner),
+                    pattern: COPYRIGHT_MATCH.to_owned(),
+                }])),
+                "bullet" => parts.push(Part::Optional(vec![Part::Var {
+                    name: "bullet".to_owned(),
+                    original: flatten(&inner),
+                    pattern: BULLET_MATCH.to_owned(),
+                }])),
+                // not part of the license text itself
+                "standardLicenseHeader" | "notes" | "crossRefs" => {}
+                _ => {
+                    push_text(&mut parts, " ");
+                    for part in inner {
+                        match part {
+                            Part::Text(text) => push_text(&mut parts, &text),
+                            part => parts.push(part),
+                        }
+                    }
+                    push_text(&mut parts, " ");
+                }
+            }
+        }
+    }
+}
+
+/// Add text to a list of parts, joining it with any text before it.
+fn push_text(parts: &mut Vec<Part>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match parts.last_mut() {
+        Some(Part::Text(last)) => last.push_str(text),
+        _ => parts.push(Part::Text(text.to_owned())),
+    }
+}
+
+/// Get the text of some parts, with variables as their original text.
+fn flatten(parts: &[Part]) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => text.clone(),
+            Part::Var { original, .. } => original.clone(),
+            Part::Optional(inner) => flatten(inner),
+        })
+        .collect()
+}
+
+/// Get the value of an XML tag's attribute.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = tag[start..].find('"')? + start;
+    Some(decode(&tag[start..end]))
+}
+
+/// Decode the entities XML escapes text with.
+fn decode(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKUP: &str = "<<beginOptional>> Example License<<endOptional>>\n\n\
+                          <<var;name=\"copyright\";original=\"Copyright (c) <year> <owner>\";\
+                          match=\".{0,500}\">>\n\n\
+                          Permission is granted to \"use\" this software, provided that \
+                          <<var;name=\"holder\";original=\"the author\";match=\"(the )?(author|owner)s?\">> \
+                          is credited.";
+
+    #[test]
+    fn markup_round_trips() {
+        let template: Template = MARKUP.parse().unwrap();
+        assert_eq!(vec!["copyright", "holder"], template.variables());
+        let again: Template = template.to_string().parse().unwrap();
+        assert_eq!(template, again);
+
+        assert!("<<beginOptional>>x".parse::<Template>().is_err());
+        assert!("x<<endOptional>>".parse::<Template>().is_err());
+        assert!("<<var;name=\"x\"".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn matching_follows_the_guidelines() {
+        let template: Template = MARKUP.parse().unwrap();
+        assert!(template.matches(
+            "Copyright 2019 ACME\n\nPermission is granted to \u{201C}use\u{201D} this \
+             software,\nprovided that the owners is credited."
+        ));
+        assert!(template.matches(
+            "  EXAMPLE LICENSE\n\nPermission is granted to \"use\" this software, \
+             provided that author is credited.\n"
+        ));
+        // variable text must fit its pattern
+        assert!(!template.matches(
+            "Permission is granted to \"use\" this software, provided that ACME is credited."
+        ));
+        // and the rest must be there, with nothing more
+        assert!(!template.matches(
+            "Permission is granted to \"use\" this software, provided that the author is \
+             credited. Also, this other thing."
+        ));
+    }
+
+    #[test]
+    fn xml_is_read() {
+        let xml = r#"<?xml version="1.0"?>
+            <SPDXLicenseCollection><license licenseId="Example">
+            <crossRefs><crossRef>https://example.com</crossRef></crossRefs>
+            <text>
+              <titleText><p>Example License</p></titleText>
+              <copyrightText><p>Copyright (c) &lt;year&gt; &lt;owner&gt;</p></copyrightText>
+              <list>
+                <item><bullet>1.</bullet> Use <alt name="what" match="it|this software">it</alt>
+                  freely.</item>
+                <item><bullet>2.</bullet> <optional>Please</optional> be nice.</item>
+              </list>
+            </text></license></SPDXLicenseCollection>"#;
+        let template = Template::from_xml(xml).unwrap();
+        assert_eq!(
+            vec!["copyright", "bullet", "what", "bullet"],
+            template.variables()
+        );
+        assert!(template.matches("Use it freely. Please be nice."));
+        assert!(template.matches(
+            "Example License\nCopyright 2020 Me\n a) Use this software freely.\n b) Be nice."
+        ));
+        assert!(!template.matches("1. Use the thing freely. 2. Be nice."));
+
+        let again: Template = template.to_string().parse().unwrap();
+        assert_eq!(template, again);
+        assert!(Template::from_xml("<text><p>unclosed</text>").is_err());
+    }
+}